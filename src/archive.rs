@@ -0,0 +1,38 @@
+use worker::Bucket;
+
+/// Name of the optional R2 bucket binding for archiving uploaded CSVs
+pub const ARCHIVE_BINDING: &str = "ARCHIVE";
+
+/// Object storage for raw uploads, implemented by R2 and by an in-memory archive in tests
+pub trait Archive {
+    /// Store the bytes under the key, overwriting any existing object
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()>;
+}
+
+impl Archive for Bucket {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()> {
+        Bucket::put(self, key, bytes)
+            .execute()
+            .await
+            .map(|_| ())
+            .map_err(|err| eyre::eyre!("failed to put {key} to R2: {err}"))
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    use super::Archive;
+
+    /// In-memory archive for tests
+    #[derive(Default)]
+    pub struct MemoryArchive(pub RefCell<BTreeMap<String, Vec<u8>>>);
+
+    impl Archive for MemoryArchive {
+        async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()> {
+            self.0.borrow_mut().insert(key.to_string(), bytes);
+            Ok(())
+        }
+    }
+}
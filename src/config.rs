@@ -1,6 +1,15 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use chrono::{NaiveDate, Weekday};
+use regex::Regex;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
 use uuid::Uuid;
 use worker::Env;
 
+use crate::ynab::types::{
+    ScheduledTransactionFrequency, TransactionClearedStatus, TransactionFlagColor,
+};
+
 /// Telegram Bot API KEY
 pub const ENV_API_KEY: &str = "API_KEY";
 /// YNAB API KEY
@@ -13,22 +22,1408 @@ pub const ENV_YNAB_BUDGET_ID: &str = "YNAB_BUDGET_ID";
 pub const ENV_YNAB_ACCOUNT_ID: &str = "YNAB_ACCOUNT_ID";
 /// Webhook API Key for authentication
 pub const ENV_WEBHOOK_API_KEY: &str = "WEBHOOK_API_KEY";
+/// Default memo for all imported transactions
+///
+/// `{date}` is replaced with the import date
+pub const ENV_DEFAULT_MEMO: &str = "DEFAULT_MEMO";
+/// Cleared status of imported transactions: `cleared` (default), `uncleared` or `reconciled`
+pub const ENV_CLEARED_STATUS: &str = "CLEARED_STATUS";
+/// Offset in minutes from UTC used to derive transaction dates
+pub const ENV_TZ_OFFSET_MINUTES: &str = "TZ_OFFSET_MINUTES";
+/// Multiplier converting CSV amounts to YNAB milliunits, e.g. `10` for amounts in pence
+pub const ENV_AMOUNT_SCALE: &str = "AMOUNT_SCALE";
+/// Warn about GBP amounts with more decimal places than this, e.g. `2` for pence
+pub const ENV_AMOUNT_MAX_DECIMALS: &str = "AMOUNT_MAX_DECIMALS";
+/// Maximum number of concurrent YNAB requests during an import
+pub const ENV_YNAB_MAX_CONCURRENT_REQUESTS: &str = "YNAB_MAX_CONCURRENT_REQUESTS";
+/// How transactions are split into YNAB import requests, see [`ImportBatching`]
+pub const ENV_IMPORT_BATCHING: &str = "IMPORT_BATCHING";
+/// Maximum number of transactions per YNAB import request, 200 by default
+pub const ENV_IMPORT_BATCH_SIZE: &str = "IMPORT_BATCH_SIZE";
+/// Format version of the YNAB import_id, see [`ImportIdVersion`]
+pub const ENV_IMPORT_ID_VERSION: &str = "IMPORT_ID_VERSION";
+/// Comma-separated Yonder fields making up the YNAB import_id, see [`ImportIdField`]
+pub const ENV_IMPORT_ID_FIELDS: &str = "IMPORT_ID_FIELDS";
+/// Transactions dated before this date keep the version 1 import_id format
+///
+/// Allows migrating to a new import_id version without re-importing already imported transactions
+pub const ENV_IMPORT_ID_TRANSITION_UNTIL: &str = "IMPORT_ID_TRANSITION_UNTIL";
+/// Whether to import transactions dated in the future, e.g. pending authorizations
+///
+/// Future transactions are skipped by default
+pub const ENV_IMPORT_FUTURE_TRANSACTIONS: &str = "IMPORT_FUTURE_TRANSACTIONS";
+/// Comma-separated currencies to import, transactions in other currencies are skipped
+pub const ENV_ALLOWED_CURRENCIES: &str = "ALLOWED_CURRENCIES";
+/// Minimum absolute GBP amount to import, smaller transactions are skipped
+pub const ENV_MIN_AMOUNT_GBP: &str = "MIN_AMOUNT_GBP";
+/// Absolute GBP amount above which transactions are flagged as large in the import result
+pub const ENV_LARGE_TRANSACTION_GBP: &str = "LARGE_TRANSACTION_GBP";
+/// Whether to keep leading and trailing whitespace in CSV text fields
+///
+/// Whitespace is trimmed by default
+pub const ENV_PRESERVE_WHITESPACE: &str = "PRESERVE_WHITESPACE";
+/// Whether to fail the import if YNAB reports duplicate import IDs
+pub const ENV_STRICT_NO_DUPLICATES: &str = "STRICT_NO_DUPLICATES";
+/// Reject CSVs with the newest transaction older than this number of days
+pub const ENV_IMPORT_WINDOW_DAYS: &str = "IMPORT_WINDOW_DAYS";
+/// Comma-separated generic descriptions, e.g. `Contactless Payment`
+///
+/// Transactions with these descriptions use the Yonder category as the payee
+pub const ENV_GENERIC_DESCRIPTIONS: &str = "GENERIC_DESCRIPTIONS";
+/// Comma-separated prefixes stripped from the start of descriptions used as payees, e.g. `VISA,POS`
+pub const ENV_DESCRIPTION_PREFIXES: &str = "DESCRIPTION_PREFIXES";
+/// Casing of descriptions used as payees, see [`DescriptionCase`]
+pub const ENV_DESCRIPTION_CASE: &str = "DESCRIPTION_CASE";
+/// Comma-separated words like `TFL,ATM` kept as is when converting the description casing
+pub const ENV_DESCRIPTION_CASE_EXCEPTIONS: &str = "DESCRIPTION_CASE_EXCEPTIONS";
+/// Whether to append the country to the payee of transactions made outside the UK
+pub const ENV_PAYEE_COUNTRY: &str = "PAYEE_COUNTRY";
+/// Payee for all credit transactions, e.g. `Refund`
+pub const ENV_CREDIT_PAYEE: &str = "CREDIT_PAYEE";
+/// Payee for all transactions, e.g. `Yonder Card`, moving the description into the memo
+pub const ENV_SINGLE_PAYEE: &str = "SINGLE_PAYEE";
+/// Comma-separated texts in descriptions of credits that are card repayments rather than cashback
+/// or refunds, e.g. `Payment received`
+pub const ENV_PAYMENT_DESCRIPTIONS: &str = "PAYMENT_DESCRIPTIONS";
+/// YNAB transfer payee ID of the account card repayments come from, making them transfers
+pub const ENV_PAYMENT_TRANSFER_PAYEE_ID: &str = "PAYMENT_TRANSFER_PAYEE_ID";
+/// Comma-separated descriptions of Yonder balance correction rows
+pub const ENV_ADJUSTMENT_DESCRIPTIONS: &str = "ADJUSTMENT_DESCRIPTIONS";
+/// Payee for balance corrections
+pub const ENV_ADJUSTMENT_PAYEE: &str = "ADJUSTMENT_PAYEE";
+/// Flag color for balance corrections
+pub const ENV_ADJUSTMENT_FLAG_COLOR: &str = "ADJUSTMENT_FLAG_COLOR";
+/// JSON object mapping Yonder categories to YNAB category IDs, e.g. `{"Transport": "<uuid>"}`
+pub const ENV_CATEGORY_MAP: &str = "CATEGORY_MAP";
+/// JSON object mapping Yonder categories to YNAB category group names, e.g. `{"Bills": "Bills"}`,
+/// importing to the first visible category of the group
+pub const ENV_CATEGORY_GROUP_MAP: &str = "CATEGORY_GROUP_MAP";
+/// JSON list of [`CategoryRule`]s assigning YNAB categories by description, taking precedence over [`ENV_CATEGORY_MAP`]
+pub const ENV_CATEGORY_DESCRIPTION_RULES: &str = "CATEGORY_DESCRIPTION_RULES";
+/// YNAB category ID for transactions not matched by the category map
+pub const ENV_DEFAULT_CATEGORY_ID: &str = "DEFAULT_CATEGORY_ID";
+/// YNAB category ID for transactions made outside the UK and not matched by category rules
+pub const ENV_TRAVEL_CATEGORY_ID: &str = "TRAVEL_CATEGORY_ID";
+/// YNAB account ID for all foreign currency transactions, taking precedence over account routes
+pub const ENV_TRAVEL_ACCOUNT_ID: &str = "TRAVEL_ACCOUNT_ID";
+/// JSON list of [`AccountRoute`]s routing transactions to other YNAB accounts
+pub const ENV_ACCOUNT_ROUTES: &str = "ACCOUNT_ROUTES";
+/// JSON list of [`ScheduledRule`]s creating recurring transactions as scheduled ones
+pub const ENV_SCHEDULED_RULES: &str = "SCHEDULED_RULES";
+/// JSON list of [`CategorySplit`]s splitting part of matching transactions into another category
+pub const ENV_CATEGORY_SPLITS: &str = "CATEGORY_SPLITS";
+/// Whether to add the name of the matched account route to the memo
+pub const ENV_MEMO_ROUTE_HINT: &str = "MEMO_ROUTE_HINT";
+/// Whether to append the Yonder category as a hashtag to the memo, e.g. `#eating_out`
+pub const ENV_MEMO_CATEGORY_HASHTAG: &str = "MEMO_CATEGORY_HASHTAG";
+/// Comma-separated Yonder fields added to the memo, see [`MemoField`]
+pub const ENV_MEMO_FIELDS: &str = "MEMO_FIELDS";
+/// Separator between the memo fields
+pub const ENV_MEMO_SEPARATOR: &str = "MEMO_SEPARATOR";
+/// Whether to append the ID shared by all transactions of the upload to the memo, e.g. `upload:1a2b3c4d`
+pub const ENV_MEMO_UPLOAD_ID: &str = "MEMO_UPLOAD_ID";
+/// Whether to append the position of the transaction in the upload to the memo, e.g. `row 12/340`
+pub const ENV_MEMO_ROW_COUNTER: &str = "MEMO_ROW_COUNTER";
+/// Comma-separated origins allowed to call the webhook from browsers, `*` allows any origin
+pub const ENV_CORS_ALLOWED_ORIGINS: &str = "CORS_ALLOWED_ORIGINS";
+/// JSON object mapping Yonder categories to YNAB flag colors, e.g. `{"Dining": "red"}`
+pub const ENV_CATEGORY_FLAG_COLORS: &str = "CATEGORY_FLAG_COLORS";
+/// Whether to flag transactions of categories without a configured flag color with a color derived
+/// from the category name
+pub const ENV_CATEGORY_FLAG_HASH: &str = "CATEGORY_FLAG_HASH";
+/// JSON object mapping weekdays to YNAB flag colors for transactions without another flag,
+/// e.g. `{"saturday": "purple", "sunday": "purple"}`
+pub const ENV_WEEKDAY_FLAG_COLORS: &str = "WEEKDAY_FLAG_COLORS";
+/// Flag color for foreign currency transactions
+pub const ENV_FOREIGN_FLAG_COLOR: &str = "FOREIGN_FLAG_COLOR";
+/// YNAB category ID for the FX fee split out of foreign currency transactions
+pub const ENV_FX_FEE_CATEGORY_ID: &str = "FX_FEE_CATEGORY_ID";
+/// JSON object of reference rates in GBP per unit of the currency used to derive FX fees, e.g. `{"EUR": 0.85}`
+pub const ENV_FX_REFERENCE_RATES: &str = "FX_REFERENCE_RATES";
+/// Which flag wins when both category and foreign currency flags apply, see [`FlagPrecedence`]
+pub const ENV_FLAG_PRECEDENCE: &str = "FLAG_PRECEDENCE";
+/// How to handle rows with a negative amount, see [`SignMismatchPolicy`]
+pub const ENV_SIGN_MISMATCH_POLICY: &str = "SIGN_MISMATCH_POLICY";
+/// Whether to check the mapping rules against the sample CSV on startup, logging broken ones
+pub const ENV_VALIDATE_RULES: &str = "VALIDATE_RULES";
+/// ISO code of the currency the YNAB budget is expected to use, e.g. `GBP`
+pub const ENV_BUDGET_CURRENCY: &str = "BUDGET_CURRENCY";
+/// How to handle a budget currency other than [`ENV_BUDGET_CURRENCY`], see
+/// [`CurrencyMismatchPolicy`]
+pub const ENV_BUDGET_CURRENCY_POLICY: &str = "BUDGET_CURRENCY_POLICY";
+/// Number of retries of a failed Telegram document download, with exponential backoff
+pub const ENV_DOWNLOAD_RETRIES: &str = "DOWNLOAD_RETRIES";
+/// Characters of the transactions preview above which it's sent as a file with all transactions
+/// instead of a message
+pub const ENV_PREVIEW_FILE_THRESHOLD: &str = "PREVIEW_FILE_THRESHOLD";
+/// Whether to approve transactions only if their payee was imported before, tracking payees in KV
+pub const ENV_APPROVE_SEEN_PAYEES: &str = "APPROVE_SEEN_PAYEES";
+/// Whether to warn about transactions edited in YNAB between imports, tracking YNAB
+/// `server_knowledge` in KV
+pub const ENV_TRACK_SERVER_KNOWLEDGE: &str = "TRACK_SERVER_KNOWLEDGE";
+/// Whether to send an onboarding message to chats messaging the bot for the first time, tracking
+/// them in KV
+pub const ENV_ONBOARDING: &str = "ONBOARDING";
+/// Whether to store the IDs of transactions imported from Telegram in KV for `/categorize`
+pub const ENV_STORE_IMPORTED_IDS: &str = "STORE_IMPORTED_IDS";
+/// How to handle summary rows like totals appended to the CSV, see [`FooterRowPolicy`]
+pub const ENV_FOOTER_ROW_POLICY: &str = "FOOTER_ROW_POLICY";
+/// Whether to import a £0 marker transaction at the end of the statement period of the CSV
+pub const ENV_STATEMENT_PERIOD_MARKER: &str = "STATEMENT_PERIOD_MARKER";
+/// Whether to import the valid rows of a CSV with rows that fail to parse, reporting them as warnings
+pub const ENV_PARTIAL_IMPORT: &str = "PARTIAL_IMPORT";
+/// Comma-separated hosts the webhook is allowed to fetch CSV from with `source_url`
+pub const ENV_SOURCE_URL_ALLOWED_HOSTS: &str = "SOURCE_URL_ALLOWED_HOSTS";
+/// URL receiving a POST with the summary of each successful import
+pub const ENV_SUMMARY_WEBHOOK_URL: &str = "SUMMARY_WEBHOOK_URL";
+/// Maximum webhook requests per minute per client, `0` disables the limit
+///
+/// Requires the `KV` namespace binding
+pub const ENV_WEBHOOK_RATE_LIMIT: &str = "WEBHOOK_RATE_LIMIT";
+/// HTTP status of webhook responses when the CSV has no transactions to import
+pub const ENV_WEBHOOK_EMPTY_STATUS: &str = "WEBHOOK_EMPTY_STATUS";
+/// Comma-separated Telegram chat IDs allowed to use privileged commands
+pub const ENV_TELEGRAM_ALLOWED_CHAT_IDS: &str = "TELEGRAM_ALLOWED_CHAT_IDS";
+/// Telegram parse mode of bot replies, see [`MessageParseMode`]
+pub const ENV_TELEGRAM_PARSE_MODE: &str = "TELEGRAM_PARSE_MODE";
+
+/// Default HTTP status of webhook responses without transactions to import
+const DEFAULT_WEBHOOK_EMPTY_STATUS: u16 = 200;
+/// Default payee for balance corrections
+const DEFAULT_ADJUSTMENT_PAYEE: &str = "Balance Adjustment";
+/// Default separator between the memo fields
+const DEFAULT_MEMO_SEPARATOR: &str = " | ";
+/// Default multiplier converting amounts in pounds to milliunits
+pub const DEFAULT_AMOUNT_SCALE: u32 = 1000;
+/// Default maximum number of concurrent YNAB requests, below the Workers limit of 6 simultaneous
+/// open connections
+pub const DEFAULT_YNAB_MAX_CONCURRENT_REQUESTS: usize = 4;
+/// Default maximum number of transactions per YNAB import request
+///
+/// YNAB accepts larger requests, but recommends batches of 200 to keep them from timing out.
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 200;
+/// Fields making up the YNAB import_id, identifying a transaction by its amount and time
+pub const DEFAULT_IMPORT_ID_FIELDS: [ImportIdField; 2] =
+    [ImportIdField::Amount, ImportIdField::Timestamp];
+/// Maximum absolute timezone offset in minutes
+const TZ_OFFSET_MINUTES_MAX: i32 = 24 * 60;
+
+/// Format version of the YNAB import_id
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportIdVersion {
+    /// `TG:<amount>:<timestamp>`
+    #[default]
+    V1,
+    /// `TG:2:<amount>:<timestamp>`
+    V2,
+    /// `TG<yyyymm>:<amount>:<timestamp>`, grouping transactions by the month of their YNAB date
+    ///
+    /// The month prefix is kept even if the import_id is hashed to fit the length limit.
+    V3,
+}
+
+impl FromStr for ImportIdVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(Self::V1),
+            "2" => Ok(Self::V2),
+            "3" => Ok(Self::V3),
+            _ => Err(format!("unknown import_id version {s}, expected 1, 2 or 3")),
+        }
+    }
+}
+
+impl Display for ImportIdVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V1 => write!(f, "1"),
+            Self::V2 => write!(f, "2"),
+            Self::V3 => write!(f, "3"),
+        }
+    }
+}
+
+/// Yonder transaction field making up the YNAB import_id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportIdField {
+    /// Amount in milliunits
+    Amount,
+    /// Time of the transaction in milliseconds since the epoch
+    Timestamp,
+    /// Date of the transaction, deduplicating transactions of the same amount on the same day
+    Date,
+    /// Lowercase description
+    Description,
+    /// Lowercase Yonder category
+    Category,
+    /// Charged currency
+    Currency,
+}
+
+impl FromStr for ImportIdField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amount" => Ok(Self::Amount),
+            "timestamp" => Ok(Self::Timestamp),
+            "date" => Ok(Self::Date),
+            "description" => Ok(Self::Description),
+            "category" => Ok(Self::Category),
+            "currency" => Ok(Self::Currency),
+            _ => Err(format!(
+                "unknown import_id field {s}, expected amount, timestamp, date, description, category or currency"
+            )),
+        }
+    }
+}
+
+impl Display for ImportIdField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Amount => write!(f, "amount"),
+            Self::Timestamp => write!(f, "timestamp"),
+            Self::Date => write!(f, "date"),
+            Self::Description => write!(f, "description"),
+            Self::Category => write!(f, "category"),
+            Self::Currency => write!(f, "currency"),
+        }
+    }
+}
+
+/// How transactions are split into YNAB import requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportBatching {
+    /// Batches of up to the configured size
+    #[default]
+    Fixed,
+    /// A batch per transaction date, so that each date appears as a separate YNAB import, split
+    /// further if larger than the batch size
+    Daily,
+    /// A batch per month of the transaction date, split further if larger than the batch size
+    Monthly,
+}
+
+impl FromStr for ImportBatching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "daily" => Ok(Self::Daily),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(format!(
+                "unknown import batching {s}, expected fixed, daily or monthly"
+            )),
+        }
+    }
+}
+
+impl Display for ImportBatching {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed => write!(f, "fixed"),
+            Self::Daily => write!(f, "daily"),
+            Self::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// Rule routing matching transactions to a YNAB account other than the default one
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AccountRoute {
+    /// Name of the route, e.g. `business`
+    pub name: String,
+    /// YNAB account ID to import matching transactions to
+    pub account_id: Uuid,
+    /// Match transactions with the description containing this text, case-insensitive
+    #[serde(default)]
+    pub description_contains: Option<String>,
+    /// Match transactions with this Yonder category, case-insensitive
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl AccountRoute {
+    /// Whether the transaction with the given description and category matches the route
+    pub fn matches(&self, description: &str, category: &str) -> bool {
+        self.description_contains.as_ref().is_none_or(|contains| {
+            description
+                .to_lowercase()
+                .contains(&contains.to_lowercase())
+        }) && self
+            .category
+            .as_ref()
+            .is_none_or(|route_category| route_category.eq_ignore_ascii_case(category))
+    }
+}
+
+/// Rule assigning a YNAB category to transactions with the description matching the regex
+#[derive(Debug, Clone, Deserialize)]
+pub struct CategoryRule {
+    /// Regex matched against the description, e.g. `(?i)uber`
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    /// YNAB category ID assigned to matching transactions
+    pub category_id: Uuid,
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// Rule creating matching transactions as scheduled transactions instead of regular ones
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduledRule {
+    /// Match transactions with the description containing this text, case-insensitive
+    pub description_contains: String,
+    /// How often the transaction repeats
+    pub frequency: ScheduledTransactionFrequency,
+}
+
+/// Rule splitting part of matching transactions into another category, e.g. fuel at a supermarket
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CategorySplit {
+    /// Match transactions with the description containing this text, case-insensitive
+    pub description_contains: String,
+    /// YNAB category ID of the split part, the rest keeps the transaction's category
+    pub category_id: Uuid,
+    /// Part of the amount split into the category
+    #[serde(flatten)]
+    pub share: SplitShare,
+}
+
+/// Part of the transaction amount split into another category
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitShare {
+    /// Percentage of the amount, e.g. `30`
+    Percent(f64),
+    /// Fixed amount in GBP, capped at the transaction amount
+    Amount(f64),
+}
+
+impl Display for SplitShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Percent(percent) => write!(f, "{percent}%"),
+            Self::Amount(amount) => write!(f, "£{amount:.2}"),
+        }
+    }
+}
+
+/// Which flag wins when both category and foreign currency flags apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlagPrecedence {
+    /// Category flag wins
+    #[default]
+    Category,
+    /// Foreign currency flag wins
+    Foreign,
+}
+
+impl FromStr for FlagPrecedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "category" => Ok(Self::Category),
+            "foreign" => Ok(Self::Foreign),
+            _ => Err(format!(
+                "unknown flag precedence {s}, expected category or foreign"
+            )),
+        }
+    }
+}
+
+impl Display for FlagPrecedence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Category => write!(f, "category"),
+            Self::Foreign => write!(f, "foreign"),
+        }
+    }
+}
+
+/// How to handle rows with a negative amount, which is inconsistent with their `Debit or Credit`
+/// kind determining the sign
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignMismatchPolicy {
+    /// Import the amount as exported, e.g. a negative debit as an inflow
+    #[default]
+    Keep,
+    /// Skip the inconsistent rows, reporting each of them, and import the rest
+    Error,
+    /// Use the absolute amount, trusting the kind
+    Correct,
+}
+
+impl FromStr for SignMismatchPolicy {
+    type Err = String;
 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "error" => Ok(Self::Error),
+            "correct" => Ok(Self::Correct),
+            _ => Err(format!(
+                "unknown sign mismatch policy {s}, expected keep, error or correct"
+            )),
+        }
+    }
+}
+
+impl Display for SignMismatchPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Keep => write!(f, "keep"),
+            Self::Error => write!(f, "error"),
+            Self::Correct => write!(f, "correct"),
+        }
+    }
+}
+
+/// How to handle summary rows like totals appended to the CSV, which are not transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FooterRowPolicy {
+    /// Skip summary rows, importing the transactions
+    #[default]
+    Skip,
+    /// Fail the import, e.g. to notice when the export format changes
+    Error,
+}
+
+impl FromStr for FooterRowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "unknown footer row policy {s}, expected skip or error"
+            )),
+        }
+    }
+}
+
+impl Display for FooterRowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skip => write!(f, "skip"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Casing of descriptions used as payees, as Yonder descriptions are often all caps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DescriptionCase {
+    /// Keep the description as is
+    #[default]
+    Preserve,
+    /// Capitalize every word, e.g. `Tesco Metro London`
+    Title,
+    /// Capitalize only the first word, e.g. `Tesco metro london`
+    Sentence,
+}
+
+impl FromStr for DescriptionCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Self::Preserve),
+            "title" => Ok(Self::Title),
+            "sentence" => Ok(Self::Sentence),
+            _ => Err(format!(
+                "unknown description case {s}, expected preserve, title or sentence"
+            )),
+        }
+    }
+}
+
+impl Display for DescriptionCase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preserve => write!(f, "preserve"),
+            Self::Title => write!(f, "title"),
+            Self::Sentence => write!(f, "sentence"),
+        }
+    }
+}
+
+/// Yonder transaction field added to the memo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoField {
+    Description,
+    Category,
+    Country,
+    Currency,
+    /// Amount in the charged currency, e.g. `35.00 EUR`
+    ChargedAmount,
+    /// Debit or credit
+    Kind,
+    /// How the payment was made if exported, e.g. `contactless` or `online`
+    Channel,
+    /// Settled and pending amounts if both are exported and differ, e.g. after adding a tip
+    PendingAmount,
+}
+
+impl FromStr for MemoField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "description" => Ok(Self::Description),
+            "category" => Ok(Self::Category),
+            "country" => Ok(Self::Country),
+            "currency" => Ok(Self::Currency),
+            "charged_amount" => Ok(Self::ChargedAmount),
+            "kind" => Ok(Self::Kind),
+            "channel" => Ok(Self::Channel),
+            "pending_amount" => Ok(Self::PendingAmount),
+            _ => Err(format!(
+                "unknown memo field {s}, expected description, category, country, currency, charged_amount, kind, channel or pending_amount"
+            )),
+        }
+    }
+}
+
+impl Display for MemoField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Description => write!(f, "description"),
+            Self::Category => write!(f, "category"),
+            Self::Country => write!(f, "country"),
+            Self::Currency => write!(f, "currency"),
+            Self::ChargedAmount => write!(f, "charged_amount"),
+            Self::Kind => write!(f, "kind"),
+            Self::Channel => write!(f, "channel"),
+            Self::PendingAmount => write!(f, "pending_amount"),
+        }
+    }
+}
+
+/// How to handle the YNAB budget using a currency other than the expected one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CurrencyMismatchPolicy {
+    /// Import, warning about the mismatch in the result
+    #[default]
+    Warn,
+    /// Fail the import
+    Error,
+}
+
+impl FromStr for CurrencyMismatchPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "unknown currency mismatch policy {s}, expected warn or error"
+            )),
+        }
+    }
+}
+
+impl Display for CurrencyMismatchPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warn => write!(f, "warn"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Telegram parse mode of bot replies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageParseMode {
+    /// Plain text
+    #[default]
+    Plain,
+    Html,
+    MarkdownV2,
+}
+
+impl FromStr for MessageParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "html" => Ok(Self::Html),
+            "markdownv2" => Ok(Self::MarkdownV2),
+            _ => Err(format!(
+                "unknown parse mode {s}, expected plain, html or markdownv2"
+            )),
+        }
+    }
+}
+
+impl Display for MessageParseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plain => write!(f, "plain"),
+            Self::Html => write!(f, "html"),
+            Self::MarkdownV2 => write!(f, "markdownv2"),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Config {
     pub ynab_api_key: String,
     pub ynab_budget_id: String,
-    pub ynab_account_id: Uuid,
+    /// Default account, optional if account routes cover all transactions
+    pub ynab_account_id: Option<Uuid>,
     pub tg_api_key: Option<String>,
     pub webhook_api_key: Option<String>,
+    pub default_memo: Option<String>,
+    pub cleared_status: Option<TransactionClearedStatus>,
+    pub tz_offset_minutes: i32,
+    pub amount_scale: Option<u32>,
+    pub amount_max_decimals: Option<u32>,
+    pub ynab_max_concurrent_requests: Option<usize>,
+    pub import_batching: ImportBatching,
+    pub import_batch_size: Option<usize>,
+    pub telegram_allowed_chat_ids: Vec<i64>,
+    pub telegram_parse_mode: MessageParseMode,
+    pub import_id_version: ImportIdVersion,
+    pub import_id_fields: Vec<ImportIdField>,
+    pub import_id_transition_until: Option<NaiveDate>,
+    pub import_future_transactions: bool,
+    pub allowed_currencies: Vec<String>,
+    pub min_amount_gbp: f64,
+    pub large_transaction_gbp: Option<f64>,
+    pub preserve_whitespace: bool,
+    pub strict_no_duplicates: bool,
+    pub import_window_days: Option<u32>,
+    pub generic_descriptions: Vec<String>,
+    pub description_prefixes: Vec<String>,
+    pub description_case: DescriptionCase,
+    pub description_case_exceptions: Vec<String>,
+    pub payee_country: bool,
+    pub credit_payee: Option<String>,
+    pub single_payee: Option<String>,
+    pub payment_descriptions: Vec<String>,
+    pub payment_transfer_payee_id: Option<Uuid>,
+    pub webhook_rate_limit: u32,
+    pub webhook_empty_status: Option<u16>,
+    pub cors_allowed_origins: Vec<String>,
+    pub source_url_allowed_hosts: Vec<String>,
+    pub summary_webhook_url: Option<reqwest::Url>,
+    pub category_map: BTreeMap<String, Uuid>,
+    pub category_group_map: BTreeMap<String, String>,
+    pub category_description_rules: Vec<CategoryRule>,
+    pub default_category_id: Option<Uuid>,
+    pub travel_category_id: Option<Uuid>,
+    pub travel_account_id: Option<Uuid>,
+    pub account_routes: Vec<AccountRoute>,
+    pub scheduled_rules: Vec<ScheduledRule>,
+    pub category_splits: Vec<CategorySplit>,
+    pub memo_route_hint: bool,
+    pub memo_fields: Vec<MemoField>,
+    pub memo_separator: Option<String>,
+    pub memo_row_counter: bool,
+    pub memo_category_hashtag: bool,
+    pub memo_upload_id: bool,
+    pub adjustment_descriptions: Vec<String>,
+    pub adjustment_payee: String,
+    pub adjustment_flag_color: Option<TransactionFlagColor>,
+    pub category_flag_colors: BTreeMap<String, TransactionFlagColor>,
+    pub category_flag_hash: bool,
+    pub weekday_flag_colors: Vec<(Weekday, TransactionFlagColor)>,
+    pub foreign_flag_color: Option<TransactionFlagColor>,
+    pub fx_fee_category_id: Option<Uuid>,
+    pub fx_reference_rates: BTreeMap<String, f64>,
+    pub flag_precedence: FlagPrecedence,
+    pub sign_mismatch_policy: SignMismatchPolicy,
+    pub footer_row_policy: FooterRowPolicy,
+    pub statement_period_marker: bool,
+    pub partial_import: bool,
+    pub validate_rules: bool,
+    pub track_server_knowledge: bool,
+    pub onboarding: bool,
+    pub store_imported_ids: bool,
+    pub approve_seen_payees: bool,
+    pub budget_currency: Option<String>,
+    pub budget_currency_policy: CurrencyMismatchPolicy,
+    pub preview_file_threshold: Option<usize>,
+    pub download_retries: u32,
+}
+
+impl Config {
+    /// Whether the Telegram chat is allowed to use privileged commands
+    pub fn is_chat_allowed(&self, chat_id: i64) -> bool {
+        self.telegram_allowed_chat_ids.contains(&chat_id)
+    }
+
+    /// Whether transactions in the currency should be imported, all are allowed by default
+    pub fn is_currency_allowed(&self, currency: &str) -> bool {
+        self.allowed_currencies.is_empty()
+            || self
+                .allowed_currencies
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(currency))
+    }
+
+    /// Whether the description is too generic to be used as the payee
+    pub fn is_generic_description(&self, description: &str) -> bool {
+        self.generic_descriptions
+            .iter()
+            .any(|generic| generic.eq_ignore_ascii_case(description.trim()))
+    }
+
+    /// Description without the first configured prefix followed by whitespace, case-insensitive
+    pub fn strip_description_prefix<'a>(&self, description: &'a str) -> &'a str {
+        self.description_prefixes
+            .iter()
+            .find_map(|prefix| {
+                let rest = description.get(prefix.len()..)?;
+                (description[..prefix.len()].eq_ignore_ascii_case(prefix)
+                    && rest.starts_with(char::is_whitespace))
+                .then(|| rest.trim_start())
+            })
+            .unwrap_or(description)
+    }
+
+    /// Description converted to the configured casing, keeping the exception words as configured
+    pub fn convert_description_case(&self, description: &str) -> String {
+        if self.description_case == DescriptionCase::Preserve {
+            return description.to_string();
+        }
+
+        let mut first = true;
+        description
+            .split(' ')
+            .map(|word| {
+                if word.is_empty() {
+                    return String::new();
+                }
+                let capitalize = first || self.description_case == DescriptionCase::Title;
+                first = false;
+
+                if let Some(exception) = self
+                    .description_case_exceptions
+                    .iter()
+                    .find(|exception| exception.eq_ignore_ascii_case(word))
+                {
+                    return exception.clone();
+                }
+
+                let mut chars = word.chars();
+                let head = chars.next().map(|head| {
+                    if capitalize {
+                        head.to_uppercase().collect::<String>()
+                    } else {
+                        head.to_lowercase().collect()
+                    }
+                });
+                head.into_iter()
+                    .chain(std::iter::once(chars.as_str().to_lowercase()))
+                    .collect()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// YNAB category ID mapped from the Yonder category
+    pub fn mapped_category_id(&self, category: &str) -> Option<Uuid> {
+        self.category_map
+            .iter()
+            .find(|(yonder_category, _)| yonder_category.eq_ignore_ascii_case(category.trim()))
+            .map(|(_, category_id)| *category_id)
+    }
+
+    /// YNAB category group name mapped to the Yonder category
+    pub fn mapped_category_group(&self, category: &str) -> Option<&str> {
+        self.category_group_map
+            .iter()
+            .find(|(yonder_category, _)| yonder_category.eq_ignore_ascii_case(category.trim()))
+            .map(|(_, group)| group.as_str())
+    }
+
+    /// YNAB category ID of the first description rule matching the description
+    pub fn description_category_id(&self, description: &str) -> Option<Uuid> {
+        self.category_description_rules
+            .iter()
+            .find(|rule| rule.pattern.is_match(description))
+            .map(|rule| rule.category_id)
+    }
+
+    /// Reference rate in GBP per unit of the currency
+    pub fn fx_reference_rate(&self, currency: &str) -> Option<f64> {
+        self.fx_reference_rates
+            .iter()
+            .find(|(rate_currency, _)| rate_currency.eq_ignore_ascii_case(currency))
+            .map(|(_, rate)| *rate)
+    }
+
+    /// First account route matching the transaction
+    pub fn account_route(&self, description: &str, category: &str) -> Option<&AccountRoute> {
+        self.account_routes
+            .iter()
+            .find(|route| route.matches(description, category))
+    }
+
+    /// Frequency of the first scheduled rule matching the description
+    pub fn scheduled_frequency(&self, description: &str) -> Option<ScheduledTransactionFrequency> {
+        let description = description.to_lowercase();
+        self.scheduled_rules
+            .iter()
+            .find(|rule| description.contains(&rule.description_contains.to_lowercase()))
+            .map(|rule| rule.frequency)
+    }
+
+    /// First category split matching the description
+    pub fn category_split(&self, description: &str) -> Option<&CategorySplit> {
+        let description = description.to_lowercase();
+        self.category_splits
+            .iter()
+            .find(|split| description.contains(&split.description_contains.to_lowercase()))
+    }
+
+    /// HTTP status of webhook responses when the CSV has no transactions to import
+    pub fn webhook_empty_status(&self) -> u16 {
+        self.webhook_empty_status
+            .unwrap_or(DEFAULT_WEBHOOK_EMPTY_STATUS)
+    }
+
+    /// Default YNAB account, required by features operating on a single account
+    pub fn account_id(&self) -> eyre::Result<Uuid> {
+        self.ynab_account_id
+            .ok_or_else(|| eyre::eyre!("{ENV_YNAB_ACCOUNT_ID} is not set"))
+    }
+
+    /// Multiplier converting CSV amounts to YNAB milliunits
+    pub fn amount_scale(&self) -> u32 {
+        self.amount_scale.unwrap_or(DEFAULT_AMOUNT_SCALE)
+    }
+
+    /// Separator between the memo fields
+    pub fn memo_separator(&self) -> &str {
+        self.memo_separator
+            .as_deref()
+            .unwrap_or(DEFAULT_MEMO_SEPARATOR)
+    }
+
+    /// Maximum number of concurrent YNAB requests during an import
+    pub fn ynab_max_concurrent_requests(&self) -> usize {
+        self.ynab_max_concurrent_requests
+            .unwrap_or(DEFAULT_YNAB_MAX_CONCURRENT_REQUESTS)
+    }
+
+    /// Fields making up the YNAB import_id
+    pub fn import_id_fields(&self) -> &[ImportIdField] {
+        if self.import_id_fields.is_empty() {
+            &DEFAULT_IMPORT_ID_FIELDS
+        } else {
+            &self.import_id_fields
+        }
+    }
+
+    /// Maximum number of transactions per YNAB import request
+    pub fn import_batch_size(&self) -> usize {
+        self.import_batch_size.unwrap_or(DEFAULT_IMPORT_BATCH_SIZE)
+    }
+
+    /// Cleared status of imported transactions
+    pub fn cleared_status(&self) -> TransactionClearedStatus {
+        self.cleared_status
+            .unwrap_or(TransactionClearedStatus::Cleared)
+    }
+
+    /// Whether browsers from the origin are allowed to call the webhook
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.cors_allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Flag color configured for the Yonder category
+    pub fn category_flag_color(&self, category: &str) -> Option<&TransactionFlagColor> {
+        self.category_flag_colors
+            .iter()
+            .find(|(flag_category, _)| flag_category.eq_ignore_ascii_case(category.trim()))
+            .map(|(_, color)| color)
+    }
+
+    /// Flag color configured for the day of week
+    pub fn weekday_flag_color(&self, weekday: Weekday) -> Option<&TransactionFlagColor> {
+        self.weekday_flag_colors
+            .iter()
+            .find(|(flag_weekday, _)| *flag_weekday == weekday)
+            .map(|(_, color)| color)
+    }
+
+    /// Whether the description marks a Yonder balance correction
+    pub fn is_adjustment(&self, description: &str) -> bool {
+        self.adjustment_descriptions
+            .iter()
+            .any(|adjustment| adjustment.eq_ignore_ascii_case(description.trim()))
+    }
+
+    /// Transfer payee ID for the credit if its description marks a card repayment
+    pub fn payment_transfer_payee_id(&self, description: &str) -> Option<Uuid> {
+        let description = description.to_lowercase();
+        self.payment_transfer_payee_id.filter(|_| {
+            self.payment_descriptions
+                .iter()
+                .any(|payment| description.contains(&payment.to_lowercase()))
+        })
+    }
+
+    /// Human-readable summary of the rules mapping transactions
+    pub fn rules(&self) -> String {
+        fn list(items: &[String]) -> String {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        }
+
+        let mut lines = vec!["Categories:".to_string()];
+        lines.extend(
+            self.category_description_rules
+                .iter()
+                .map(|rule| format!("- description /{}/ → {}", rule.pattern, rule.category_id)),
+        );
+        lines.extend(
+            self.category_map
+                .iter()
+                .map(|(category, category_id)| format!("- category {category} → {category_id}")),
+        );
+        lines.extend(
+            self.category_group_map
+                .iter()
+                .map(|(category, group)| format!("- category {category} → group {group}")),
+        );
+        if let Some(travel_category_id) = self.travel_category_id {
+            lines.push(format!("- outside the UK → {travel_category_id}"));
+        }
+        lines.push(format!(
+            "- default → {}",
+            self.default_category_id
+                .map_or("none".to_string(), |category_id| category_id.to_string())
+        ));
+        lines.extend(self.category_splits.iter().map(|split| {
+            format!(
+                "- description contains {:?} → {} split into {}",
+                split.description_contains, split.share, split.category_id
+            )
+        }));
+
+        lines.push("Payees:".to_string());
+        lines.push(format!(
+            "- generic descriptions use the category: {}",
+            list(&self.generic_descriptions)
+        ));
+        if !self.description_prefixes.is_empty() {
+            lines.push(format!(
+                "- prefixes stripped: {}",
+                self.description_prefixes.join(", ")
+            ));
+        }
+        if self.description_case != DescriptionCase::Preserve {
+            lines.push(format!(
+                "- {} case, except: {}",
+                self.description_case,
+                list(&self.description_case_exceptions)
+            ));
+        }
+        lines.push(format!(
+            "- adjustments → {}: {}",
+            self.adjustment_payee,
+            list(&self.adjustment_descriptions)
+        ));
+        if let Some(credit_payee) = &self.credit_payee {
+            lines.push(format!("- credits → {credit_payee}"));
+        }
+        if let Some(single_payee) = &self.single_payee {
+            lines.push(format!(
+                "- all transactions → {single_payee}, description in the memo"
+            ));
+        }
+        if let Some(payee_id) = self.payment_transfer_payee_id {
+            lines.push(format!(
+                "- payment credits → transfer {payee_id}: {}",
+                list(&self.payment_descriptions)
+            ));
+        }
+        if self.payee_country {
+            lines.push("- country appended outside the UK".to_string());
+        }
+
+        lines.push("Accounts:".to_string());
+        if let Some(travel_account_id) = self.travel_account_id {
+            lines.push(format!("- foreign currency → {travel_account_id}"));
+        }
+        lines.extend(self.account_routes.iter().map(|route| {
+            let conditions = [
+                route
+                    .description_contains
+                    .as_ref()
+                    .map(|contains| format!("description contains {contains:?}")),
+                route
+                    .category
+                    .as_ref()
+                    .map(|category| format!("category {category}")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+            format!(
+                "- {} ({}) → {}",
+                route.name,
+                list(&conditions),
+                route.account_id
+            )
+        }));
+        lines.push(format!(
+            "- default → {}",
+            self.ynab_account_id
+                .map_or("none".to_string(), |account_id| account_id.to_string())
+        ));
+
+        if !self.scheduled_rules.is_empty() {
+            lines.push("Scheduled:".to_string());
+            lines.extend(self.scheduled_rules.iter().map(|rule| {
+                format!(
+                    "- description contains {:?} → {}",
+                    rule.description_contains, rule.frequency
+                )
+            }));
+        }
+
+        lines.push("Skipped:".to_string());
+        if !self.import_future_transactions {
+            lines.push("- future transactions".to_string());
+        }
+        if !self.allowed_currencies.is_empty() {
+            lines.push(format!(
+                "- currencies other than {}",
+                self.allowed_currencies.join(", ")
+            ));
+        }
+        if self.min_amount_gbp > 0.0 {
+            lines.push(format!("- amounts under £{:.2}", self.min_amount_gbp));
+        }
+        if let Some(import_window_days) = self.import_window_days {
+            lines.push(format!("- CSVs older than {import_window_days} days"));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Human-readable config summary with secrets redacted
+    pub fn redacted(&self) -> String {
+        fn secret(value: Option<&str>) -> &'static str {
+            if value.is_some_and(|value| !value.is_empty()) {
+                "set"
+            } else {
+                "unset"
+            }
+        }
+
+        [
+            format!("{ENV_YNAB_API_KEY}: {}", secret(Some(&self.ynab_api_key))),
+            format!("{ENV_YNAB_BUDGET_ID}: {}", self.ynab_budget_id),
+            format!(
+                "{ENV_YNAB_ACCOUNT_ID}: {}",
+                self.ynab_account_id
+                    .map_or("unset".to_string(), |account_id| account_id.to_string())
+            ),
+            format!("{ENV_API_KEY}: {}", secret(self.tg_api_key.as_deref())),
+            format!(
+                "{ENV_WEBHOOK_API_KEY}: {}",
+                secret(self.webhook_api_key.as_deref())
+            ),
+            format!(
+                "{ENV_DEFAULT_MEMO}: {}",
+                self.default_memo.as_deref().unwrap_or("unset")
+            ),
+            format!("{ENV_CLEARED_STATUS}: {}", self.cleared_status()),
+            format!("{ENV_TZ_OFFSET_MINUTES}: {}", self.tz_offset_minutes),
+            format!("{ENV_AMOUNT_SCALE}: {}", self.amount_scale()),
+            format!(
+                "{ENV_AMOUNT_MAX_DECIMALS}: {}",
+                self.amount_max_decimals
+                    .map_or("unset".to_string(), |decimals| decimals.to_string())
+            ),
+            format!(
+                "{ENV_YNAB_MAX_CONCURRENT_REQUESTS}: {}",
+                self.ynab_max_concurrent_requests()
+            ),
+            format!("{ENV_IMPORT_BATCHING}: {}", self.import_batching),
+            format!("{ENV_IMPORT_BATCH_SIZE}: {}", self.import_batch_size()),
+            format!("{ENV_IMPORT_ID_VERSION}: {}", self.import_id_version),
+            format!(
+                "{ENV_IMPORT_ID_FIELDS}: {}",
+                self.import_id_fields()
+                    .iter()
+                    .map(ImportIdField::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            format!(
+                "{ENV_IMPORT_ID_TRANSITION_UNTIL}: {}",
+                self.import_id_transition_until
+                    .map_or("unset".to_string(), |date| date.to_string())
+            ),
+            format!(
+                "{ENV_IMPORT_FUTURE_TRANSACTIONS}: {}",
+                self.import_future_transactions
+            ),
+            format!(
+                "{ENV_ALLOWED_CURRENCIES}: {}",
+                self.allowed_currencies.join(", ")
+            ),
+            format!("{ENV_MIN_AMOUNT_GBP}: {}", self.min_amount_gbp),
+            format!(
+                "{ENV_LARGE_TRANSACTION_GBP}: {}",
+                self.large_transaction_gbp
+                    .map_or("unset".to_string(), |amount| amount.to_string())
+            ),
+            format!("{ENV_PRESERVE_WHITESPACE}: {}", self.preserve_whitespace),
+            format!("{ENV_STRICT_NO_DUPLICATES}: {}", self.strict_no_duplicates),
+            format!(
+                "{ENV_IMPORT_WINDOW_DAYS}: {}",
+                self.import_window_days
+                    .map_or("unset".to_string(), |days| days.to_string())
+            ),
+            format!(
+                "{ENV_GENERIC_DESCRIPTIONS}: {}",
+                self.generic_descriptions.join(", ")
+            ),
+            format!(
+                "{ENV_DESCRIPTION_PREFIXES}: {}",
+                self.description_prefixes.join(", ")
+            ),
+            format!("{ENV_DESCRIPTION_CASE}: {}", self.description_case),
+            format!(
+                "{ENV_DESCRIPTION_CASE_EXCEPTIONS}: {}",
+                self.description_case_exceptions.join(", ")
+            ),
+            format!("{ENV_PAYEE_COUNTRY}: {}", self.payee_country),
+            format!(
+                "{ENV_CREDIT_PAYEE}: {}",
+                self.credit_payee.as_deref().unwrap_or("unset")
+            ),
+            format!(
+                "{ENV_SINGLE_PAYEE}: {}",
+                self.single_payee.as_deref().unwrap_or("unset")
+            ),
+            format!(
+                "{ENV_PAYMENT_DESCRIPTIONS}: {}",
+                self.payment_descriptions.join(", ")
+            ),
+            format!(
+                "{ENV_PAYMENT_TRANSFER_PAYEE_ID}: {}",
+                self.payment_transfer_payee_id
+                    .map_or("unset".to_string(), |payee_id| payee_id.to_string())
+            ),
+            format!(
+                "{ENV_ADJUSTMENT_DESCRIPTIONS}: {}",
+                self.adjustment_descriptions.join(", ")
+            ),
+            format!("{ENV_ADJUSTMENT_PAYEE}: {}", self.adjustment_payee),
+            format!(
+                "{ENV_ADJUSTMENT_FLAG_COLOR}: {}",
+                self.adjustment_flag_color
+                    .as_ref()
+                    .map_or("unset".to_string(), |color| color.to_string())
+            ),
+            format!("{ENV_CATEGORY_MAP}: {} categories", self.category_map.len()),
+            format!(
+                "{ENV_CATEGORY_GROUP_MAP}: {} categories",
+                self.category_group_map.len()
+            ),
+            format!(
+                "{ENV_CATEGORY_DESCRIPTION_RULES}: {} rules",
+                self.category_description_rules.len()
+            ),
+            format!(
+                "{ENV_DEFAULT_CATEGORY_ID}: {}",
+                self.default_category_id
+                    .map_or("unset".to_string(), |category_id| category_id.to_string())
+            ),
+            format!(
+                "{ENV_TRAVEL_CATEGORY_ID}: {}",
+                self.travel_category_id
+                    .map_or("unset".to_string(), |category_id| category_id.to_string())
+            ),
+            format!(
+                "{ENV_TRAVEL_ACCOUNT_ID}: {}",
+                self.travel_account_id
+                    .map_or("unset".to_string(), |account_id| account_id.to_string())
+            ),
+            format!(
+                "{ENV_ACCOUNT_ROUTES}: {}",
+                self.account_routes
+                    .iter()
+                    .map(|route| format!("{} -> {}", route.name, route.account_id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            format!(
+                "{ENV_SCHEDULED_RULES}: {} rules",
+                self.scheduled_rules.len()
+            ),
+            format!(
+                "{ENV_CATEGORY_SPLITS}: {} rules",
+                self.category_splits.len()
+            ),
+            format!("{ENV_MEMO_ROUTE_HINT}: {}", self.memo_route_hint),
+            format!(
+                "{ENV_MEMO_FIELDS}: {}",
+                self.memo_fields
+                    .iter()
+                    .map(|field| field.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            format!("{ENV_MEMO_SEPARATOR}: {:?}", self.memo_separator()),
+            format!("{ENV_MEMO_ROW_COUNTER}: {}", self.memo_row_counter),
+            format!(
+                "{ENV_MEMO_CATEGORY_HASHTAG}: {}",
+                self.memo_category_hashtag
+            ),
+            format!("{ENV_MEMO_UPLOAD_ID}: {}", self.memo_upload_id),
+            format!(
+                "{ENV_CATEGORY_FLAG_COLORS}: {}",
+                self.category_flag_colors
+                    .iter()
+                    .map(|(category, color)| format!("{category} -> {color}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            format!("{ENV_CATEGORY_FLAG_HASH}: {}", self.category_flag_hash),
+            format!(
+                "{ENV_WEEKDAY_FLAG_COLORS}: {}",
+                self.weekday_flag_colors
+                    .iter()
+                    .map(|(weekday, color)| format!("{weekday} -> {color}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            format!(
+                "{ENV_FOREIGN_FLAG_COLOR}: {}",
+                self.foreign_flag_color
+                    .as_ref()
+                    .map_or("unset".to_string(), |color| color.to_string())
+            ),
+            format!(
+                "{ENV_FX_FEE_CATEGORY_ID}: {}",
+                self.fx_fee_category_id
+                    .map_or("unset".to_string(), |category_id| category_id.to_string())
+            ),
+            format!(
+                "{ENV_FX_REFERENCE_RATES}: {}",
+                self.fx_reference_rates
+                    .iter()
+                    .map(|(currency, rate)| format!("{currency}={rate}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            format!("{ENV_FLAG_PRECEDENCE}: {}", self.flag_precedence),
+            format!("{ENV_SIGN_MISMATCH_POLICY}: {}", self.sign_mismatch_policy),
+            format!("{ENV_FOOTER_ROW_POLICY}: {}", self.footer_row_policy),
+            format!(
+                "{ENV_STATEMENT_PERIOD_MARKER}: {}",
+                self.statement_period_marker
+            ),
+            format!("{ENV_PARTIAL_IMPORT}: {}", self.partial_import),
+            format!("{ENV_VALIDATE_RULES}: {}", self.validate_rules),
+            format!(
+                "{ENV_TRACK_SERVER_KNOWLEDGE}: {}",
+                self.track_server_knowledge
+            ),
+            format!("{ENV_ONBOARDING}: {}", self.onboarding),
+            format!("{ENV_STORE_IMPORTED_IDS}: {}", self.store_imported_ids),
+            format!("{ENV_APPROVE_SEEN_PAYEES}: {}", self.approve_seen_payees),
+            format!(
+                "{ENV_BUDGET_CURRENCY}: {}",
+                self.budget_currency.as_deref().unwrap_or("unset")
+            ),
+            format!(
+                "{ENV_BUDGET_CURRENCY_POLICY}: {}",
+                self.budget_currency_policy
+            ),
+            format!("{ENV_DOWNLOAD_RETRIES}: {}", self.download_retries),
+            format!(
+                "{ENV_PREVIEW_FILE_THRESHOLD}: {}",
+                self.preview_file_threshold
+                    .map_or("unset".to_string(), |threshold| threshold.to_string())
+            ),
+            format!("{ENV_WEBHOOK_RATE_LIMIT}: {}", self.webhook_rate_limit),
+            format!(
+                "{ENV_WEBHOOK_EMPTY_STATUS}: {}",
+                self.webhook_empty_status()
+            ),
+            format!(
+                "{ENV_CORS_ALLOWED_ORIGINS}: {}",
+                self.cors_allowed_origins.join(", ")
+            ),
+            format!(
+                "{ENV_SOURCE_URL_ALLOWED_HOSTS}: {}",
+                self.source_url_allowed_hosts.join(", ")
+            ),
+            // Webhook URLs like Slack's contain secrets
+            format!(
+                "{ENV_SUMMARY_WEBHOOK_URL}: {}",
+                secret(self.summary_webhook_url.as_ref().map(|url| url.as_str()))
+            ),
+            format!(
+                "{ENV_TELEGRAM_ALLOWED_CHAT_IDS}: {} chats",
+                self.telegram_allowed_chat_ids.len()
+            ),
+            format!("{ENV_TELEGRAM_PARSE_MODE}: {}", self.telegram_parse_mode),
+        ]
+        .join("\n")
+    }
+}
+
+/// Why the import_id fields risk deduplicating distinct transactions or repeat a field
+fn import_id_fields_error(fields: &[ImportIdField]) -> Option<String> {
+    if fields.is_empty() {
+        return None;
+    }
+    if let Some(field) = fields
+        .iter()
+        .enumerate()
+        .find_map(|(i, field)| fields[..i].contains(field).then_some(field))
+    {
+        return Some(format!("lists {field} more than once"));
+    }
+    if !fields.contains(&ImportIdField::Amount) {
+        return Some(
+            "must include amount, otherwise transactions of different amounts share the import_id"
+                .to_string(),
+        );
+    }
+    if !fields.contains(&ImportIdField::Timestamp) && !fields.contains(&ImportIdField::Date) {
+        return Some("must include timestamp or date, otherwise transactions of the same amount are only imported once".to_string());
+    }
+    None
 }
 
 pub fn init_config(env: &Env) -> worker::Result<Config> {
     let ynab_api_key = env.secret(ENV_YNAB_API_KEY)?.to_string();
     let ynab_budget_id = env.secret(ENV_YNAB_BUDGET_ID)?.to_string();
     let ynab_account_id = env
-        .secret(ENV_YNAB_ACCOUNT_ID)?
-        .to_string()
-        .parse::<Uuid>()
+        .secret(ENV_YNAB_ACCOUNT_ID)
+        .ok()
+        .map(|account_id| account_id.to_string())
+        .filter(|account_id| !account_id.is_empty())
+        .map(|account_id| account_id.parse::<Uuid>())
+        .transpose()
         .map_err(|err| worker::Error::RustError(err.to_string()))?;
     let tg_api_key = env
         .secret(ENV_API_KEY)
@@ -38,6 +1433,136 @@ pub fn init_config(env: &Env) -> worker::Result<Config> {
         .secret(ENV_WEBHOOK_API_KEY)
         .ok()
         .map(|secret| secret.to_string());
+    let default_memo = optional_var(env, ENV_DEFAULT_MEMO);
+    let cleared_status = parse_var(env, ENV_CLEARED_STATUS)?;
+    let tz_offset_minutes: i32 = parse_var(env, ENV_TZ_OFFSET_MINUTES)?.unwrap_or_default();
+    let amount_scale: Option<u32> = parse_var(env, ENV_AMOUNT_SCALE)?;
+    let amount_max_decimals = parse_var(env, ENV_AMOUNT_MAX_DECIMALS)?;
+    let ynab_max_concurrent_requests: Option<usize> =
+        parse_var(env, ENV_YNAB_MAX_CONCURRENT_REQUESTS)?;
+    let import_batching = parse_var(env, ENV_IMPORT_BATCHING)?.unwrap_or_default();
+    let import_batch_size: Option<usize> = parse_var(env, ENV_IMPORT_BATCH_SIZE)?;
+    let telegram_allowed_chat_ids = parse_list_var(env, ENV_TELEGRAM_ALLOWED_CHAT_IDS)?;
+    let telegram_parse_mode = parse_var(env, ENV_TELEGRAM_PARSE_MODE)?.unwrap_or_default();
+    let import_id_version = parse_var(env, ENV_IMPORT_ID_VERSION)?.unwrap_or_default();
+    let import_id_fields: Vec<ImportIdField> = parse_list_var(env, ENV_IMPORT_ID_FIELDS)?;
+    let import_id_transition_until = parse_var(env, ENV_IMPORT_ID_TRANSITION_UNTIL)?;
+    let import_future_transactions =
+        parse_var(env, ENV_IMPORT_FUTURE_TRANSACTIONS)?.unwrap_or_default();
+    let allowed_currencies = parse_list_var(env, ENV_ALLOWED_CURRENCIES)?;
+    let min_amount_gbp: f64 = parse_var(env, ENV_MIN_AMOUNT_GBP)?.unwrap_or_default();
+    let large_transaction_gbp: Option<f64> = parse_var(env, ENV_LARGE_TRANSACTION_GBP)?;
+    let preserve_whitespace = parse_var(env, ENV_PRESERVE_WHITESPACE)?.unwrap_or_default();
+    let strict_no_duplicates = parse_var(env, ENV_STRICT_NO_DUPLICATES)?.unwrap_or_default();
+    let import_window_days = parse_var(env, ENV_IMPORT_WINDOW_DAYS)?;
+    let generic_descriptions = parse_list_var(env, ENV_GENERIC_DESCRIPTIONS)?;
+    let description_prefixes = parse_list_var(env, ENV_DESCRIPTION_PREFIXES)?;
+    let description_case = parse_var(env, ENV_DESCRIPTION_CASE)?.unwrap_or_default();
+    let description_case_exceptions = parse_list_var(env, ENV_DESCRIPTION_CASE_EXCEPTIONS)?;
+    let payee_country = parse_var(env, ENV_PAYEE_COUNTRY)?.unwrap_or_default();
+    let credit_payee = optional_var(env, ENV_CREDIT_PAYEE);
+    let single_payee = optional_var(env, ENV_SINGLE_PAYEE);
+    let payment_descriptions = parse_list_var(env, ENV_PAYMENT_DESCRIPTIONS)?;
+    let payment_transfer_payee_id = parse_var(env, ENV_PAYMENT_TRANSFER_PAYEE_ID)?;
+    let adjustment_descriptions = parse_list_var(env, ENV_ADJUSTMENT_DESCRIPTIONS)?;
+    let adjustment_payee = optional_var(env, ENV_ADJUSTMENT_PAYEE)
+        .unwrap_or_else(|| DEFAULT_ADJUSTMENT_PAYEE.to_string());
+    let adjustment_flag_color = parse_var(env, ENV_ADJUSTMENT_FLAG_COLOR)?;
+    let cors_allowed_origins = parse_list_var(env, ENV_CORS_ALLOWED_ORIGINS)?;
+    let source_url_allowed_hosts = parse_list_var(env, ENV_SOURCE_URL_ALLOWED_HOSTS)?;
+    let summary_webhook_url = parse_var(env, ENV_SUMMARY_WEBHOOK_URL)?;
+    let category_map = parse_json_var(env, ENV_CATEGORY_MAP)?.unwrap_or_default();
+    let category_group_map = parse_json_var(env, ENV_CATEGORY_GROUP_MAP)?.unwrap_or_default();
+    let category_description_rules =
+        parse_json_var(env, ENV_CATEGORY_DESCRIPTION_RULES)?.unwrap_or_default();
+    let default_category_id = parse_var(env, ENV_DEFAULT_CATEGORY_ID)?;
+    let travel_category_id = parse_var(env, ENV_TRAVEL_CATEGORY_ID)?;
+    let travel_account_id = parse_var(env, ENV_TRAVEL_ACCOUNT_ID)?;
+    let account_routes = parse_json_var(env, ENV_ACCOUNT_ROUTES)?.unwrap_or_default();
+    let scheduled_rules = parse_json_var(env, ENV_SCHEDULED_RULES)?.unwrap_or_default();
+    let category_splits = parse_json_var(env, ENV_CATEGORY_SPLITS)?.unwrap_or_default();
+    let memo_route_hint = parse_var(env, ENV_MEMO_ROUTE_HINT)?.unwrap_or_default();
+    let memo_fields = parse_list_var(env, ENV_MEMO_FIELDS)?;
+    let memo_separator = optional_var(env, ENV_MEMO_SEPARATOR);
+    let memo_row_counter = parse_var(env, ENV_MEMO_ROW_COUNTER)?.unwrap_or_default();
+    let memo_category_hashtag = parse_var(env, ENV_MEMO_CATEGORY_HASHTAG)?.unwrap_or_default();
+    let memo_upload_id = parse_var(env, ENV_MEMO_UPLOAD_ID)?.unwrap_or_default();
+    let category_flag_colors = parse_json_var(env, ENV_CATEGORY_FLAG_COLORS)?.unwrap_or_default();
+    let category_flag_hash = parse_var(env, ENV_CATEGORY_FLAG_HASH)?.unwrap_or_default();
+    let weekday_flag_colors =
+        parse_json_var::<BTreeMap<String, TransactionFlagColor>>(env, ENV_WEEKDAY_FLAG_COLORS)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(weekday, color)| {
+                weekday
+                    .parse::<Weekday>()
+                    .map(|weekday| (weekday, color))
+                    .map_err(|_| {
+                        worker::Error::RustError(format!(
+                            "unknown {ENV_WEEKDAY_FLAG_COLORS} weekday {weekday}, expected monday to sunday"
+                        ))
+                    })
+            })
+            .collect::<worker::Result<Vec<_>>>()?;
+    let foreign_flag_color = parse_var(env, ENV_FOREIGN_FLAG_COLOR)?;
+    let fx_fee_category_id = parse_var(env, ENV_FX_FEE_CATEGORY_ID)?;
+    let fx_reference_rates = parse_json_var(env, ENV_FX_REFERENCE_RATES)?.unwrap_or_default();
+    let flag_precedence = parse_var(env, ENV_FLAG_PRECEDENCE)?.unwrap_or_default();
+    let sign_mismatch_policy = parse_var(env, ENV_SIGN_MISMATCH_POLICY)?.unwrap_or_default();
+    let footer_row_policy = parse_var(env, ENV_FOOTER_ROW_POLICY)?.unwrap_or_default();
+    let statement_period_marker = parse_var(env, ENV_STATEMENT_PERIOD_MARKER)?.unwrap_or_default();
+    let partial_import = parse_var(env, ENV_PARTIAL_IMPORT)?.unwrap_or_default();
+    let validate_rules = parse_var(env, ENV_VALIDATE_RULES)?.unwrap_or_default();
+    let track_server_knowledge = parse_var(env, ENV_TRACK_SERVER_KNOWLEDGE)?.unwrap_or_default();
+    let onboarding = parse_var(env, ENV_ONBOARDING)?.unwrap_or_default();
+    let store_imported_ids = parse_var(env, ENV_STORE_IMPORTED_IDS)?.unwrap_or_default();
+    let approve_seen_payees = parse_var(env, ENV_APPROVE_SEEN_PAYEES)?.unwrap_or_default();
+    let budget_currency = optional_var(env, ENV_BUDGET_CURRENCY);
+    let budget_currency_policy = parse_var(env, ENV_BUDGET_CURRENCY_POLICY)?.unwrap_or_default();
+    let preview_file_threshold = parse_var(env, ENV_PREVIEW_FILE_THRESHOLD)?;
+    let download_retries = parse_var(env, ENV_DOWNLOAD_RETRIES)?.unwrap_or_default();
+    let webhook_rate_limit = parse_var(env, ENV_WEBHOOK_RATE_LIMIT)?.unwrap_or_default();
+    let webhook_empty_status: Option<u16> = parse_var(env, ENV_WEBHOOK_EMPTY_STATUS)?;
+    if webhook_empty_status.is_some_and(|status| !(200..=599).contains(&status)) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_WEBHOOK_EMPTY_STATUS} must be an HTTP status between 200 and 599"
+        )));
+    }
+    if let Some(err) = import_id_fields_error(&import_id_fields) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_IMPORT_ID_FIELDS} {err}"
+        )));
+    }
+    if amount_scale == Some(0) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_AMOUNT_SCALE} must be positive"
+        )));
+    }
+    if ynab_max_concurrent_requests == Some(0) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_YNAB_MAX_CONCURRENT_REQUESTS} must be positive"
+        )));
+    }
+    if import_batch_size == Some(0) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_IMPORT_BATCH_SIZE} must be positive"
+        )));
+    }
+    if !min_amount_gbp.is_finite() || min_amount_gbp < 0.0 {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_MIN_AMOUNT_GBP} must be a non-negative amount"
+        )));
+    }
+    if large_transaction_gbp.is_some_and(|amount| !amount.is_finite() || amount <= 0.0) {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_LARGE_TRANSACTION_GBP} must be a positive amount"
+        )));
+    }
+    if tz_offset_minutes.abs() >= TZ_OFFSET_MINUTES_MAX {
+        return Err(worker::Error::RustError(format!(
+            "{ENV_TZ_OFFSET_MINUTES} must be less than {TZ_OFFSET_MINUTES_MAX} minutes from UTC"
+        )));
+    }
 
     Ok(Config {
         ynab_api_key,
@@ -45,5 +1570,154 @@ pub fn init_config(env: &Env) -> worker::Result<Config> {
         ynab_account_id,
         tg_api_key,
         webhook_api_key,
+        default_memo,
+        cleared_status,
+        tz_offset_minutes,
+        amount_scale,
+        amount_max_decimals,
+        ynab_max_concurrent_requests,
+        import_batching,
+        import_batch_size,
+        telegram_allowed_chat_ids,
+        telegram_parse_mode,
+        import_id_version,
+        import_id_fields,
+        import_id_transition_until,
+        import_future_transactions,
+        allowed_currencies,
+        min_amount_gbp,
+        large_transaction_gbp,
+        preserve_whitespace,
+        strict_no_duplicates,
+        import_window_days,
+        generic_descriptions,
+        description_prefixes,
+        description_case,
+        description_case_exceptions,
+        payee_country,
+        credit_payee,
+        single_payee,
+        payment_descriptions,
+        payment_transfer_payee_id,
+        webhook_rate_limit,
+        webhook_empty_status,
+        cors_allowed_origins,
+        source_url_allowed_hosts,
+        summary_webhook_url,
+        category_map,
+        category_group_map,
+        category_description_rules,
+        default_category_id,
+        travel_category_id,
+        travel_account_id,
+        account_routes,
+        scheduled_rules,
+        category_splits,
+        memo_route_hint,
+        memo_fields,
+        memo_separator,
+        memo_row_counter,
+        memo_category_hashtag,
+        memo_upload_id,
+        adjustment_descriptions,
+        adjustment_payee,
+        adjustment_flag_color,
+        category_flag_colors,
+        category_flag_hash,
+        weekday_flag_colors,
+        foreign_flag_color,
+        fx_fee_category_id,
+        fx_reference_rates,
+        flag_precedence,
+        sign_mismatch_policy,
+        footer_row_policy,
+        statement_period_marker,
+        partial_import,
+        validate_rules,
+        track_server_knowledge,
+        onboarding,
+        store_imported_ids,
+        approve_seen_payees,
+        budget_currency,
+        budget_currency_policy,
+        preview_file_threshold,
+        download_retries,
     })
 }
+
+/// Read an optional plain-text variable, treating empty values as unset
+fn optional_var(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|var| var.to_string())
+        .filter(|var| !var.is_empty())
+}
+
+/// Read and parse an optional plain-text variable
+fn parse_var<T>(env: &Env, name: &str) -> worker::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    optional_var(env, name)
+        .map(|var| {
+            var.parse()
+                .map_err(|err| worker::Error::RustError(format!("failed to parse {name}: {err}")))
+        })
+        .transpose()
+}
+
+/// Read and parse an optional JSON variable
+fn parse_json_var<T: DeserializeOwned>(env: &Env, name: &str) -> worker::Result<Option<T>> {
+    optional_var(env, name)
+        .map(|var| {
+            serde_json::from_str(&var).map_err(|err| {
+                worker::Error::RustError(format!("failed to parse {name} as JSON: {err}"))
+            })
+        })
+        .transpose()
+}
+
+/// Read and parse an optional comma-separated list variable
+fn parse_list_var<T>(env: &Env, name: &str) -> worker::Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    optional_var(env, name)
+        .iter()
+        .flat_map(|var| var.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse().map_err(|err| {
+                worker::Error::RustError(format!("failed to parse {name} item {item:?}: {err}"))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn test_redacted_config() {
+        let config = Config {
+            ynab_api_key: "ynab-secret".to_string(),
+            ynab_budget_id: "last-used".to_string(),
+            tg_api_key: Some("telegram-secret".to_string()),
+            webhook_api_key: Some("webhook-secret".to_string()),
+            ..Default::default()
+        };
+
+        let redacted = config.redacted();
+
+        for secret in ["ynab-secret", "telegram-secret", "webhook-secret"] {
+            assert!(!redacted.contains(secret), "secret {secret} is printed");
+        }
+        assert!(redacted.contains("YNAB_API_KEY: set"));
+        assert!(redacted.contains("API_KEY: set"));
+        assert!(redacted.contains("YNAB_BUDGET_ID: last-used"));
+    }
+}
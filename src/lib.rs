@@ -1,20 +1,54 @@
-use std::{fmt::Display, io::Cursor, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    future::Future,
+    io::{Cursor, Read},
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use eyre::{Context, OptionExt};
-use futures::TryFutureExt;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use eyre::{bail, Context, OptionExt};
+use futures::{Stream, StreamExt, TryFutureExt};
 use reqwest::header::HeaderMap;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use tgbot_worker_rs::{
-    frankenstein::{methods::GetFileParams, AsyncTelegramApi},
+    frankenstein::{
+        methods::{GetFileParams, SendMessageParams},
+        types::Document,
+        AsyncTelegramApi, ParseMode,
+    },
     App, Bot, BotError, Message,
 };
-use worker::{event, Env, Request, Response};
+use uuid::Uuid;
+use worker::{event, kv::KvStore, wasm_bindgen::JsCast, Bucket, Env, Method, Request, Response};
+
+use crate::ynab::types::{
+    NewTransaction, SaveScheduledTransaction, SaveSubTransaction, ScheduledTransactionFrequency,
+    TransactionClearedStatus, TransactionFlagColor,
+};
 
-use crate::ynab::types::{NewTransaction, PostTransactionsWrapper, TransactionClearedStatus};
+mod archive;
+use archive::{Archive, ARCHIVE_BINDING};
 
 mod config;
-use config::{init_config, Config};
+use config::{
+    init_config, Config, CurrencyMismatchPolicy, FlagPrecedence, FooterRowPolicy, ImportBatching,
+    ImportIdField, ImportIdVersion, MemoField, MessageParseMode, SignMismatchPolicy, SplitShare,
+    DEFAULT_AMOUNT_SCALE, DEFAULT_IMPORT_ID_FIELDS, ENV_BUDGET_CURRENCY, ENV_YNAB_ACCOUNT_ID,
+};
+
+mod store;
+use store::{Store, KV_BINDING};
+
+mod ynab_api;
+use ynab_api::{
+    AccountTransaction, BudgetUnavailable, InvalidYnabToken, LimitedYnab, SavedTransactions,
+    ScheduledTransaction, YnabApi,
+};
 
 mod ynab {
     progenitor::generate_api!(spec = "ynab_openapi.yml", derives = [PartialEq]);
@@ -26,27 +60,184 @@ struct YonderTransaction {
     date_time: YonderTransactionDateTime,
     #[serde(rename = "Description")]
     description: String,
-    #[serde(rename = "Amount (GBP)")]
+    #[serde(rename = "Amount (GBP)", deserialize_with = "deserialize_amount")]
     amount_gbp: f64,
-    #[serde(rename = "Amount (in Charged Currency)")]
+    #[serde(
+        rename = "Amount (in Charged Currency)",
+        deserialize_with = "deserialize_amount"
+    )]
     amount_charged: f64,
-    #[serde(rename = "Currency")]
+    /// Missing for some rows of some exports, assumed to be GBP then
+    #[serde(rename = "Currency", default)]
     currency: String,
     #[serde(rename = "Category")]
     category: String,
     #[serde(rename = "Debit or Credit")]
     kind: YonderTransactionKind,
-    #[serde(rename = "Country")]
+    /// Missing for some rows of some exports, assumed to be the UK then
+    #[serde(rename = "Country", default)]
     country: String,
+    /// How the payment was made, e.g. `Contactless`, `Online` or `Chip`, if exported
+    #[serde(rename = "Channel", default)]
+    channel: Option<String>,
+    /// Amount of the card authorization, which may differ from the settled amount, e.g. for tips
+    /// or fuel holds, if exported
+    #[serde(
+        rename = "Pending Amount (GBP)",
+        default,
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    pending_amount_gbp: Option<f64>,
+    /// Final amount of the transaction, if exported
+    #[serde(
+        rename = "Settled Amount (GBP)",
+        default,
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    settled_amount_gbp: Option<f64>,
+    /// GBP amount replaced by the settled one, if they differ by at least a penny
+    #[serde(skip)]
+    unsettled_amount_gbp: Option<f64>,
+    /// Line of the row in the CSV file, for reporting problems with it
+    #[serde(skip)]
+    line: u64,
+}
+
+/// Currency symbols that may prefix CSV amounts, e.g. `£3.00`
+const CURRENCY_SYMBOLS: [char; 4] = ['£', '€', '$', '¥'];
+
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let amount = String::deserialize(deserializer)?;
+    parse_amount(&amount).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize an optional amount, empty if the column is missing or the field is blank
+fn deserialize_optional_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let amount = Option::<String>::deserialize(deserializer)?;
+    amount
+        .filter(|amount| !amount.trim().is_empty())
+        .map(|amount| parse_amount(&amount).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Parse the amount, stripping a currency symbol after the sign, e.g. `-£3.00`
+fn parse_amount(amount: &str) -> Result<f64, String> {
+    let trimmed = amount.trim();
+    let (sign, unsigned) = match trimmed.strip_prefix('-') {
+        Some(unsigned) => (-1.0, unsigned),
+        None => (1.0, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let unsigned = unsigned.trim_start_matches(CURRENCY_SYMBOLS).trim_start();
+    unsigned
+        .parse::<f64>()
+        .map(|unsigned| sign * unsigned)
+        .map_err(|err| format!("invalid amount {amount:?}: {err}"))
+}
+
+impl YonderTransaction {
+    /// Trim leading and trailing whitespace in text fields
+    fn trim(&mut self) {
+        for field in [
+            &mut self.description,
+            &mut self.currency,
+            &mut self.category,
+            &mut self.country,
+        ] {
+            let trimmed = field.trim();
+            if trimmed.len() != field.len() {
+                *field = trimmed.to_string();
+            }
+        }
+    }
+
+    /// Use the settled amount as the GBP amount if exported, rather than the authorized one
+    ///
+    /// The charged amount is settled at the same rate, so that e.g. a tip isn't taken for an FX
+    /// fee.
+    fn settle(&mut self) {
+        let Some(settled_amount_gbp) = self.settled_amount_gbp else {
+            return;
+        };
+        if (settled_amount_gbp - self.amount_gbp).abs() >= 0.005 {
+            if self.amount_gbp != 0.0 {
+                self.amount_charged *= settled_amount_gbp / self.amount_gbp;
+            }
+            self.unsettled_amount_gbp = Some(self.amount_gbp);
+        }
+        self.amount_gbp = settled_amount_gbp;
+    }
+
+    /// Pending and settled amounts if they differ by at least a penny, the pending one being the
+    /// replaced GBP amount if settling changed it
+    fn pending_difference(&self) -> Option<(f64, f64)> {
+        let pending = self.unsettled_amount_gbp.or(self.pending_amount_gbp)?;
+        let settled = self.settled_amount_gbp?;
+        ((pending - settled).abs() >= 0.005).then_some((pending, settled))
+    }
+
+    /// Whether the transaction was made in the UK, assuming so if the country is unknown
+    fn is_domestic(&self) -> bool {
+        self.country.is_empty()
+            || ["GBR", "GB"]
+                .iter()
+                .any(|uk| self.country.eq_ignore_ascii_case(uk))
+    }
+
+    /// Signed amount in milliunits, converted from CSV units with the scale
+    fn amount(&self, scale: u32) -> i64 {
+        (match self.kind {
+            YonderTransactionKind::Debit => -self.amount_gbp,
+            YonderTransactionKind::Credit => self.amount_gbp,
+        } * f64::from(scale)) as i64
+    }
+
+    /// Currency the transaction was charged in, assuming GBP if it's unknown
+    fn charged_currency(&self) -> &str {
+        if self.currency.is_empty() {
+            "GBP"
+        } else {
+            &self.currency
+        }
+    }
+
+    /// Whether the transaction was charged in a currency other than GBP
+    fn is_foreign(&self) -> bool {
+        !self.charged_currency().eq_ignore_ascii_case("GBP")
+    }
+
+    /// Whether any amount is negative, flipping the sign given by the kind
+    fn has_sign_mismatch(&self) -> bool {
+        self.amount_gbp < 0.0 || self.amount_charged < 0.0
+    }
+
+    /// Whether the GBP amount has more decimal places than the maximum
+    fn exceeds_decimals(&self, max_decimals: u32) -> bool {
+        let scaled = self.amount_gbp * 10f64.powi(max_decimals as i32);
+        // Tolerate floating point representation errors of the parsed amount
+        (scaled - scaled.round()).abs() > 1e-6
+    }
+
+    /// Make amounts absolute, so that only the kind determines the sign
+    fn correct_sign(&mut self) {
+        self.amount_gbp = self.amount_gbp.abs();
+        self.amount_charged = self.amount_charged.abs();
+    }
 }
 
 impl From<YonderTransaction> for NewTransaction {
     fn from(value: YonderTransaction) -> Self {
-        let amount = (match value.kind {
-            YonderTransactionKind::Debit => -value.amount_gbp,
-            YonderTransactionKind::Credit => value.amount_gbp,
-        } * 1000.0) as i64;
-        let date_time = value.date_time.utc();
+        let amount = value.amount(DEFAULT_AMOUNT_SCALE);
+        let date_time = value.date_time.clone().utc();
+        let import_id = import_id(
+            ImportIdVersion::V1,
+            &DEFAULT_IMPORT_ID_FIELDS,
+            &value,
+            amount,
+            date_time,
+            date_time.date_naive(),
+        );
         Self {
             account_id: None,
             amount: Some(amount),
@@ -55,24 +246,572 @@ impl From<YonderTransaction> for NewTransaction {
             cleared: Some(TransactionClearedStatus::Cleared),
             date: Some(date_time.date_naive()),
             flag_color: None,
-            import_id: Some(
-                format!("TG:{}:{}", amount, date_time.timestamp_millis())
-                    .parse()
-                    .unwrap(),
-            ),
+            import_id: Some(import_id.parse().unwrap()),
             memo: None,
             payee_id: None,
-            payee_name: Some(value.description.parse().unwrap()),
+            payee_name: truncate(&value.description, PAYEE_NAME_MAX_LENGTH)
+                .parse()
+                .ok(),
             subtransactions: vec![],
         }
     }
 }
 
+/// YNAB import_id of the transaction in the given format version, made up of the given fields
+///
+/// `date` is the YNAB date of the transaction, whose month prefixes V3 import_ids like the month
+/// of its batch.
+fn import_id(
+    version: ImportIdVersion,
+    fields: &[ImportIdField],
+    transaction: &YonderTransaction,
+    amount: i64,
+    date_time: DateTime<Utc>,
+    date: NaiveDate,
+) -> String {
+    let builder = match version {
+        ImportIdVersion::V1 => ImportIdBuilder::new("TG"),
+        ImportIdVersion::V2 => ImportIdBuilder::new("TG").segment(2),
+        ImportIdVersion::V3 => ImportIdBuilder::new(format!("TG{}", date.format("%Y%m"))),
+    };
+    fields
+        .iter()
+        .fold(builder, |builder, field| match field {
+            ImportIdField::Amount => builder.segment(amount),
+            ImportIdField::Timestamp => builder.segment(date_time.timestamp_millis()),
+            ImportIdField::Date => builder.segment(date_time.format("%Y%m%d")),
+            ImportIdField::Description => {
+                builder.segment(transaction.description.trim().to_lowercase())
+            }
+            ImportIdField::Category => builder.segment(transaction.category.trim().to_lowercase()),
+            ImportIdField::Currency => builder.segment(transaction.charged_currency()),
+        })
+        .build()
+}
+
+/// Maximum length of the YNAB import_id
+const IMPORT_ID_MAX_LENGTH: usize = 36;
+
+/// Builder of colon-separated YNAB import_ids, enforcing the length limit
+#[derive(Debug, Clone)]
+struct ImportIdBuilder {
+    prefix: String,
+    segments: Vec<String>,
+}
+
+impl ImportIdBuilder {
+    fn new(prefix: impl Display) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            segments: Vec::new(),
+        }
+    }
+
+    fn segment(mut self, segment: impl Display) -> Self {
+        self.segments.push(segment.to_string());
+        self
+    }
+
+    /// Build the import_id, replacing the segments with their hash if it's too long
+    ///
+    /// The hash is deterministic, so the same segments always produce the same import_id.
+    fn build(self) -> String {
+        let import_id = std::iter::once(self.prefix.as_str())
+            .chain(self.segments.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(":");
+        if import_id.len() <= IMPORT_ID_MAX_LENGTH {
+            return import_id;
+        }
+
+        let hashed = format!("{}:h:{:016x}", self.prefix, fnv1a(import_id.as_bytes()));
+        truncate(&hashed, IMPORT_ID_MAX_LENGTH)
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across builds unlike [`std::hash::DefaultHasher`]
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Maximum length of the YNAB transaction memo
+const MEMO_MAX_LENGTH: usize = 500;
+/// Maximum length of the YNAB payee name
+const PAYEE_NAME_MAX_LENGTH: usize = 200;
+
+/// State shared by all transactions of a single import
+struct ImportContext {
+    /// Time the import started at
+    now: DateTime<Utc>,
+    /// Short ID shared by all transactions of the upload for tracing, e.g. `1a2b3c4d`
+    upload_id: String,
+    /// YNAB category IDs resolved for the mapped category groups, by lowercase group name
+    group_category_ids: BTreeMap<String, Uuid>,
+}
+
+impl ImportContext {
+    /// Context of a new import with a generated upload ID
+    fn new(now: DateTime<Utc>) -> Self {
+        let mut upload_id = Uuid::new_v4().simple().to_string();
+        upload_id.truncate(8);
+        Self {
+            now,
+            upload_id,
+            group_category_ids: BTreeMap::new(),
+        }
+    }
+}
+
+/// Resolve the category groups of [`Config::category_group_map`] to their first visible category
+///
+/// Fails if a group doesn't exist in the budget or has no visible categories, rather than silently
+/// importing its transactions uncategorized.
+async fn resolve_category_groups(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+) -> eyre::Result<BTreeMap<String, Uuid>> {
+    if config.category_group_map.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let categories = ynab_client.categories(&config.ynab_budget_id).await?;
+    let mut group_category_ids = BTreeMap::new();
+    for name in config.category_group_map.values() {
+        let group = name.trim().to_lowercase();
+        if group_category_ids.contains_key(&group) {
+            continue;
+        }
+        let in_group = categories
+            .iter()
+            .filter(|category| category.group.trim().to_lowercase() == group)
+            .collect::<Vec<_>>();
+        if in_group.is_empty() {
+            bail!("YNAB category group {name:?} not found");
+        }
+        let category = in_group
+            .into_iter()
+            .find(|category| !category.hidden)
+            .ok_or_else(|| eyre::eyre!("YNAB category group {name:?} has no visible categories"))?;
+        group_category_ids.insert(group, category.id);
+    }
+    Ok(group_category_ids)
+}
+
+/// Map Yonder transaction to YNAB format according to the config
+fn map_transaction(
+    transaction: YonderTransaction,
+    config: &Config,
+    context: &ImportContext,
+) -> NewTransaction {
+    let route = account_route(&transaction, config);
+    let memo = build_memo(&transaction, config, context, route.map(|(name, _)| name));
+    let mut payee_name = truncate(&payee_name(&transaction, config), PAYEE_NAME_MAX_LENGTH);
+    if config.payee_country
+        && config.single_payee.is_none()
+        && !transaction.is_domestic()
+        && !config.is_adjustment(&transaction.description)
+    {
+        payee_name = truncate_with_suffix(
+            &payee_name,
+            &format!("({})", transaction.country),
+            PAYEE_NAME_MAX_LENGTH,
+        );
+    }
+    let flag_color = flag_color(&transaction, config);
+    let category_id = category_id(&transaction, config, context);
+    let amount = transaction.amount(config.amount_scale());
+    let mut subtransactions = fx_fee_split(&transaction, config, amount, category_id);
+    if subtransactions.is_empty() {
+        subtransactions = category_split(&transaction, config, amount, category_id);
+    }
+    let utc = transaction.date_time.clone().utc();
+    let date_time = utc + chrono::Duration::minutes(config.tz_offset_minutes.into());
+
+    // Keep the previous import_id format for already imported transactions during migration
+    let (import_id_version, import_id_fields) = match config.import_id_transition_until {
+        Some(until) if utc.date_naive() < until => {
+            (ImportIdVersion::V1, DEFAULT_IMPORT_ID_FIELDS.as_slice())
+        }
+        _ => (config.import_id_version, config.import_id_fields()),
+    };
+    let import_id = import_id(
+        import_id_version,
+        import_id_fields,
+        &transaction,
+        amount,
+        utc,
+        date_time.date_naive(),
+    );
+
+    // Card repayments are transfers from another account, unlike cashback and refunds
+    let transfer_payee_id = config
+        .payment_transfer_payee_id(&transaction.description)
+        .filter(|_| transaction.kind == YonderTransactionKind::Credit);
+
+    let mut ynab_transaction = NewTransaction::from(transaction);
+    ynab_transaction.account_id = route
+        .map(|(_, account_id)| account_id)
+        .or(config.ynab_account_id);
+    ynab_transaction.date = Some(date_time.date_naive());
+    ynab_transaction.amount = Some(amount);
+    ynab_transaction.import_id = Some(import_id.parse().unwrap());
+    ynab_transaction.payee_name = payee_name.parse().ok();
+    ynab_transaction.memo = memo.and_then(|memo| truncate(&memo, MEMO_MAX_LENGTH).parse().ok());
+    ynab_transaction.cleared = Some(config.cleared_status());
+    ynab_transaction.flag_color = flag_color;
+    // Split transactions are categorized by their subtransactions
+    ynab_transaction.category_id = category_id.filter(|_| subtransactions.is_empty());
+    ynab_transaction.subtransactions = subtransactions;
+    if let Some(transfer_payee_id) = transfer_payee_id {
+        ynab_transaction.payee_id = Some(transfer_payee_id);
+        ynab_transaction.payee_name = None;
+        ynab_transaction.category_id = None;
+        ynab_transaction.subtransactions = Vec::new();
+    }
+    ynab_transaction
+}
+
+/// Split the implied FX fee of a foreign currency transaction into a subtransaction
+///
+/// The fee is the GBP amount exceeding the charged amount converted at the reference rate.
+/// Returns no subtransactions if the fee category or the rate isn't configured, or there's no fee.
+fn fx_fee_split(
+    transaction: &YonderTransaction,
+    config: &Config,
+    amount: i64,
+    category_id: Option<Uuid>,
+) -> Vec<SaveSubTransaction> {
+    let Some(fee_category_id) = config.fx_fee_category_id else {
+        return Vec::new();
+    };
+    let Some(rate) = config
+        .fx_reference_rate(transaction.charged_currency())
+        .filter(|_| transaction.is_foreign())
+    else {
+        return Vec::new();
+    };
+
+    let converted =
+        (transaction.amount_charged.abs() * rate * f64::from(config.amount_scale())).round() as i64;
+    let converted = converted * amount.signum();
+    let fee = amount - converted;
+    // Only costs are fees, a better rate than the reference one isn't split
+    if fee == 0 || fee.signum() != amount.signum() {
+        return Vec::new();
+    }
+
+    vec![
+        SaveSubTransaction {
+            amount: converted,
+            category_id,
+            memo: None,
+            payee_id: None,
+            payee_name: None,
+        },
+        SaveSubTransaction {
+            amount: fee,
+            category_id: Some(fee_category_id),
+            memo: Some("FX fee".parse().unwrap()),
+            payee_id: None,
+            payee_name: None,
+        },
+    ]
+}
+
+/// Subtransactions splitting part of the transaction into the category of the matching split rule
+///
+/// The split part is rounded to milliunits and the rest keeps the transaction's category, so the
+/// subtransactions always sum to the amount.
+fn category_split(
+    transaction: &YonderTransaction,
+    config: &Config,
+    amount: i64,
+    category_id: Option<Uuid>,
+) -> Vec<SaveSubTransaction> {
+    let Some(split) = config.category_split(&transaction.description) else {
+        return Vec::new();
+    };
+
+    let part = match split.share {
+        SplitShare::Percent(percent) => {
+            (amount as f64 * percent.clamp(0.0, 100.0) / 100.0).round() as i64
+        }
+        SplitShare::Amount(split_amount) => {
+            let split_amount =
+                (split_amount.abs() * f64::from(config.amount_scale())).round() as i64;
+            split_amount.min(amount.abs()) * amount.signum()
+        }
+    };
+    if part == 0 || part == amount {
+        return Vec::new();
+    }
+
+    vec![
+        SaveSubTransaction {
+            amount: amount - part,
+            category_id,
+            memo: None,
+            payee_id: None,
+            payee_name: None,
+        },
+        SaveSubTransaction {
+            amount: part,
+            category_id: Some(split.category_id),
+            memo: None,
+            payee_id: None,
+            payee_name: None,
+        },
+    ]
+}
+
+/// YNAB category ID for the transaction
+///
+/// Description rules take precedence over the Yonder category map, then transactions outside the UK
+/// get the travel category, falling back to the default category.
+fn category_id(
+    transaction: &YonderTransaction,
+    config: &Config,
+    context: &ImportContext,
+) -> Option<Uuid> {
+    config
+        .description_category_id(&transaction.description)
+        .or_else(|| config.mapped_category_id(&transaction.category))
+        .or_else(|| {
+            config
+                .mapped_category_group(&transaction.category)
+                .and_then(|group| {
+                    context
+                        .group_category_ids
+                        .get(&group.trim().to_lowercase())
+                        .copied()
+                })
+        })
+        .or(config
+            .travel_category_id
+            .filter(|_| !transaction.is_domestic()))
+        .or(config.default_category_id)
+}
+
+/// YNAB flag color for the transaction
+fn flag_color(transaction: &YonderTransaction, config: &Config) -> Option<TransactionFlagColor> {
+    if config.is_adjustment(&transaction.description) {
+        return config.adjustment_flag_color.clone();
+    }
+
+    let category = config
+        .category_flag_color(&transaction.category)
+        .cloned()
+        .or_else(|| {
+            config
+                .category_flag_hash
+                .then(|| hashed_flag_color(&transaction.category))
+                .flatten()
+        });
+    let foreign = config
+        .foreign_flag_color
+        .clone()
+        .filter(|_| transaction.is_foreign());
+    let flag_color = match config.flag_precedence {
+        FlagPrecedence::Category => category.or(foreign),
+        FlagPrecedence::Foreign => foreign.or(category),
+    };
+
+    // Weekday flags are the fallback, so that they don't hide category and foreign currency flags
+    flag_color.or_else(|| {
+        let date_time = transaction.date_time.clone().utc()
+            + chrono::Duration::minutes(config.tz_offset_minutes.into());
+        config.weekday_flag_color(date_time.weekday()).cloned()
+    })
+}
+
+/// YNAB flag colors assigned to categories by hashing their names
+const HASHED_FLAG_COLORS: [&str; 6] = ["red", "orange", "yellow", "green", "blue", "purple"];
+
+/// Flag color derived from the category name, the same for the category across imports
+fn hashed_flag_color(category: &str) -> Option<TransactionFlagColor> {
+    let category = category.trim().to_lowercase();
+    if category.is_empty() {
+        return None;
+    }
+
+    let hash = fnv1a(category.as_bytes());
+    HASHED_FLAG_COLORS[(hash % HASHED_FLAG_COLORS.len() as u64) as usize]
+        .parse()
+        .ok()
+}
+
+/// Name of the route selecting a YNAB account other than the default one, and the account ID
+fn account_route<'a>(
+    transaction: &YonderTransaction,
+    config: &'a Config,
+) -> Option<(&'a str, Uuid)> {
+    // Foreign currency spending is tracked in the travel account regardless of other routes
+    if let Some(travel_account_id) = config
+        .travel_account_id
+        .filter(|_| transaction.is_foreign())
+    {
+        return Some(("travel", travel_account_id));
+    }
+
+    config
+        .account_route(&transaction.description, &transaction.category)
+        .map(|route| (route.name.as_str(), route.account_id))
+}
+
+/// YNAB payee name for the transaction
+fn payee_name(transaction: &YonderTransaction, config: &Config) -> String {
+    if let Some(single_payee) = &config.single_payee {
+        return single_payee.clone();
+    }
+
+    if config.is_adjustment(&transaction.description) {
+        return config.adjustment_payee.clone();
+    }
+
+    if let Some(credit_payee) = config
+        .credit_payee
+        .as_ref()
+        .filter(|_| transaction.kind == YonderTransactionKind::Credit)
+    {
+        return credit_payee.clone();
+    }
+
+    // Generic descriptions don't identify the merchant, the category is more useful
+    if config.is_generic_description(&transaction.description) && !transaction.category.is_empty() {
+        return transaction.category.clone();
+    }
+
+    config.convert_description_case(config.strip_description_prefix(&transaction.description))
+}
+
+/// Build the YNAB memo for the transaction from the configured parts
+fn build_memo(
+    transaction: &YonderTransaction,
+    config: &Config,
+    context: &ImportContext,
+    route: Option<&str>,
+) -> Option<String> {
+    // The merchant is kept in the memo when all transactions share a payee
+    let mut parts: Vec<String> = config
+        .single_payee
+        .as_ref()
+        .map(|_| {
+            config
+                .convert_description_case(config.strip_description_prefix(&transaction.description))
+        })
+        .into_iter()
+        .chain(
+            config
+                .default_memo
+                .iter()
+                .map(|memo| memo.replace("{date}", &context.now.date_naive().to_string())),
+        )
+        .collect();
+    let mut fields = config
+        .memo_fields
+        .iter()
+        .map(|field| memo_field(transaction, *field))
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>();
+    // The imported amount differs from the CSV one, so the memo always explains it
+    if transaction.unsettled_amount_gbp.is_some()
+        && !config.memo_fields.contains(&MemoField::PendingAmount)
+    {
+        fields.push(memo_field(transaction, MemoField::PendingAmount));
+    }
+    if !fields.is_empty() {
+        parts.push(fields.join(config.memo_separator()));
+    }
+    if let Some(route) = route.filter(|_| config.memo_route_hint) {
+        parts.push(format!("routed: {route}"));
+    }
+    let memo = (!parts.is_empty()).then(|| parts.join(" "));
+
+    // The hashtag is kept whole for searching, truncating the rest of the memo instead
+    match category_hashtag(&transaction.category).filter(|_| config.memo_category_hashtag) {
+        Some(hashtag) => Some(match memo {
+            Some(memo) => truncate_with_suffix(&memo, &hashtag, MEMO_MAX_LENGTH),
+            None => hashtag,
+        }),
+        None => memo,
+    }
+}
+
+/// Value of the Yonder transaction field for the memo
+fn memo_field(transaction: &YonderTransaction, field: MemoField) -> String {
+    match field {
+        MemoField::Description => transaction.description.clone(),
+        MemoField::Category => transaction.category.clone(),
+        MemoField::Country => transaction.country.clone(),
+        MemoField::Currency => transaction.currency.clone(),
+        MemoField::ChargedAmount => {
+            format!(
+                "{:.2} {}",
+                transaction.amount_charged,
+                transaction.charged_currency()
+            )
+        }
+        MemoField::Kind => match transaction.kind {
+            YonderTransactionKind::Debit => "Debit".to_string(),
+            YonderTransactionKind::Credit => "Credit".to_string(),
+        },
+        MemoField::Channel => transaction
+            .channel
+            .as_deref()
+            .map(|channel| channel.trim().to_string())
+            .unwrap_or_default(),
+        MemoField::PendingAmount => transaction
+            .pending_difference()
+            .map(|(pending, settled)| {
+                format!("settled {settled:.2} GBP (pending {pending:.2} GBP)")
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Hashtag of the Yonder category for searching memos, e.g. `#eating_out` for `Eating Out`
+fn category_hashtag(category: &str) -> Option<String> {
+    let tag = category
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("_");
+    (!tag.is_empty()).then(|| format!("#{tag}"))
+}
+
+/// Append text to the memo, truncating the existing memo to keep the text within the length limit
+fn append_memo(transaction: &mut NewTransaction, text: &str) {
+    let memo = match transaction.memo.as_ref() {
+        Some(memo) => truncate_with_suffix(memo, text, MEMO_MAX_LENGTH),
+        None => truncate(text, MEMO_MAX_LENGTH),
+    };
+    transaction.memo = Some(memo.parse().unwrap());
+}
+
+/// Join the value and the suffix with a space, truncating the value to fit the maximum length
+fn truncate_with_suffix(value: &str, suffix: &str, max_length: usize) -> String {
+    let value_length = max_length.saturating_sub(suffix.chars().count() + 1);
+    truncate(
+        &format!("{} {suffix}", truncate(value, value_length)),
+        max_length,
+    )
+}
+
+/// Truncate the string to at most `max_length` characters
+fn truncate(value: &str, max_length: usize) -> String {
+    value.chars().take(max_length).collect()
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
 enum YonderTransactionDateTime {
     Naive(NaiveDateTime),
     Utc(DateTime<Utc>),
+    /// Date without the time component, assumed to be at midnight
+    Date(NaiveDate),
 }
 
 impl YonderTransactionDateTime {
@@ -80,6 +819,7 @@ impl YonderTransactionDateTime {
         match self {
             YonderTransactionDateTime::Naive(naive_date_time) => naive_date_time.and_utc(),
             YonderTransactionDateTime::Utc(date_time) => date_time,
+            YonderTransactionDateTime::Date(date) => date.and_time(NaiveTime::MIN).and_utc(),
         }
     }
 }
@@ -92,10 +832,119 @@ enum YonderTransactionKind {
     Credit,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 struct DocumentResult {
     imported: usize,
+    /// IDs of imported transactions
+    #[serde(skip)]
+    transaction_ids: Vec<String>,
     duplicates: usize,
+    /// import_ids YNAB reported as already imported
+    #[serde(skip)]
+    duplicate_import_ids: Vec<String>,
+    skipped: usize,
+    /// Recurring transactions created as scheduled transactions
+    scheduled: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<BalanceCheck>,
+    /// Sum of imported transaction amounts in milliunits
+    net: i64,
+    /// Problems with the CSV that didn't fail the import
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// Failures after YNAB saved some of the transactions, failing the import without discarding
+    /// its result
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+    /// Transactions above `LARGE_TRANSACTION_GBP`, e.g. `row 2: "Rent" -£1500.00`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    large_transactions: Vec<String>,
+    /// ID appended to the memos of the upload's transactions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_id: Option<String>,
+    /// Knowledge of the YNAB server after the import, if any transactions were sent
+    #[serde(skip)]
+    server_knowledge: Option<i64>,
+    /// Payees of the transactions sent to YNAB
+    #[serde(skip)]
+    payees: Vec<String>,
+}
+
+/// Account balance after the import compared to the expected one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+struct BalanceCheck {
+    /// Expected balance in milliunits
+    expected: i64,
+    /// Actual balance in milliunits
+    actual: i64,
+}
+
+impl Display for BalanceCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.expected == self.actual {
+            write!(f, "Account balance matches: {}", format_gbp(self.actual))
+        } else {
+            write!(
+                f,
+                "WARNING: account balance {} differs from expected {} by {}",
+                format_gbp(self.actual),
+                format_gbp(self.expected),
+                format_gbp(self.actual - self.expected)
+            )
+        }
+    }
+}
+
+/// Format milliunits amount as GBP, e.g. `-£123.45`
+fn format_gbp(milliunits: i64) -> String {
+    let sign = if milliunits < 0 { "-" } else { "" };
+    let pence = (milliunits.unsigned_abs() + 5) / 10;
+    format!("{sign}£{}.{:02}", pence / 100, pence % 100)
+}
+
+/// GBP amount in milliunits, e.g. to compare a configured threshold with scaled amounts
+fn gbp_milliunits(amount_gbp: f64) -> i64 {
+    (amount_gbp * 1000.0).round() as i64
+}
+
+impl DocumentResult {
+    /// Response headers exposing the import outcome for monitoring
+    fn headers(&self) -> [(&'static str, String); 2] {
+        [
+            ("X-Imported-Count", self.imported.to_string()),
+            ("X-Duplicate-Count", self.duplicates.to_string()),
+        ]
+    }
+
+    /// Add the result of another import, keeping the latest balance check
+    fn add(&mut self, other: &DocumentResult) {
+        self.imported += other.imported;
+        self.transaction_ids
+            .extend(other.transaction_ids.iter().cloned());
+        self.duplicates += other.duplicates;
+        self.duplicate_import_ids
+            .extend(other.duplicate_import_ids.iter().cloned());
+        self.skipped += other.skipped;
+        self.scheduled += other.scheduled;
+        self.net += other.net;
+        self.warnings.extend(other.warnings.iter().cloned());
+        self.errors.extend(other.errors.iter().cloned());
+        self.large_transactions
+            .extend(other.large_transactions.iter().cloned());
+        if other.balance.is_some() {
+            self.balance = other.balance;
+        }
+        if other.upload_id.is_some() {
+            self.upload_id.clone_from(&other.upload_id);
+        }
+        self.server_knowledge = self.server_knowledge.max(other.server_knowledge);
+        self.payees.extend(other.payees.iter().cloned());
+    }
+
+    /// Whether the CSV had no transactions to import, as opposed to only duplicates
+    fn is_empty(&self) -> bool {
+        self.imported == 0 && self.duplicates == 0 && self.scheduled == 0
+    }
 }
 
 impl Display for DocumentResult {
@@ -104,13 +953,47 @@ impl Display for DocumentResult {
             f,
             "Imported new transactions: {}\nSkipped duplicate transactions: {}",
             self.imported, self.duplicates
-        )
+        )?;
+        if self.skipped > 0 {
+            write!(f, "\nSkipped rows: {}", self.skipped)?;
+        }
+        if self.scheduled > 0 {
+            write!(f, "\nScheduled transactions: {}", self.scheduled)?;
+        }
+        write!(f, "\nNet: {}", format_gbp(self.net))?;
+        if let Some(balance) = &self.balance {
+            write!(f, "\n{balance}")?;
+        }
+        if let Some(upload_id) = &self.upload_id {
+            write!(f, "\nUpload ID: {upload_id}")?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWARNING: {warning}")?;
+        }
+        for error in &self.errors {
+            write!(f, "\nERROR: {error}")?;
+        }
+        for transaction in &self.large_transactions {
+            write!(f, "\nLARGE TRANSACTION: {transaction}")?;
+        }
+        Ok(())
     }
 }
 
+/// Mapping rules are validated on the first request of the isolate
+static RULES_VALIDATED: std::sync::Once = std::sync::Once::new();
+
 #[event(fetch)]
 pub async fn fetch(req: Request, env: Env, ctx: worker::Context) -> worker::Result<Response> {
     let config = init_config(&env)?;
+    if config.validate_rules {
+        // The config is the same for all requests of the isolate, so the rules are checked once
+        RULES_VALIDATED.call_once(|| {
+            for warning in validate_rules(SELFTEST_CSV, &config) {
+                worker::console_warn!("Invalid mapping rule: {warning}");
+            }
+        });
+    }
 
     let ynab_client = ynab::Client::new_with_client(
         "https://api.ynab.com/v1",
@@ -125,214 +1008,7780 @@ pub async fn fetch(req: Request, env: Env, ctx: worker::Context) -> worker::Resu
 
     let config = Arc::new(config);
     let ynab_client = Arc::new(ynab_client);
+    // KV is optional, features relying on it are disabled without the binding
+    let store = env.kv(KV_BINDING).ok();
+    // R2 archive of raw uploads is optional as well
+    let archive = env.bucket(ARCHIVE_BINDING).ok();
 
     if req.path() == "/import" {
         // Handle custom webhook
-        on_webhook_import(req, config, ynab_client).await
+        let cors_headers = cors_headers(&config, req.headers().get("Origin")?.as_deref());
+        let mut response = match req.method() {
+            // CORS preflight
+            Method::Options => Response::empty()?.with_status(204),
+            // Availability check
+            Method::Head => Response::empty()?,
+            _ => {
+                on_webhook_import(
+                    req,
+                    config,
+                    ynab_client,
+                    store.as_ref(),
+                    archive.as_ref(),
+                    Arc::new(ctx),
+                )
+                .await?
+            }
+        };
+        for (name, value) in cors_headers {
+            response.headers_mut().set(name, &value)?;
+        }
+        Ok(response)
+    } else if req.path() == "/version" && req.method() == Method::Get {
+        webhook_json(
+            &serde_json::json!({ "version": VERSION, "build": build_id() }),
+            200,
+        )
+    } else if req.path() == "/recent" && req.method() == Method::Get {
+        on_webhook_recent(req, &config, ynab_client.as_ref()).await
+    } else if req.path() == "/reset-dedup" && req.method() == Method::Post {
+        on_webhook_reset_dedup(req, &config, store.as_ref()).await
+    } else if req.path() == "/selftest" && req.method() == Method::Post {
+        on_webhook_selftest(req, &config, ynab_client.as_ref()).await
     } else {
         // Handle Telegram bot webhook
         let mut app = App::new();
 
         let config_clone = config.clone();
         let ynab_client_clone = ynab_client.clone();
+        let store = Arc::new(store);
+        // R2 buckets are not thread-safe, but the Worker handles the message on a single thread
+        let archive = Rc::new(archive);
+        let execution_context = Arc::new(clone_context(&ctx));
 
         app.on_message(move |bot, msg| {
-            on_telegram_message(config_clone.clone(), ynab_client_clone.clone(), bot, msg)
-                .map_err(|err| BotError::Custom(err.to_string()))
+            on_telegram_message(
+                config_clone.clone(),
+                ynab_client_clone.clone(),
+                store.clone(),
+                archive.clone(),
+                execution_context.clone(),
+                bot,
+                msg,
+            )
+            .map_err(|err| BotError::Custom(err.to_string()))
         });
 
         app.run(req, env, ctx).await
     }
 }
 
+/// Another handle to the execution context, as the Telegram app takes ownership of it
+fn clone_context(ctx: &worker::Context) -> worker::Context {
+    let inner: &worker::js_sys::Object = ctx.as_ref();
+    worker::Context::new(inner.clone().unchecked_into())
+}
+
 /// Handle Telegram message
 async fn on_telegram_message(
     config: Arc<Config>,
     ynab_client: Arc<ynab::Client>,
+    store: Arc<Option<KvStore>>,
+    archive: Rc<Option<Bucket>>,
+    execution_context: Arc<worker::Context>,
     bot: Bot,
     msg: Message,
 ) -> eyre::Result<()> {
+    let chat_id = msg.inner().chat.id;
+    let store = store.as_ref().as_ref();
+    let archive = archive.as_ref().as_ref();
+    let command = msg.inner().text.as_deref().and_then(parse_command);
+    if let Some(reply) = onboarding_message(&config, store, chat_id).await {
+        // `/start` and `/help` already explain the bot
+        if !matches!(command, Some(("start" | "help", _))) {
+            send_reply(&config, &bot, chat_id, &reply).await?;
+        }
+    }
+    match command {
+        Some(("cleared", args)) => {
+            let reply = on_telegram_cleared(&config, store, chat_id, args)
+                .await
+                .unwrap_or_else(|err| format!("Failed to set cleared status:\n\n{err}"));
+            send_reply(&config, &bot, chat_id, &reply).await?;
+            return Ok(());
+        }
+        Some(("reset", args)) => {
+            let reply = on_telegram_reset(store, chat_id, args)
+                .await
+                .unwrap_or_else(|err| format!("Failed to reset chat settings:\n\n{err}"));
+            send_reply(&config, &bot, chat_id, &reply).await?;
+            return Ok(());
+        }
+        Some(("balance", args)) => {
+            let reply = if config.is_chat_allowed(chat_id) {
+                on_telegram_balance(&config, ynab_client.as_ref(), args)
+                    .await
+                    .unwrap_or_else(|err| format!("Failed to get category balance:\n\n{err}"))
+            } else {
+                COMMAND_NOT_ALLOWED_MESSAGE.to_string()
+            };
+            send_reply(&config, &bot, chat_id, &reply).await?;
+            return Ok(());
+        }
+        Some(("categorize", args)) => {
+            let reply = if config.is_chat_allowed(chat_id) {
+                on_telegram_categorize(&config, ynab_client.as_ref(), store, chat_id, args)
+                    .await
+                    .unwrap_or_else(|err| format!("Failed to categorize transactions:\n\n{err}"))
+            } else {
+                COMMAND_NOT_ALLOWED_MESSAGE.to_string()
+            };
+            send_reply(&config, &bot, chat_id, &reply).await?;
+            return Ok(());
+        }
+        Some(("refresh", _)) => {
+            let reply = if config.is_chat_allowed(chat_id) {
+                on_telegram_refresh(&config, ynab_client.as_ref(), store)
+                    .await
+                    .unwrap_or_else(|err| format!("Failed to refresh YNAB account:\n\n{err}"))
+            } else {
+                COMMAND_NOT_ALLOWED_MESSAGE.to_string()
+            };
+            send_reply(&config, &bot, chat_id, &reply).await?;
+            return Ok(());
+        }
+        Some((command, args)) => {
+            if let Some(reply) = on_telegram_command(&config, chat_id, command, args) {
+                send_reply(&config, &bot, chat_id, &reply).await?;
+                return Ok(());
+            }
+        }
+        None => {}
+    }
+
     let Some(document) = msg.inner().document.clone() else {
-        bot.send_message(msg.chat_id(), "Send Yonder CSV export as a document")
+        // CSV can also be pasted as text, e.g. in a code block
+        let Some(csv) = msg.inner().text.as_deref().and_then(inline_csv) else {
+            send_reply(
+                &config,
+                &bot,
+                chat_id,
+                "Send Yonder CSV export as a document",
+            )
             .await?;
+            return Ok(());
+        };
+        archive_upload(
+            archive,
+            &format!("telegram-{chat_id}"),
+            Utc::now(),
+            csv.as_bytes(),
+        )
+        .await;
+
+        let options = ImportOptions {
+            cleared_status: chat_cleared_status(store, chat_id).await,
+            seen_payees: seen_payees(&config, store).await,
+            group_category_ids: cached_category_groups(&config, store).await,
+            execution_context: Some(execution_context),
+            last_server_knowledge: last_server_knowledge(&config, store).await,
+            ..Default::default()
+        };
+        let reply =
+            match import_yonder_csv_to_ynab(csv, &config, ynab_client.as_ref(), &options).await {
+                Ok(result) => {
+                    record_import(&config, store, &result).await;
+                    record_imported_ids(&config, store, chat_id, &result).await;
+                    result.to_string()
+                }
+                Err(err) => format!("Failed to import transactions:\n\n{}", err),
+            };
+        send_reply(&config, &bot, chat_id, &reply).await?;
         return Ok(());
     };
 
-    match on_telegram_document(config, ynab_client, bot.clone(), document.file_id).await {
-        Ok(result) => bot.send_message(msg.chat_id(), &result.to_string()).await?,
-        Err(err) => {
-            bot.send_message(
-                msg.chat_id(),
-                &format!("Failed to import transactions:\n\n{}", err),
+    // Telegram can't serve larger files to bots, so downloading them would fail obscurely
+    if let Some(reply) = oversized_document_message(document.file_size) {
+        send_reply(&config, &bot, chat_id, &reply).await?;
+        return Ok(());
+    }
+
+    // Commands operating on the document are sent as its caption
+    let command = msg.inner().caption.as_deref().and_then(parse_command);
+    match command {
+        Some(("mappreview", _)) => {
+            match on_telegram_map_preview(
+                &config,
+                ynab_client.as_ref(),
+                store,
+                &bot,
+                document.file_id,
+            )
+            .await
+            {
+                Ok(Preview::Inline(preview)) => {
+                    send_html(&bot, chat_id, &format_preview_table(&preview)).await?
+                }
+                Ok(Preview::File(preview)) => {
+                    send_telegram_file(
+                        &config,
+                        &bot,
+                        chat_id,
+                        PREVIEW_FILE_NAME,
+                        preview,
+                        "Preview is too large for a message, see the attached file",
+                    )
+                    .await?
+                }
+                Err(err) => {
+                    send_reply(
+                        &config,
+                        &bot,
+                        chat_id,
+                        &format!("Failed to preview transactions:\n\n{}", err),
+                    )
+                    .await?
+                }
+            }
+        }
+        Some(("importlast", _)) => {
+            let options = ImportOptions {
+                cleared_status: chat_cleared_status(store, chat_id).await,
+                seen_payees: seen_payees(&config, store).await,
+                execution_context: Some(execution_context),
+                last_server_knowledge: last_server_knowledge(&config, store).await,
+                ..Default::default()
+            };
+            let reply = match on_telegram_import_last(
+                &config,
+                ynab_client.as_ref(),
+                archive,
+                &bot,
+                chat_id,
+                document.file_id,
+                &options,
             )
-            .await?
+            .await
+            {
+                Ok((reply, result)) => {
+                    record_import(&config, store, &result).await;
+                    reply
+                }
+                Err(err) => format!("Failed to import the latest transaction:\n\n{err}"),
+            };
+            send_reply(&config, &bot, chat_id, &reply).await?;
         }
+        Some(("reconcile", args)) => {
+            let reply =
+                on_telegram_reconcile(&config, ynab_client.as_ref(), &bot, document.file_id, args)
+                    .await
+                    .unwrap_or_else(|err| format!("Failed to reconcile transactions:\n\n{err}"));
+            send_reply(&config, &bot, chat_id, &reply).await?;
+        }
+        _ => match on_telegram_document(
+            config.clone(),
+            ynab_client,
+            archive,
+            bot.clone(),
+            chat_id,
+            *document,
+            &ImportOptions {
+                cleared_status: chat_cleared_status(store, chat_id).await,
+                seen_payees: seen_payees(&config, store).await,
+                group_category_ids: cached_category_groups(&config, store).await,
+                execution_context: Some(execution_context),
+                last_server_knowledge: last_server_knowledge(&config, store).await,
+                ..Default::default()
+            },
+        )
+        .await
+        {
+            Ok(result) => {
+                record_import(&config, store, &result).await;
+                record_imported_ids(&config, store, chat_id, &result).await;
+                send_reply(&config, &bot, chat_id, &result.to_string()).await?
+            }
+            Err(err) => {
+                send_reply(
+                    &config,
+                    &bot,
+                    chat_id,
+                    &format!("Failed to import transactions:\n\n{}", err),
+                )
+                .await?
+            }
+        },
     }
 
     Ok(())
 }
 
-/// Handle Telegram document
-async fn on_telegram_document(
-    config: Arc<Config>,
-    ynab_client: Arc<ynab::Client>,
-    bot: Bot,
-    file_id: String,
-) -> eyre::Result<DocumentResult> {
-    let tg_api_key = config
-        .tg_api_key
-        .as_deref()
-        .ok_or_eyre("Telegram API key is not set")?;
+/// Maximum length of a Telegram message
+const TELEGRAM_MESSAGE_MAX_LENGTH: usize = 4096;
 
-    // Download file from Telegram
-    let file = bot.inner().get_file(&GetFileParams { file_id }).await?;
-    let file_path = file.result.file_path.ok_or_eyre("no file path found")?;
-    let file_response = bot
-        .inner()
-        .client
-        .get(format!(
-            "https://api.telegram.org/file/bot{tg_api_key}/{file_path}",
-        ))
-        .send()
+/// Send Telegram reply with the configured parse mode, split into several messages if it's too long
+async fn send_reply(config: &Config, bot: &Bot, chat_id: i64, text: &str) -> eyre::Result<()> {
+    // Telegram limits the length after parsing entities, so the plain text is split before escaping
+    for chunk in split_message(text, TELEGRAM_MESSAGE_MAX_LENGTH) {
+        let (chunk, parse_mode) = escape_reply(chunk, config.telegram_parse_mode);
+        let mut params = SendMessageParams::builder()
+            .chat_id(chat_id)
+            .text(chunk)
+            .build();
+        params.parse_mode = parse_mode;
+        bot.inner().send_message(&params).await?;
+    }
+    Ok(())
+}
+
+/// Escape the plain text reply for the parse mode, so that it's sent as is
+fn escape_reply(text: String, parse_mode: MessageParseMode) -> (String, Option<ParseMode>) {
+    match parse_mode {
+        MessageParseMode::Plain => (text, None),
+        MessageParseMode::Html => (escape_html(&text), Some(ParseMode::Html)),
+        MessageParseMode::MarkdownV2 => (escape_markdown_v2(&text), Some(ParseMode::MarkdownV2)),
+    }
+}
+
+/// Characters reserved by Telegram MarkdownV2
+const MARKDOWN_V2_RESERVED: &str = "\\_*[]()~`>#+-=|{}.!";
+
+/// Escape text for Telegram MarkdownV2
+fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        if MARKDOWN_V2_RESERVED.contains(char) {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// Split the text into chunks of at most `max_length` characters, preferring line boundaries
+fn split_message(text: &str, max_length: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_length = 0;
+    for line in text.split_inclusive('\n') {
+        let mut line = line;
+        let mut line_length = line.chars().count();
+        if chunk_length + line_length > max_length && chunk_length > 0 {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_length = 0;
+        }
+        // Lines that don't fit into a message on their own are split at the limit
+        while line_length > max_length {
+            let (index, _) = line.char_indices().nth(max_length).unwrap_or_default();
+            chunks.push(line[..index].to_string());
+            line = &line[index..];
+            line_length -= max_length;
+        }
+        chunk.push_str(line);
+        chunk_length += line_length;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Send HTML-formatted Telegram message
+async fn send_html(bot: &Bot, chat_id: i64, html: &str) -> eyre::Result<()> {
+    bot.inner()
+        .send_message(
+            &SendMessageParams::builder()
+                .chat_id(chat_id)
+                .text(html)
+                .parse_mode(ParseMode::Html)
+                .build(),
+        )
         .await?;
+    Ok(())
+}
 
-    let csv_bytes = file_response.bytes().await?;
-    import_yonder_csv_to_ynab(csv_bytes, &config, &ynab_client).await
+/// Split Telegram bot command text into the command name and its arguments
+///
+/// `/debug@my_bot args` becomes `("debug", "args")`
+fn parse_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim().strip_prefix('/')?;
+    let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let command = command
+        .split_once('@')
+        .map_or(command, |(command, _)| command);
+    Some((command, args.trim()))
 }
 
-/// Handle CSV import via HTTP webhook
-async fn on_webhook_import(
-    mut req: Request,
-    config: Arc<Config>,
-    ynab_client: Arc<ynab::Client>,
-) -> worker::Result<Response> {
-    let api_key = req
-        .url()?
-        .query_pairs()
-        .find_map(|(k, v)| (k == "api_key").then(|| v.into_owned()));
+/// Version of the crate
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-    let Some(webhook_api_key) = config.webhook_api_key.as_deref() else {
-        return Response::error("Webhook API key is not set", 401);
+/// Identifier of the build, the git SHA if `GIT_SHA` was set when building
+fn build_id() -> Option<&'static str> {
+    option_env!("GIT_SHA").filter(|sha| !sha.is_empty())
+}
+
+/// Version of the deployed build, e.g. `0.1.0 (1a2b3c4)`
+fn version() -> String {
+    format!("{VERSION} ({})", build_id().unwrap_or("unknown build"))
+}
+
+/// Onboarding message for `/start` and `/help` commands
+const HELP_MESSAGE: &str = "Import Yonder transactions into YNAB.
+
+To export transactions from Yonder:
+1. Open Yonder app
+2. Tap the current month balance at the top
+3. Breakdown
+4. Download CSV
+5. Share the file to this chat
+
+You can also paste the CSV contents as a message.
+
+Commands:
+/mappreview - send as the caption of a CSV document to preview mapped transactions
+/cleared [STATUS] - set the cleared status of future imports: cleared, uncleared, reconciled or default
+/reset - reset the settings of this chat, like the cleared status, to the defaults
+/reconcile [FROM TO] - send as the caption of a CSV document to compare its total with YNAB
+/importlast - send as the caption of a CSV document to import only its latest transaction
+/refresh - re-resolve the configured YNAB account and cache the mapped category groups
+/balance CATEGORY - show the budgeted, activity and balance of a YNAB category this month
+/categorize CATEGORY - set the YNAB category of the transactions last imported in this chat
+/rules - show the rules mapping transactions
+/version - show the deployed version
+/help - show this message";
+
+/// Extract CSV pasted as message text, optionally wrapped in a code block
+fn inline_csv(text: &str) -> Option<&str> {
+    let text = text.trim();
+    let csv = match text.strip_prefix("```") {
+        Some(code) => {
+            let code = code.strip_suffix("```")?;
+            // Skip the optional language of the code block
+            code.strip_prefix("csv").unwrap_or(code).trim()
+        }
+        None => text,
+    };
+
+    csv.lines()
+        .next()
+        .is_some_and(|header| header.contains("Date/Time of transaction"))
+        .then_some(csv)
+}
+
+/// Reply to privileged commands in chats that are not allowed to use them
+const COMMAND_NOT_ALLOWED_MESSAGE: &str = "This command is not allowed in this chat";
+
+/// KV key of the YNAB category IDs resolved for the mapped category groups
+const CATEGORY_GROUPS_KEY: &str = "category_groups";
+
+/// YNAB category IDs of the mapped category groups cached by `/refresh`, if they cover all groups
+async fn cached_category_groups(
+    config: &Config,
+    store: Option<&impl Store>,
+) -> Option<BTreeMap<String, Uuid>> {
+    let store = store.filter(|_| !config.category_group_map.is_empty())?;
+    let cached = match store.get(CATEGORY_GROUPS_KEY).await {
+        Ok(cached) => cached?,
+        Err(err) => {
+            // Resolve the groups from YNAB rather than failing the import
+            worker::console_warn!("Failed to read cached category groups: {err}");
+            return None;
+        }
+    };
+    let group_category_ids: BTreeMap<String, Uuid> = serde_json::from_str(&cached).ok()?;
+    config
+        .category_group_map
+        .values()
+        .all(|group| group_category_ids.contains_key(&group.trim().to_lowercase()))
+        .then_some(group_category_ids)
+}
+
+/// Map the CSV outside of an import, e.g. for a preview, with the category groups cached by
+/// `/refresh` or resolved from YNAB like the import does
+async fn map_yonder_csv_with_groups(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    store: Option<&impl Store>,
+) -> eyre::Result<MappedCsv> {
+    let group_category_ids = match cached_category_groups(config, store).await {
+        Some(group_category_ids) => group_category_ids,
+        None => resolve_category_groups(config, ynab_client).await?,
+    };
+    let context = ImportContext {
+        group_category_ids,
+        ..ImportContext::new(Utc::now())
     };
+    map_yonder_csv(yonder_csv, config, &context)
+}
 
-    if api_key.as_deref() != Some(webhook_api_key) {
-        return Response::error("Invalid API key", 401);
+/// Handle `/refresh` command, re-resolving the configured YNAB account and category groups from
+/// the API and updating the cached category IDs
+async fn on_telegram_refresh(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    store: Option<&impl Store>,
+) -> eyre::Result<String> {
+    let mut reply = format!("Budget: {}", config.ynab_budget_id);
+    if let Some(account_id) = config.ynab_account_id {
+        let account = ynab_client
+            .account(&config.ynab_budget_id, account_id)
+            .await?;
+        reply.push_str(&format!(
+            "\nAccount: {} ({})\nBalance: {}",
+            account.name,
+            account_id,
+            format_gbp(account.balance)
+        ));
+        if account.closed {
+            reply.push_str("\nWARNING: account is closed");
+        }
     }
 
-    let csv_bytes = req.bytes().await?;
-    match import_yonder_csv_to_ynab(csv_bytes, &config, &ynab_client).await {
-        Ok(result) => Response::from_json(&serde_json::json!({"message": result.to_string()})),
-        Err(err) => Response::error(err.to_string(), 500),
+    if !config.category_group_map.is_empty() {
+        let group_category_ids = resolve_category_groups(config, ynab_client).await?;
+        // Without KV storage, imports resolve the groups themselves every time
+        if let Some(store) = store {
+            store
+                .put(
+                    CATEGORY_GROUPS_KEY,
+                    &serde_json::to_string(&group_category_ids)?,
+                    None,
+                )
+                .await?;
+        }
+        reply.push_str(&format!(
+            "\nCategory groups: {} resolved",
+            group_category_ids.len()
+        ));
     }
+    Ok(reply)
 }
 
-/// Parse Yonder transacitons in CSV format and import to YNAB
-async fn import_yonder_csv_to_ynab(
-    yonder_csv: impl AsRef<[u8]>,
+/// Handle `/balance` command, showing the amounts of the YNAB category with the given name
+async fn on_telegram_balance(
     config: &Config,
-    ynab_client: &ynab::Client,
-) -> eyre::Result<DocumentResult> {
-    // Parse CSV with Yonder transactions
-    let yonder_transactions: Vec<YonderTransaction> =
-        csv::Reader::from_reader(Cursor::new(yonder_csv))
-            .into_deserialize()
-            .collect::<Result<_, _>>()
-            .wrap_err("failed to deserialize as Yonder transactions CSV")?;
+    ynab_client: &impl YnabApi,
+    args: &str,
+) -> eyre::Result<String> {
+    let name = args.trim();
+    if name.is_empty() {
+        bail!("specify the category name, e.g. /balance Groceries");
+    }
 
-    // Map Yonder transactions to YNAB format
-    let ynab_transactions: Vec<_> = yonder_transactions
+    let category = ynab_client
+        .categories(&config.ynab_budget_id)
+        .await?
         .into_iter()
-        .map(NewTransaction::from)
-        .map(|mut transaction| {
-            transaction.account_id = Some(config.ynab_account_id);
-            transaction
-        })
-        .collect();
+        .find(|category| category.name.trim().eq_ignore_ascii_case(name))
+        .ok_or_else(|| eyre::eyre!("category {name:?} not found"))?;
 
-    // Import transactions to YNAB
-    let ynab_response = ynab_client
-        .create_transaction(
-            &config.ynab_budget_id,
-            &PostTransactionsWrapper {
-                transaction: None,
-                transactions: ynab_transactions,
-            },
+    Ok(format!(
+        "Category: {}\nBudgeted: {}\nActivity: {}\nBalance: {}",
+        category.name,
+        format_gbp(category.budgeted),
+        format_gbp(category.activity),
+        format_gbp(category.balance)
+    ))
+}
+
+/// KV key marking chats that messaged the bot before
+fn contact_key(chat_id: i64) -> String {
+    format!("contact:{chat_id}")
+}
+
+/// Onboarding message for chats messaging the bot for the first time
+const ONBOARDING_MESSAGE: &str = "Welcome! This bot imports Yonder transactions into YNAB.
+
+To export transactions from Yonder, tap the current month balance in the app, then Breakdown and \
+Download CSV, and share the file to this chat.
+
+Importing needs YNAB_API_KEY, YNAB_BUDGET_ID and YNAB_ACCOUNT_ID set in the worker config. \
+Send /rules to check how transactions will be mapped, or /help for all commands.";
+
+/// Onboarding message if the chat messages the bot for the first time, recording the contact
+///
+/// Chats are only onboarded with KV bound, so that they don't get the message on every contact.
+async fn onboarding_message(
+    config: &Config,
+    store: Option<&impl Store>,
+    chat_id: i64,
+) -> Option<String> {
+    let store = store.filter(|_| config.onboarding)?;
+    let key = contact_key(chat_id);
+    match store.get(&key).await {
+        Ok(Some(_)) => return None,
+        Ok(None) => {}
+        Err(err) => {
+            worker::console_warn!("Failed to read chat contact: {err}");
+            return None;
+        }
+    }
+    if let Err(err) = store.put(&key, "1", None).await {
+        // Skip onboarding rather than sending it on every message
+        worker::console_warn!("Failed to record chat contact: {err}");
+        return None;
+    }
+    Some(ONBOARDING_MESSAGE.to_string())
+}
+
+/// KV key of the cleared status of future imports in the chat
+fn cleared_status_key(chat_id: i64) -> String {
+    format!("cleared:{chat_id}")
+}
+
+/// Cleared status of future imports set in the chat with `/cleared`
+async fn chat_cleared_status(
+    store: Option<&impl Store>,
+    chat_id: i64,
+) -> Option<TransactionClearedStatus> {
+    match store?.get(&cleared_status_key(chat_id)).await {
+        Ok(status) => status.and_then(|status| status.parse().ok()),
+        Err(err) => {
+            // Fall back to the configured status rather than failing the import
+            worker::console_warn!("Failed to read cleared status: {err}");
+            None
+        }
+    }
+}
+
+/// KV key of the payees imported before, a JSON array of `seen_payee` entries
+const SEEN_PAYEES_KEY: &str = "seen_payees";
+
+/// Length above which payees are tracked by their hash, keeping the set compact
+const SEEN_PAYEE_MAX_LENGTH: usize = 64;
+
+/// Entry of the payee in the seen payees, case-insensitive
+fn seen_payee(payee: &str) -> String {
+    let payee = payee.to_lowercase();
+    if payee.len() > SEEN_PAYEE_MAX_LENGTH {
+        format!("#{:016x}", fnv1a(payee.as_bytes()))
+    } else {
+        payee
+    }
+}
+
+/// Payees imported before if only their transactions are approved
+async fn seen_payees(config: &Config, store: Option<&impl Store>) -> Option<BTreeSet<String>> {
+    let store = store.filter(|_| config.approve_seen_payees)?;
+    match read_seen_payees(store).await {
+        Ok(payees) => Some(payees),
+        Err(err) => {
+            // Leave all transactions unapproved rather than failing the import
+            worker::console_warn!("Failed to read seen payees: {err}");
+            Some(BTreeSet::new())
+        }
+    }
+}
+
+/// Payees recorded in the store, empty if none were recorded yet
+async fn read_seen_payees(store: &impl Store) -> eyre::Result<BTreeSet<String>> {
+    match store.get(SEEN_PAYEES_KEY).await? {
+        Some(payees) => serde_json::from_str(&payees).wrap_err("invalid seen payees"),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Record payees of the imported transactions for approving their next transactions
+///
+/// Concurrent imports may drop each other's new payees, leaving their next transactions
+/// unapproved for review, which is the safe side.
+async fn record_seen_payees(config: &Config, store: Option<&impl Store>, result: &DocumentResult) {
+    let Some(store) = store.filter(|_| config.approve_seen_payees) else {
+        return;
+    };
+    let record = async {
+        let mut payees = read_seen_payees(store).await?;
+        let count = payees.len();
+        payees.extend(result.payees.iter().map(|payee| seen_payee(payee)));
+        if payees.len() > count {
+            store
+                .put(SEEN_PAYEES_KEY, &serde_json::to_string(&payees)?, None)
+                .await?;
+        }
+        eyre::Ok(())
+    };
+    if let Err(err) = record.await {
+        worker::console_warn!("Failed to record seen payees: {err}");
+    }
+}
+
+/// Record the state after the import that the next imports depend on, whatever the import path
+async fn record_import(config: &Config, store: Option<&impl Store>, result: &DocumentResult) {
+    record_seen_payees(config, store, result).await;
+    record_server_knowledge(config, store, result).await;
+}
+
+/// KV key of the IDs of the transactions last imported from the chat
+fn imported_ids_key(chat_id: i64) -> String {
+    format!("imported:{chat_id}")
+}
+
+/// Store the IDs of the transactions imported from the chat for `/categorize`, replacing the
+/// previous import's
+async fn record_imported_ids(
+    config: &Config,
+    store: Option<&impl Store>,
+    chat_id: i64,
+    result: &DocumentResult,
+) {
+    let Some(store) = store.filter(|_| config.store_imported_ids) else {
+        return;
+    };
+    if result.transaction_ids.is_empty() {
+        return;
+    }
+    if let Err(err) = store
+        .put(
+            &imported_ids_key(chat_id),
+            &result.transaction_ids.join(","),
+            None,
         )
         .await
-        .map_err(|err| eyre::Report::msg(err.to_string()))?;
+    {
+        worker::console_warn!("Failed to record imported transaction IDs: {err}");
+    }
+}
 
-    Ok(DocumentResult {
-        imported: ynab_response.data.transaction_ids.len(),
-        duplicates: ynab_response.data.duplicate_import_ids.len(),
-    })
+/// Handle `/categorize` command, setting the YNAB category with the given name for all transactions
+/// last imported from the chat
+async fn on_telegram_categorize(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    store: Option<&impl Store>,
+    chat_id: i64,
+    args: &str,
+) -> eyre::Result<String> {
+    let store = store.ok_or_eyre("KV storage is not configured")?;
+    let name = args.trim();
+    if name.is_empty() {
+        bail!("specify the category name, e.g. /categorize Groceries");
+    }
+
+    let transaction_ids: Vec<String> = store
+        .get(&imported_ids_key(chat_id))
+        .await?
+        .map(|ids| {
+            ids.split(',')
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if transaction_ids.is_empty() {
+        bail!("no imported transactions to categorize, import a CSV first");
+    }
+
+    let category = ynab_client
+        .categories(&config.ynab_budget_id)
+        .await?
+        .into_iter()
+        .find(|category| category.name.trim().eq_ignore_ascii_case(name))
+        .ok_or_else(|| eyre::eyre!("category {name:?} not found"))?;
+    let updated = ynab_client
+        .categorize_transactions(&config.ynab_budget_id, &transaction_ids, category.id)
+        .await?;
+
+    Ok(format!(
+        "Categorized {updated} transactions as {}",
+        category.name
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        ynab::types::NewTransaction, YonderTransaction, YonderTransactionDateTime,
-        YonderTransactionKind,
+/// Handle `/cleared` command, setting the cleared status of future imports in the chat
+async fn on_telegram_cleared(
+    config: &Config,
+    store: Option<&impl Store>,
+    chat_id: i64,
+    args: &str,
+) -> eyre::Result<String> {
+    let store = store.ok_or_eyre("KV storage is not configured")?;
+    let key = cleared_status_key(chat_id);
+    match args.trim().to_lowercase().as_str() {
+        "" => {}
+        "default" => store.delete(&key).await?,
+        status => {
+            let status: TransactionClearedStatus = status.parse().map_err(|_| {
+                eyre::eyre!(
+                    "unknown cleared status {status:?}, expected cleared, uncleared or reconciled"
+                )
+            })?;
+            store.put(&key, &status.to_string(), None).await?;
+        }
+    }
+
+    let status = chat_cleared_status(Some(store), chat_id)
+        .await
+        .unwrap_or(config.cleared_status());
+    Ok(format!("Cleared status of future imports: {status}"))
+}
+
+/// KV keys of the settings overridden in the chat, with their names
+fn chat_override_keys(chat_id: i64) -> [(&'static str, String); 1] {
+    [("cleared status", cleared_status_key(chat_id))]
+}
+
+/// Handle `/reset` command, listing the settings overridden in the chat and deleting them once
+/// confirmed with `/reset confirm`, so that the chat uses the configured defaults again
+async fn on_telegram_reset(
+    store: Option<&impl Store>,
+    chat_id: i64,
+    args: &str,
+) -> eyre::Result<String> {
+    let store = store.ok_or_eyre("KV storage is not configured")?;
+    let mut overrides = Vec::new();
+    for (name, key) in chat_override_keys(chat_id) {
+        if store.get(&key).await?.is_some() {
+            overrides.push((name, key));
+        }
+    }
+    if overrides.is_empty() {
+        return Ok("This chat uses the default settings, nothing to reset".to_string());
+    }
+
+    let names = overrides
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    match args.trim().to_lowercase().as_str() {
+        "" => Ok(format!(
+            "This resets the settings of this chat to the defaults: {names}\n\nSend /reset confirm to proceed"
+        )),
+        "confirm" => {
+            for (_, key) in &overrides {
+                store.delete(key).await?;
+            }
+            Ok(format!("Reset the settings of this chat to the defaults: {names}"))
+        }
+        args => bail!("unknown argument {args:?}, expected /reset or /reset confirm"),
+    }
+}
+
+/// Handle Telegram bot command, returning the reply if the command is known
+fn on_telegram_command(
+    config: &Config,
+    chat_id: i64,
+    command: &str,
+    _args: &str,
+) -> Option<String> {
+    let reply = match command {
+        "start" | "help" => HELP_MESSAGE.to_string(),
+        "debug" if config.is_chat_allowed(chat_id) => config.redacted(),
+        "debug" => COMMAND_NOT_ALLOWED_MESSAGE.to_string(),
+        "rules" if config.is_chat_allowed(chat_id) => config.rules(),
+        "rules" => COMMAND_NOT_ALLOWED_MESSAGE.to_string(),
+        "version" => format!("Version: {}", version()),
+        _ => return None,
     };
 
-    #[test]
-    fn test_parse_yonder() -> eyre::Result<()> {
-        let yonder_transactions: Vec<YonderTransaction> =
-            csv::Reader::from_reader(std::fs::read("yonder.csv")?.as_slice())
-                .into_deserialize()
-                .collect::<Result<_, _>>()?;
+    Some(reply)
+}
 
-        assert_eq!(
-            yonder_transactions,
-            vec![
-                YonderTransaction {
-                    date_time: YonderTransactionDateTime::Utc(
-                        "2026-01-01T10:34:50.211697Z".parse()?
-                    ),
-                    description: "TFL - Transport for London".to_string(),
-                    amount_gbp: 3.0,
-                    amount_charged: 3.0,
-                    currency: "GBP".to_string(),
-                    category: "Transport".to_string(),
-                    kind: YonderTransactionKind::Debit,
-                    country: "GBR".to_string()
-                },
-                YonderTransaction {
-                    date_time: YonderTransactionDateTime::Naive(
-                        "2026-01-01T10:34:50.211697".parse()?
-                    ),
-                    description: "TFL - Transport for London".to_string(),
-                    amount_gbp: 3.00,
-                    amount_charged: 3.00,
-                    currency: "GBP".to_string(),
-                    category: "Transport".to_string(),
-                    kind: YonderTransactionKind::Debit,
-                    country: "GBR".to_string()
-                }
-            ]
-        );
+/// Download Telegram document contents
+async fn download_telegram_document(
+    config: &Config,
+    bot: &Bot,
+    file_id: String,
+) -> eyre::Result<Vec<u8>> {
+    let tg_api_key = config
+        .tg_api_key
+        .as_deref()
+        .ok_or_eyre("Telegram API key is not set")?;
 
-        assert_eq!(
-            NewTransaction::from(yonder_transactions[0].clone()),
-            NewTransaction::from(yonder_transactions[1].clone())
+    let file = bot.inner().get_file(&GetFileParams { file_id }).await?;
+    let file_path = file.result.file_path.ok_or_eyre("no file path found")?;
+    let file_response = bot
+        .inner()
+        .client
+        .get(format!(
+            "https://api.telegram.org/file/bot{tg_api_key}/{file_path}",
+        ))
+        .send()
+        .await?;
+
+    Ok(file_response.bytes().await?.to_vec())
+}
+
+/// Send the text as a Telegram document with the file name
+async fn send_telegram_file(
+    config: &Config,
+    bot: &Bot,
+    chat_id: i64,
+    file_name: &str,
+    text: String,
+    caption: &str,
+) -> eyre::Result<()> {
+    let tg_api_key = config
+        .tg_api_key
+        .as_deref()
+        .ok_or_eyre("Telegram API key is not set")?;
+
+    let form = reqwest::multipart::Form::new()
+        .text("chat_id", chat_id.to_string())
+        .text("caption", caption.to_string())
+        .part(
+            "document",
+            reqwest::multipart::Part::text(text)
+                .file_name(file_name.to_string())
+                .mime_str("text/plain")?,
         );
+    bot.inner()
+        .client
+        .post(format!(
+            "https://api.telegram.org/bot{tg_api_key}/sendDocument"
+        ))
+        .multipart(form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())?;
+    Ok(())
+}
 
-        Ok(())
+/// Delay before the first retry of a failed Telegram download, doubled after each retry
+const DOWNLOAD_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Maximum retries of a Telegram download, each taking two subrequests of the Workers limit
+const MAX_DOWNLOAD_RETRIES: u32 = 3;
+
+/// Run the operation, retrying failures up to `retries` times with exponential backoff
+async fn retry_with_backoff<T, F, S>(
+    retries: u32,
+    mut operation: impl FnMut() -> F,
+    sleep: impl Fn(Duration) -> S,
+) -> eyre::Result<T>
+where
+    F: Future<Output = eyre::Result<T>>,
+    S: Future<Output = ()>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(_) if attempt < retries => {
+                sleep(DOWNLOAD_RETRY_BASE_DELAY * 2u32.pow(attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err.wrap_err(format!("failed after {} attempts", attempt + 1))),
+        }
     }
+}
 
-    #[test]
-    fn test_ynab_import_id_length() -> eyre::Result<()> {
-        let yonder_transactions: Vec<YonderTransaction> =
-            csv::Reader::from_reader(std::fs::read("yonder.csv")?.as_slice())
-                .into_deserialize()
-                .collect::<Result<_, _>>()?;
+/// Handle `/mappreview` command, previewing mapped transactions of the document
+async fn on_telegram_map_preview(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    store: Option<&impl Store>,
+    bot: &Bot,
+    file_id: String,
+) -> eyre::Result<Preview> {
+    let csv_bytes = download_telegram_document(config, bot, file_id).await?;
+    let mapped = map_yonder_csv_with_groups(csv_bytes, config, ynab_client, store).await?;
+    Ok(preview(&mapped.transactions, config, fits_telegram_message))
+}
 
-        for transaction in yonder_transactions {
-            let import_id = NewTransaction::from(transaction).import_id;
-            assert!(
-                import_id.expect("import_id must be set").len() < 36,
-                "import_id must be no longer than 36 characters"
-            );
-        }
+/// Handle `/importlast` command, importing only the latest transaction of the document
+async fn on_telegram_import_last(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    archive: Option<&Bucket>,
+    bot: &Bot,
+    chat_id: i64,
+    file_id: String,
+    options: &ImportOptions,
+) -> eyre::Result<(String, DocumentResult)> {
+    let csv_bytes = download_telegram_document(config, bot, file_id).await?;
+    archive_upload(
+        archive,
+        &format!("telegram-{chat_id}"),
+        Utc::now(),
+        &csv_bytes,
+    )
+    .await;
+    import_latest_transaction(csv_bytes, config, ynab_client, options).await
+}
+
+/// Import only the latest transaction of the CSV, describing it in the reply
+async fn import_latest_transaction(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> eyre::Result<(String, DocumentResult)> {
+    let options = ImportOptions {
+        latest_only: true,
+        ..options.clone()
+    };
+    let (mapped, context) = prepare_import(yonder_csv, config, ynab_client, &options).await?;
+    let transaction = mapped
+        .transactions
+        .first()
+        .cloned()
+        .ok_or_eyre("no transactions to import")?;
+    let result = import_mapped(mapped, &context, config, ynab_client, &options).await?;
+
+    let reply = format!(
+        "Latest transaction:\n{} {} {}\n\n{result}",
+        transaction
+            .date
+            .map_or("unknown date".to_string(), |date| date.to_string()),
+        transaction
+            .payee_name
+            .as_ref()
+            .map_or("", |payee_name| payee_name.as_str()),
+        format_gbp(transaction.amount.unwrap_or_default())
+    );
+    Ok((reply, result))
+}
+
+/// Handle `/reconcile` command, comparing the document total with YNAB
+async fn on_telegram_reconcile(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    bot: &Bot,
+    file_id: String,
+    args: &str,
+) -> eyre::Result<String> {
+    let range = parse_date_range(args)?;
+    let csv_bytes = download_telegram_document(config, bot, file_id).await?;
+    let reconciliation = reconcile_yonder_csv(csv_bytes, config, ynab_client, range).await?;
+    Ok(reconciliation.to_string())
+}
+
+/// Parse optional inclusive date range in `FROM TO` format
+fn parse_date_range(args: &str) -> eyre::Result<Option<(NaiveDate, NaiveDate)>> {
+    let dates = args
+        .split_whitespace()
+        .map(|date| {
+            date.parse::<NaiveDate>()
+                .wrap_err_with(|| format!("invalid date {date:?}, expected YYYY-MM-DD"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    match dates.as_slice() {
+        [] => Ok(None),
+        [from, to] if from <= to => Ok(Some((*from, *to))),
+        [_, _] => bail!("start of the date range is after its end"),
+        _ => bail!("expected date range as FROM TO"),
+    }
+}
+
+/// Maximum number of transactions in the preview table, keeping it within Telegram message limits
+const PREVIEW_MAX_ROWS: usize = 30;
+/// Width of the payee column in the preview table
+const PREVIEW_PAYEE_WIDTH: usize = 24;
+
+/// Name of the file with the preview too large for a message
+const PREVIEW_FILE_NAME: &str = "preview.txt";
+
+/// Preview of mapped transactions as a plain-text table
+#[derive(Debug, PartialEq)]
+enum Preview {
+    /// Preview small enough to be sent as a message
+    Inline(String),
+    /// Preview of all transactions larger than the configured threshold, sent as a file
+    File(String),
+}
+
+/// Preview mapped transactions, listing all of them in a file if the preview exceeds the
+/// configured threshold or doesn't fit the message according to `fits_message`
+fn preview(
+    transactions: &[NewTransaction],
+    config: &Config,
+    fits_message: impl Fn(&str) -> bool,
+) -> Preview {
+    let all = || format_preview_rows(transactions, transactions.len());
+    let inline = match config.preview_file_threshold {
+        Some(threshold) => {
+            let table = all();
+            if table.chars().count() > threshold {
+                return Preview::File(table);
+            }
+            table
+        }
+        None => format_preview_rows(transactions, PREVIEW_MAX_ROWS),
+    };
+    if fits_message(&inline) {
+        Preview::Inline(inline)
+    } else {
+        Preview::File(all())
+    }
+}
+
+/// Whether the preview fits a Telegram message once formatted, as escaping lengthens it
+fn fits_telegram_message(preview: &str) -> bool {
+    format_preview_table(preview).chars().count() <= TELEGRAM_MESSAGE_MAX_LENGTH
+}
+
+/// Format the plain-text preview table as a monospace HTML table
+fn format_preview_table(preview: &str) -> String {
+    format!("<pre>{}</pre>", escape_html(preview))
+}
+
+/// Format at most `max_rows` mapped transactions as a plain-text table
+fn format_preview_rows(transactions: &[NewTransaction], max_rows: usize) -> String {
+    let mut table = format!(
+        "{:<10}  {:<PREVIEW_PAYEE_WIDTH$}  {:>10}\n",
+        "Date", "Payee", "Amount"
+    );
+    for transaction in transactions.iter().take(max_rows) {
+        table.push_str(&format!(
+            "{:<10}  {:<PREVIEW_PAYEE_WIDTH$}  {:>10}\n",
+            transaction
+                .date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            truncate(
+                transaction
+                    .payee_name
+                    .as_deref()
+                    .map_or("", |payee| payee.as_str()),
+                PREVIEW_PAYEE_WIDTH
+            ),
+            format_gbp(transaction.amount.unwrap_or_default())
+        ));
+    }
+    if transactions.len() > max_rows {
+        table.push_str(&format!("... and {} more\n", transactions.len() - max_rows));
+    }
+
+    table.trim_end().to_string()
+}
+
+/// Escape text for Telegram HTML parse mode
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Handle Telegram document
+async fn on_telegram_document(
+    config: Arc<Config>,
+    ynab_client: Arc<ynab::Client>,
+    archive: Option<&Bucket>,
+    bot: Bot,
+    chat_id: i64,
+    document: Document,
+    options: &ImportOptions,
+) -> eyre::Result<DocumentResult> {
+    let csv_bytes = retry_with_backoff(
+        config.download_retries.min(MAX_DOWNLOAD_RETRIES),
+        || {
+            download_telegram_document(&config, &bot, document.file_id.clone())
+                .inspect_err(|err| worker::console_warn!("Document download failed: {err}"))
+        },
+        worker::Delay::from,
+    )
+    .await?;
+    archive_upload(
+        archive,
+        &format!("telegram-{chat_id}"),
+        Utc::now(),
+        &csv_bytes,
+    )
+    .await;
+    import_document(
+        csv_bytes,
+        document.mime_type.as_deref(),
+        &config,
+        ynab_client.as_ref(),
+        options,
+    )
+    .await
+}
+
+/// Maximum size of a file bots can download from Telegram
+const TELEGRAM_DOWNLOAD_MAX_SIZE: u64 = 20 * 1024 * 1024;
+
+/// Reply to a document too large for bots to download, suggesting the webhook instead
+fn oversized_document_message(file_size: Option<u64>) -> Option<String> {
+    let file_size = file_size.filter(|file_size| *file_size > TELEGRAM_DOWNLOAD_MAX_SIZE)?;
+    Some(format!(
+        "The document is {:.1} MB, but Telegram only lets bots download files up to {} MB.\n\n\
+        Send a shorter export, e.g. zipped or for fewer months, or upload it to the /import webhook",
+        file_size as f64 / (1024.0 * 1024.0),
+        TELEGRAM_DOWNLOAD_MAX_SIZE / (1024 * 1024)
+    ))
+}
+
+/// MIME types of documents expected to be Yonder CSV exports, possibly zipped
+const CSV_MIME_TYPES: [&str; 5] = [
+    "text/csv",
+    "text/comma-separated-values",
+    "application/csv",
+    "application/vnd.ms-excel",
+    "application/zip",
+];
+
+/// Import the document regardless of its MIME type
+///
+/// Telegram often reports CSVs as `application/octet-stream` or `text/plain`, so documents of
+/// other types are still parsed and only rejected if they aren't a Yonder CSV.
+async fn import_document(
+    document: Vec<u8>,
+    mime_type: Option<&str>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> eyre::Result<DocumentResult> {
+    if let Some(mime_type) = mime_type.filter(|mime_type| {
+        !CSV_MIME_TYPES
+            .iter()
+            .any(|csv_mime_type| csv_mime_type.eq_ignore_ascii_case(mime_type))
+    }) {
+        extract_csv(&document)
+            .and_then(transcode_utf16)
+            .and_then(parse_yonder_csv)
+            .wrap_err_with(|| format!("document of type {mime_type} is not a Yonder CSV export"))?;
+    }
+
+    import_yonder_csv_to_ynab(document, config, ynab_client, options).await
+}
+
+/// Handle CSV import via HTTP webhook
+async fn on_webhook_import(
+    mut req: Request,
+    config: Arc<Config>,
+    ynab_client: Arc<ynab::Client>,
+    store: Option<&KvStore>,
+    archive: Option<&Bucket>,
+    execution_context: Arc<worker::Context>,
+) -> worker::Result<Response> {
+    let format = ResponseFormat::negotiate(req.headers().get("Accept")?.as_deref());
+    let url = req.url()?;
+    let query_param = |name: &str| {
+        url.query_pairs()
+            .find_map(|(k, v)| (k == name).then(|| v.into_owned()))
+    };
+    let api_key = query_param("api_key");
+    if let Some(error) = webhook_api_key_error(&config, api_key.as_deref()) {
+        return webhook_json_error(error, 401);
+    }
+    // Only authorized requests are counted, so anonymous traffic doesn't use up KV writes
+    if let (Some(store), Some(api_key)) = (store, &api_key) {
+        let client = format!("{:016x}", fnv1a(api_key.as_bytes()));
+        if is_rate_limited(store, &client, config.webhook_rate_limit, Utc::now()).await {
+            return webhook_json_error("Too many requests", 429);
+        }
+    }
+
+    let expected_balance = match query_param("expected_balance").map(|value| value.parse::<f64>()) {
+        Some(Ok(balance)) => Some((balance * 1000.0).round() as i64),
+        Some(Err(err)) => {
+            return webhook_json_error(format!("Invalid expected balance: {err}"), 400)
+        }
+        None => None,
+    };
+    let options = ImportOptions {
+        expected_balance,
+        seen_payees: seen_payees(&config, store).await,
+        group_category_ids: cached_category_groups(&config, store).await,
+        execution_context: Some(execution_context),
+        last_server_knowledge: last_server_knowledge(&config, store).await,
+        ..Default::default()
+    };
+
+    let body = if let Some(source_url) = query_param("source_url") {
+        let source_url = match validate_source_url(&config, &source_url) {
+            Ok(source_url) => source_url,
+            Err(err) => return webhook_json_error(err.to_string(), 400),
+        };
+        match download_source_csv(&config, source_url, fetch_source).await {
+            Ok(csv_bytes) => {
+                archive_upload(archive, "webhook", Utc::now(), &csv_bytes).await;
+                WebhookBody::Csv(csv_bytes)
+            }
+            Err(err) if err.is::<BodyTooLarge>() => {
+                return webhook_json_error(err.to_string(), 413)
+            }
+            Err(err) => return webhook_json_error(format!("{err:#}"), 502),
+        }
+    } else {
+        let content_type = req.headers().get("Content-Type")?;
+        let body = if req.inner().body().is_some() {
+            match read_body(req.stream()?, WEBHOOK_BODY_MAX_SIZE).await {
+                Ok(body) => body,
+                Err(err) if err.is::<BodyTooLarge>() => {
+                    return webhook_json_error(err.to_string(), 413)
+                }
+                Err(err) => return webhook_json_error(err.to_string(), 400),
+            }
+        } else {
+            Vec::new()
+        };
+        archive_upload(archive, "webhook", Utc::now(), &body).await;
+        match decode_webhook_body(content_type.as_deref(), body) {
+            Ok(body) => body,
+            Err(err) => return webhook_json_error(err.to_string(), 400),
+        }
+    };
+    let csv_bytes = match body {
+        WebhookBody::Csv(csv_bytes) => csv_bytes,
+        WebhookBody::Manifest(files) => {
+            let result = import_manifest(files, &config, ynab_client.as_ref(), &options).await;
+            record_import(&config, store, &result.total).await;
+            let status = if result.is_success() { 200 } else { 500 };
+            let mut json = serde_json::to_value(&result)?;
+            json["message"] = result.total.to_string().into();
+            let mut response = webhook_json(&json, status)?;
+            for (name, value) in result.total.headers() {
+                response.headers_mut().set(name, &value)?;
+            }
+            return Ok(response);
+        }
+    };
+    if query_param("convert").as_deref() == Some("ynab_csv") {
+        return match map_yonder_csv_with_groups(csv_bytes, &config, ynab_client.as_ref(), store)
+            .await
+            .and_then(|mapped| ynab_import_csv(&mapped))
+        {
+            Ok(csv) => {
+                let mut response = Response::ok(csv)?;
+                response
+                    .headers_mut()
+                    .set("Content-Type", "text/csv; charset=utf-8")?;
+                response.headers_mut().set(
+                    "Content-Disposition",
+                    "attachment; filename=\"ynab-import.csv\"",
+                )?;
+                Ok(response)
+            }
+            Err(err) => webhook_json_error(err.to_string(), 400),
+        };
+    }
+    if query_param("preview").as_deref() == Some("true") {
+        return match map_yonder_csv_with_groups(csv_bytes, &config, ynab_client.as_ref(), store)
+            .await
+        {
+            Ok(mapped) => match preview(&mapped.transactions, &config, |_| true) {
+                Preview::Inline(preview) => {
+                    webhook_json(&serde_json::json!({ "preview": preview }), 200)
+                }
+                Preview::File(preview) => {
+                    let mut response = Response::ok(preview)?;
+                    response
+                        .headers_mut()
+                        .set("Content-Type", "text/plain; charset=utf-8")?;
+                    response.headers_mut().set(
+                        "Content-Disposition",
+                        &format!("attachment; filename=\"{PREVIEW_FILE_NAME}\""),
+                    )?;
+                    Ok(response)
+                }
+            },
+            Err(err) => webhook_json_error(err.to_string(), 400),
+        };
+    }
+    if query_param("diff").as_deref() == Some("true") {
+        let Some(store) = store else {
+            return webhook_json_error("KV storage is not configured", 503);
+        };
+        return match import_diff(csv_bytes, &config, ynab_client.as_ref(), store, &options).await {
+            Ok(diff) => webhook_json(&diff, 200),
+            Err(err) => {
+                let (status, message) = webhook_error(&err);
+                webhook_json_error(message, status)
+            }
+        };
+    }
+    match import_yonder_csv_to_ynab(csv_bytes, &config, ynab_client.as_ref(), &options).await {
+        Ok(result) => {
+            record_import(&config, store, &result).await;
+            let (status, message) = webhook_import_status(&config, &result);
+            let mut response = match format {
+                ResponseFormat::Json => {
+                    webhook_json(&import_result_json(&result, &message)?, status)?
+                }
+                ResponseFormat::Text => match null_body_response(status)? {
+                    Some(response) => response,
+                    None => {
+                        let mut response = Response::ok(message)?.with_status(status);
+                        response
+                            .headers_mut()
+                            .set("Content-Type", "text/plain; charset=utf-8")?;
+                        response
+                    }
+                },
+            };
+            for (name, value) in result.headers() {
+                response.headers_mut().set(name, &value)?;
+            }
+            Ok(response)
+        }
+        Err(err) => {
+            let (status, message) = webhook_error(&err);
+            webhook_json_error(message, status)
+        }
+    }
+}
+
+/// Convert mapped transactions to the CSV format of YNAB file import, with amounts in pounds
+fn ynab_import_csv(mapped: &MappedCsv) -> eyre::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["Date", "Payee", "Memo", "Outflow", "Inflow"])?;
+    for transaction in mapped
+        .transactions
+        .iter()
+        .chain(mapped.scheduled.iter().map(|(transaction, _)| transaction))
+    {
+        let amount = transaction.amount.unwrap_or_default();
+        let pounds = format_gbp(amount.abs()).replace('£', "");
+        let (outflow, inflow) = if amount < 0 {
+            (pounds, String::new())
+        } else {
+            (String::new(), pounds)
+        };
+        writer.write_record([
+            transaction
+                .date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            transaction
+                .payee_name
+                .as_ref()
+                .map(|payee_name| payee_name.to_string())
+                .unwrap_or_default(),
+            transaction
+                .memo
+                .as_ref()
+                .map(|memo| memo.to_string())
+                .unwrap_or_default(),
+            outflow,
+            inflow,
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Version of the webhook JSON response schema, bumped when the shape of responses changes
+const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+
+/// Webhook JSON response body with the schema version added to the object
+fn versioned_json(value: &impl Serialize) -> serde_json::Result<serde_json::Value> {
+    let mut json = serde_json::to_value(value)?;
+    if let Some(object) = json.as_object_mut() {
+        object.insert("schema_version".to_string(), WEBHOOK_SCHEMA_VERSION.into());
+    }
+    Ok(json)
+}
+
+/// Webhook JSON error body with the schema version
+fn error_json(message: impl Display) -> serde_json::Value {
+    serde_json::json!({
+        "schema_version": WEBHOOK_SCHEMA_VERSION,
+        "error": message.to_string(),
+    })
+}
+
+/// Format of the webhook import response, negotiated with the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseFormat {
+    /// Import result as JSON with the summary as `message`
+    Json,
+    /// Import summary as plain text
+    Text,
+}
+
+impl ResponseFormat {
+    /// Format of the first supported media type in the `Accept` header, JSON by default
+    fn negotiate(accept: Option<&str>) -> Self {
+        accept
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|media_range| media_range.split(';').next())
+            .find_map(
+                |media_type| match media_type.trim().to_lowercase().as_str() {
+                    "application/json" => Some(Self::Json),
+                    "text/plain" | "text/*" => Some(Self::Text),
+                    _ => None,
+                },
+            )
+            .unwrap_or(Self::Json)
+    }
+}
+
+/// JSON body of the webhook import response, the result with its summary as `message`
+fn import_result_json(
+    result: &DocumentResult,
+    message: &str,
+) -> serde_json::Result<serde_json::Value> {
+    let mut json = serde_json::to_value(result)?;
+    json["message"] = message.into();
+    Ok(json)
+}
+
+/// Statuses of responses that can't have a body, which the Workers runtime fails to construct
+const NULL_BODY_STATUSES: [u16; 3] = [204, 205, 304];
+
+/// Empty response if the status can't have a body, e.g. a configured `204 No Content`
+fn null_body_response(status: u16) -> worker::Result<Option<Response>> {
+    if !NULL_BODY_STATUSES.contains(&status) {
+        return Ok(None);
+    }
+    Ok(Some(Response::empty()?.with_status(status)))
+}
+
+/// Webhook JSON response with the schema version
+fn webhook_json(value: &impl Serialize, status: u16) -> worker::Result<Response> {
+    if let Some(response) = null_body_response(status)? {
+        return Ok(response);
+    }
+    Ok(Response::from_json(&versioned_json(value)?)?.with_status(status))
+}
+
+/// Webhook JSON error response with the schema version
+fn webhook_json_error(message: impl Display, status: u16) -> worker::Result<Response> {
+    Ok(Response::from_json(&error_json(message))?.with_status(status))
+}
+
+/// Webhook response status and message for the failed request
+///
+/// YNAB rejecting the API key is reported as unauthorized, so that callers know to update it.
+fn webhook_error(err: &eyre::Report) -> (u16, String) {
+    if err.chain().any(|cause| cause.is::<InvalidYnabToken>()) {
+        (401, InvalidYnabToken.to_string())
+    } else if err.chain().any(|cause| cause.is::<BudgetUnavailable>()) {
+        // Reported without the context, which is less actionable than the cause
+        (500, BudgetUnavailable.to_string())
+    } else {
+        (500, err.to_string())
+    }
+}
+
+/// Archive the raw upload under a key made of the upload time and its source, if the archive is
+/// configured
+async fn archive_upload(
+    archive: Option<&impl Archive>,
+    source: &str,
+    now: DateTime<Utc>,
+    bytes: &[u8],
+) {
+    let Some(archive) = archive else {
+        return;
+    };
+
+    let key = format!("uploads/{}/{source}", now.format("%Y-%m-%dT%H:%M:%S%.3fZ"));
+    // The archive is only for audit, so it never fails the import
+    if let Err(err) = archive.put(&key, bytes.to_vec()).await {
+        worker::console_warn!("Failed to archive upload {key}: {err}");
+    }
+}
+
+/// Error if the webhook API key is not set or doesn't match the given one
+fn webhook_api_key_error(config: &Config, api_key: Option<&str>) -> Option<&'static str> {
+    let Some(webhook_api_key) = config.webhook_api_key.as_deref() else {
+        return Some("Webhook API key is not set");
+    };
+
+    (api_key != Some(webhook_api_key)).then_some("Invalid API key")
+}
+
+/// Default number of transactions returned by `/recent`
+const RECENT_DEFAULT_COUNT: usize = 10;
+/// Maximum number of transactions returned by `/recent`
+const RECENT_MAX_COUNT: usize = 100;
+
+/// Handle `GET /recent`, listing the latest transactions of the configured account
+async fn on_webhook_recent(
+    req: Request,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+) -> worker::Result<Response> {
+    let url = req.url()?;
+    let query_param = |name: &str| {
+        url.query_pairs()
+            .find_map(|(k, v)| (k == name).then(|| v.into_owned()))
+    };
+
+    if let Some(error) = webhook_api_key_error(config, query_param("api_key").as_deref()) {
+        return webhook_json_error(error, 401);
+    }
+
+    let count = match query_param("n").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => n.min(RECENT_MAX_COUNT),
+        Some(Err(err)) => return webhook_json_error(format!("Invalid n: {err}"), 400),
+        None => RECENT_DEFAULT_COUNT,
+    };
+
+    match recent_transactions(config, ynab_client, count).await {
+        Ok(transactions) => webhook_json(&serde_json::json!({ "transactions": transactions }), 200),
+        Err(err) => {
+            let (status, message) = webhook_error(&err);
+            webhook_json_error(message, status)
+        }
+    }
+}
+
+/// Sample Yonder CSV imported by the self-test, also used in tests
+const SELFTEST_CSV: &str = include_str!("../yonder.csv");
+/// Prefix of the tag marking self-test transactions
+const SELFTEST_TAG_PREFIX: &str = "SELFTEST";
+
+/// Handle `POST /selftest`, importing the sample CSV to verify the deployment end-to-end
+async fn on_webhook_selftest(
+    req: Request,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+) -> worker::Result<Response> {
+    let url = req.url()?;
+    let query_param = |name: &str| {
+        url.query_pairs()
+            .find_map(|(k, v)| (k == name).then(|| v.into_owned()))
+    };
+
+    if let Some(error) = webhook_api_key_error(config, query_param("api_key").as_deref()) {
+        return webhook_json_error(error, 401);
+    }
+
+    let delete = match query_param("delete").map(|delete| delete.parse::<bool>()) {
+        Some(Ok(delete)) => delete,
+        Some(Err(err)) => return webhook_json_error(format!("Invalid delete: {err}"), 400),
+        None => false,
+    };
+
+    match self_test(config, ynab_client, delete, Utc::now()).await {
+        Ok(result) => {
+            let mut json = serde_json::to_value(&result)?;
+            json["message"] = result.result.to_string().into();
+            webhook_json(&json, 200)
+        }
+        Err(err) => {
+            let (status, message) = webhook_error(&err);
+            webhook_json_error(message, status)
+        }
+    }
+}
+
+/// Outcome of the self-test
+#[derive(Debug, Serialize)]
+struct SelfTestResult {
+    /// Tag of the imported transactions
+    tag: String,
+    #[serde(flatten)]
+    result: DocumentResult,
+    /// Number of imported transactions deleted afterwards
+    deleted: usize,
+}
+
+/// Import the sample CSV with transactions tagged as a self-test, optionally deleting them after
+async fn self_test(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    delete: bool,
+    now: DateTime<Utc>,
+) -> eyre::Result<SelfTestResult> {
+    let tag = format!("{SELFTEST_TAG_PREFIX}:{}", now.timestamp_millis());
+    let options = ImportOptions {
+        tag: Some(tag.clone()),
+        ..Default::default()
+    };
+    let result = import_yonder_csv_to_ynab(SELFTEST_CSV, config, ynab_client, &options)
+        .await
+        .wrap_err("self-test import failed")?;
+
+    let mut deleted = 0;
+    if delete {
+        for transaction_id in &result.transaction_ids {
+            ynab_client
+                .delete_transaction(&config.ynab_budget_id, transaction_id)
+                .await
+                .wrap_err_with(|| {
+                    format!("failed to delete self-test transaction {transaction_id}")
+                })?;
+            deleted += 1;
+        }
+    }
+
+    Ok(SelfTestResult {
+        tag,
+        result,
+        deleted,
+    })
+}
+
+/// Check the mapping rules against the CSV, returning the rules matching no rows and invalid
+/// mapped transactions
+fn validate_rules(yonder_csv: &str, config: &Config) -> Vec<String> {
+    let transactions = match parse_yonder_csv(yonder_csv) {
+        Ok(yonder_csv) => yonder_csv.transactions,
+        Err(err) => return vec![format!("failed to parse the sample CSV: {err}")],
+    };
+
+    let mut warnings = Vec::new();
+    let mut unmatched = |rule: String, matches: &dyn Fn(&YonderTransaction) -> bool| {
+        if !transactions.iter().any(matches) {
+            warnings.push(format!("{rule} matches no rows"));
+        }
+    };
+    for category in config.category_map.keys() {
+        unmatched(format!("category {category:?}"), &|transaction| {
+            transaction.category.trim().eq_ignore_ascii_case(category)
+        });
+    }
+    for category in config.category_group_map.keys() {
+        unmatched(
+            format!("category group mapping {category:?}"),
+            &|transaction| transaction.category.trim().eq_ignore_ascii_case(category),
+        );
+    }
+    for rule in &config.category_description_rules {
+        unmatched(
+            format!("category rule {:?}", rule.pattern.as_str()),
+            &|transaction| rule.pattern.is_match(&transaction.description),
+        );
+    }
+    for route in &config.account_routes {
+        unmatched(format!("account route {:?}", route.name), &|transaction| {
+            route.matches(&transaction.description, &transaction.category)
+        });
+    }
+    for split in &config.category_splits {
+        unmatched(
+            format!("category split {:?}", split.description_contains),
+            &|transaction| {
+                transaction
+                    .description
+                    .to_lowercase()
+                    .contains(&split.description_contains.to_lowercase())
+            },
+        );
+    }
+    for rule in &config.scheduled_rules {
+        unmatched(
+            format!("scheduled rule {:?}", rule.description_contains),
+            &|transaction| {
+                transaction
+                    .description
+                    .to_lowercase()
+                    .contains(&rule.description_contains.to_lowercase())
+            },
+        );
+    }
+
+    // Map as of the newest transaction so that the import window doesn't reject old samples
+    let Some(now) = transactions
+        .iter()
+        .map(|transaction| transaction.date_time.clone().utc())
+        .max()
+    else {
+        return warnings;
+    };
+    match map_yonder_csv(yonder_csv, config, &ImportContext::new(now)) {
+        Ok(mapped) => {
+            warnings.extend(mapped.unrouted);
+            for import_id in mapped
+                .transactions
+                .iter()
+                .filter_map(|transaction| transaction.import_id.as_ref())
+                .map(|import_id| import_id.to_string())
+                .filter(|import_id| import_id.len() > IMPORT_ID_MAX_LENGTH)
+            {
+                warnings.push(format!(
+                    "import_id {import_id} is longer than {IMPORT_ID_MAX_LENGTH} characters"
+                ));
+            }
+            warnings.extend(mapped.warnings);
+        }
+        Err(err) => warnings.push(format!("failed to map the sample CSV: {err}")),
+    }
+    warnings
+}
+
+/// KV key of import IDs tracked for local deduplication, stored as a JSON set
+const DEDUP_KEY: &str = "dedup";
+
+/// Handle `POST /reset-dedup`, purging import IDs tracked in KV
+async fn on_webhook_reset_dedup(
+    req: Request,
+    config: &Config,
+    store: Option<&KvStore>,
+) -> worker::Result<Response> {
+    let api_key = req
+        .url()?
+        .query_pairs()
+        .find_map(|(k, v)| (k == "api_key").then(|| v.into_owned()));
+    if let Some(error) = webhook_api_key_error(config, api_key.as_deref()) {
+        return webhook_json_error(error, 401);
+    }
+
+    let Some(store) = store else {
+        return webhook_json_error("KV storage is not configured", 503);
+    };
+    match reset_dedup(store).await {
+        Ok(removed) => webhook_json(&serde_json::json!({ "removed": removed }), 200),
+        Err(err) => webhook_json_error(err.to_string(), 500),
+    }
+}
+
+/// Import IDs tracked for local deduplication, empty if none were recorded yet
+async fn read_dedup_import_ids(store: &impl Store) -> eyre::Result<BTreeSet<String>> {
+    match store.get(DEDUP_KEY).await? {
+        Some(import_ids) => serde_json::from_str(&import_ids).wrap_err("invalid dedup import IDs"),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Delete import IDs tracked for local deduplication, returning the number of removed IDs
+async fn reset_dedup(store: &impl Store) -> eyre::Result<usize> {
+    // Invalid state is deleted as well, that's what the reset is for
+    let removed = read_dedup_import_ids(store)
+        .await
+        .map_or(0, |import_ids| import_ids.len());
+    store.delete(DEDUP_KEY).await?;
+    Ok(removed)
+}
+
+/// Latest transactions of the configured account, newest first
+async fn recent_transactions(
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    count: usize,
+) -> eyre::Result<Vec<AccountTransaction>> {
+    let mut transactions = ynab_client
+        .account_transactions(&config.ynab_budget_id, config.account_id()?, None)
+        .await?;
+    // Stable sort keeps YNAB order for transactions on the same date
+    transactions.sort_by_key(|transaction| std::cmp::Reverse(transaction.date));
+    transactions.truncate(count);
+    Ok(transactions)
+}
+
+/// Webhook response status and message for the import result
+fn webhook_import_status(config: &Config, result: &DocumentResult) -> (u16, String) {
+    if !result.errors.is_empty() {
+        (500, result.to_string())
+    } else if result.is_empty() {
+        (
+            config.webhook_empty_status(),
+            format!("No transactions found\n{result}"),
+        )
+    } else {
+        (200, result.to_string())
+    }
+}
+
+/// Parse the CSV source URL, allowing only HTTPS URLs of the configured hosts to prevent SSRF
+fn validate_source_url(config: &Config, source_url: &str) -> eyre::Result<reqwest::Url> {
+    let source_url: reqwest::Url = source_url.parse().wrap_err("invalid source URL")?;
+    if source_url.scheme() != "https" {
+        bail!("source URL must use HTTPS");
+    }
+
+    let host = source_url.host_str().unwrap_or_default();
+    // `*.example.com` allows subdomains, as redirect targets like Google's often vary
+    if !config
+        .source_url_allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", domain.to_ascii_lowercase())),
+            None => allowed.eq_ignore_ascii_case(host),
+        })
+    {
+        bail!("source URL host {host} is not allowed");
+    }
+
+    Ok(source_url)
+}
+
+/// Download CSV from the validated source URL, e.g. a Google Sheets CSV export
+///
+/// Redirects are followed manually, validating every hop like the source URL itself.
+async fn download_source_csv<F, S>(
+    config: &Config,
+    source_url: reqwest::Url,
+    fetch: impl Fn(reqwest::Url) -> F,
+) -> eyre::Result<Vec<u8>>
+where
+    F: Future<Output = eyre::Result<SourceResponse<S>>>,
+    S: Stream<Item = worker::Result<Vec<u8>>> + Unpin,
+{
+    let mut url = source_url;
+    for _ in 0..=MAX_SOURCE_REDIRECTS {
+        let response = fetch(url.clone())
+            .await
+            .wrap_err("failed to fetch source URL")?;
+        if (300..400).contains(&response.status) {
+            let location = response
+                .location
+                .ok_or_eyre("source URL redirected without a location")?;
+            let location = url
+                .join(&location)
+                .wrap_err("invalid source URL redirect")?;
+            url = validate_source_url(config, location.as_str())
+                .wrap_err("source URL redirected to a disallowed URL")?;
+            continue;
+        }
+        if !(200..300).contains(&response.status) {
+            bail!("failed to fetch source URL: HTTP {}", response.status);
+        }
+        return read_body(response.body, WEBHOOK_BODY_MAX_SIZE).await;
+    }
+    bail!("source URL redirected more than {MAX_SOURCE_REDIRECTS} times")
+}
+
+/// Maximum number of redirects followed when fetching the source URL
+const MAX_SOURCE_REDIRECTS: usize = 5;
+
+/// Response to a source URL request, with redirects not followed
+struct SourceResponse<S> {
+    status: u16,
+    /// `Location` header of redirects
+    location: Option<String>,
+    body: S,
+}
+
+/// Fetch the source URL without following redirects
+async fn fetch_source(url: reqwest::Url) -> eyre::Result<SourceResponse<worker::ByteStream>> {
+    let mut init = worker::RequestInit::new();
+    init.with_redirect(worker::RequestRedirect::Manual);
+    let request = Request::new_with_init(url.as_str(), &init)?;
+    let mut response = worker::Fetch::Request(request).send().await?;
+    Ok(SourceResponse {
+        status: response.status_code(),
+        location: response.headers().get("Location")?,
+        body: response.stream()?,
+    })
+}
+
+/// CORS headers for webhook responses to browsers from the origin, empty if the origin is not allowed
+fn cors_headers(config: &Config, origin: Option<&str>) -> Vec<(&'static str, String)> {
+    let Some(origin) = origin.filter(|origin| config.is_origin_allowed(origin)) else {
+        return Vec::new();
+    };
+
+    vec![
+        ("Access-Control-Allow-Origin", origin.to_string()),
+        (
+            "Access-Control-Allow-Methods",
+            "POST, HEAD, OPTIONS".to_string(),
+        ),
+        ("Access-Control-Allow-Headers", "Content-Type".to_string()),
+        (
+            "Access-Control-Expose-Headers",
+            "X-Imported-Count, X-Duplicate-Count".to_string(),
+        ),
+        ("Access-Control-Max-Age", "86400".to_string()),
+        ("Vary", "Origin".to_string()),
+    ]
+}
+
+/// Count the webhook request and check whether the API key exceeded the per-minute limit
+///
+/// Best-effort only: KV is eventually consistent and allows about one write per second per key,
+/// so concurrent requests can undercount. Fails open, so storage errors never block legitimate
+/// imports.
+async fn is_rate_limited(store: &impl Store, client: &str, limit: u32, now: DateTime<Utc>) -> bool {
+    if limit == 0 {
+        return false;
+    }
+
+    let key = format!("ratelimit:{client}:{}", now.timestamp() / 60);
+    let count = match store.get(&key).await {
+        Ok(count) => {
+            count
+                .and_then(|count| count.parse::<u32>().ok())
+                .unwrap_or(0)
+                + 1
+        }
+        Err(err) => {
+            worker::console_warn!("Rate limiter is unavailable: {err}");
+            return false;
+        }
+    };
+    if count > limit {
+        return true;
+    }
+
+    // KV requires TTL of at least 60 seconds, the key is only used within a minute anyway
+    if let Err(err) = store.put(&key, &count.to_string(), Some(120)).await {
+        worker::console_warn!("Rate limiter is unavailable: {err}");
+    }
+
+    false
+}
+
+/// JSON webhook body for callers that can't send raw CSV
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WebhookJsonBody {
+    Csv {
+        csv_base64: String,
+    },
+    /// Several CSV files imported in one request
+    Manifest {
+        files: Vec<WebhookJsonFile>,
+    },
+}
+
+#[derive(Deserialize)]
+struct WebhookJsonFile {
+    name: String,
+    csv_base64: String,
+}
+
+/// Decoded webhook body
+#[derive(Debug, Clone, PartialEq)]
+enum WebhookBody {
+    Csv(Vec<u8>),
+    Manifest(Vec<ManifestFile>),
+}
+
+/// CSV file of the webhook manifest
+#[derive(Debug, Clone, PartialEq)]
+struct ManifestFile {
+    name: String,
+    csv: Vec<u8>,
+}
+
+/// Maximum size of the webhook body, which is buffered in memory before parsing
+const WEBHOOK_BODY_MAX_SIZE: usize = 20 * 1024 * 1024;
+
+/// Request body exceeded the maximum size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BodyTooLarge {
+    max_size: usize,
+}
+
+impl Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "request body is larger than {} MB",
+            self.max_size / (1024 * 1024)
+        )
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// Buffer the whole request body from its chunks, failing once it exceeds the maximum size
+///
+/// Bodies sent with chunked transfer encoding have no `Content-Length`, so the size is only known
+/// after the last chunk.
+async fn read_body(
+    mut chunks: impl Stream<Item = worker::Result<Vec<u8>>> + Unpin,
+    max_size: usize,
+) -> eyre::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.wrap_err("failed to read request body")?;
+        if body.len() + chunk.len() > max_size {
+            return Err(BodyTooLarge { max_size }.into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Decode the webhook body into CSV bytes according to its content type
+///
+/// JSON bodies carry the CSV base64-encoded, any other body is the CSV itself.
+fn decode_webhook_body(content_type: Option<&str>, body: Vec<u8>) -> eyre::Result<WebhookBody> {
+    let is_json = content_type.is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !is_json {
+        return Ok(WebhookBody::Csv(body));
+    }
+
+    let body: WebhookJsonBody = serde_json::from_slice(&body).wrap_err(
+        "invalid JSON body, expected {\"csv_base64\": ...} or {\"files\": [{\"name\": ..., \"csv_base64\": ...}]}",
+    )?;
+    let decode = |csv_base64: &str| {
+        BASE64_STANDARD
+            .decode(csv_base64.trim())
+            .wrap_err("invalid base64 in csv_base64")
+    };
+    match body {
+        WebhookJsonBody::Csv { csv_base64 } => Ok(WebhookBody::Csv(decode(&csv_base64)?)),
+        WebhookJsonBody::Manifest { files } => files
+            .into_iter()
+            .map(|file| {
+                Ok(ManifestFile {
+                    csv: decode(&file.csv_base64)
+                        .wrap_err_with(|| format!("invalid file {:?}", file.name))?,
+                    name: file.name,
+                })
+            })
+            .collect::<eyre::Result<_>>()
+            .map(WebhookBody::Manifest),
+    }
+}
+
+/// Result of importing a CSV file of the manifest
+#[derive(Serialize)]
+struct ManifestFileResult {
+    name: String,
+    #[serde(flatten)]
+    result: Option<DocumentResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Results of importing all CSV files of the manifest
+#[derive(Serialize)]
+struct ManifestResult {
+    files: Vec<ManifestFileResult>,
+    total: DocumentResult,
+}
+
+impl ManifestResult {
+    fn is_success(&self) -> bool {
+        self.files.iter().all(|file| file.error.is_none()) && self.total.errors.is_empty()
+    }
+}
+
+/// Import CSV files of the manifest one by one, aggregating their results
+///
+/// Failed files don't stop the import of the remaining ones. The balance is checked after the last file.
+async fn import_manifest(
+    files: Vec<ManifestFile>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> ManifestResult {
+    let mut results = Vec::with_capacity(files.len());
+    let mut total = DocumentResult::default();
+    let last = files.len().saturating_sub(1);
+    let mut last_server_knowledge = options.last_server_knowledge;
+    for (index, file) in files.into_iter().enumerate() {
+        let options = ImportOptions {
+            expected_balance: options.expected_balance.filter(|_| index == last),
+            last_server_knowledge,
+            ..options.clone()
+        };
+        let result = import_yonder_csv_to_ynab(file.csv, config, ynab_client, &options).await;
+        results.push(match result {
+            Ok(result) => {
+                // Transactions of the previous files are not changes for the next ones
+                last_server_knowledge = result.server_knowledge.or(last_server_knowledge);
+                total.add(&result);
+                ManifestFileResult {
+                    name: file.name,
+                    result: Some(result),
+                    error: None,
+                }
+            }
+            Err(err) => ManifestFileResult {
+                name: file.name,
+                result: None,
+                error: Some(err.to_string()),
+            },
+        });
+    }
+
+    ManifestResult {
+        files: results,
+        total,
+    }
+}
+
+/// Yonder transactions parsed from CSV
+struct YonderCsv {
+    transactions: Vec<YonderTransaction>,
+    /// Rows that were not transactions, e.g. repeated headers of concatenated exports
+    skipped: usize,
+    /// Summary rows like totals, which are not transactions
+    footers: usize,
+    /// Rows that failed to parse as transactions, with their line numbers
+    invalid_rows: Vec<String>,
+    /// First and last date of the statement, if the CSV has a statement period row
+    statement_period: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl YonderCsv {
+    /// Error listing all rows that failed to parse, if any
+    fn invalid_rows_error(&self) -> Option<eyre::Report> {
+        (!self.invalid_rows.is_empty()).then(|| {
+            eyre::eyre!(
+                "failed to deserialize {} rows as Yonder transactions:\n{}",
+                self.invalid_rows.len(),
+                self.invalid_rows.join("\n")
+            )
+        })
+    }
+}
+
+/// Columns of the Yonder CSV export
+const YONDER_CSV_COLUMNS: [&str; 8] = [
+    "Date/Time of transaction",
+    "Description",
+    "Amount (GBP)",
+    "Amount (in Charged Currency)",
+    "Currency",
+    "Category",
+    "Debit or Credit",
+    "Country",
+];
+
+/// Columns of the Yonder CSV export that may be missing, defaulting to empty values
+const OPTIONAL_YONDER_CSV_COLUMNS: [&str; 2] = ["Currency", "Country"];
+
+/// Check that the CSV has all Yonder columns, reporting missing and unexpected ones otherwise
+///
+/// Columns are matched by name, so their order doesn't matter.
+fn validate_yonder_csv_headers(headers: &csv::StringRecord) -> eyre::Result<()> {
+    let missing: Vec<_> = YONDER_CSV_COLUMNS
+        .iter()
+        .filter(|column| !OPTIONAL_YONDER_CSV_COLUMNS.contains(column))
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .map(|column| format!("{column:?}"))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let unexpected: Vec<_> = headers
+        .iter()
+        .filter(|header| !YONDER_CSV_COLUMNS.contains(header))
+        .map(|header| format!("{header:?}"))
+        .collect();
+    let mut report = format!(
+        "CSV columns don't match the Yonder export, missing: {}",
+        missing.join(", ")
+    );
+    if !unexpected.is_empty() {
+        report.push_str(&format!("; unexpected: {}", unexpected.join(", ")));
+    }
+    bail!(report)
+}
+
+/// Parse Yonder transactions in CSV format, failing if any row is not a valid transaction
+fn parse_yonder_csv(yonder_csv: impl AsRef<[u8]>) -> eyre::Result<YonderCsv> {
+    let yonder_csv = read_yonder_csv(yonder_csv)?;
+    match yonder_csv.invalid_rows_error() {
+        Some(err) => Err(err),
+        None => Ok(yonder_csv),
+    }
+}
+
+/// Parse Yonder transactions in CSV format, collecting rows that are not valid transactions
+fn read_yonder_csv(yonder_csv: impl AsRef<[u8]>) -> eyre::Result<YonderCsv> {
+    // Summary rows may have fewer fields than transactions
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(Cursor::new(yonder_csv));
+    let headers = reader.headers()?.clone();
+    if !headers.is_empty() {
+        validate_yonder_csv_headers(&headers)?;
+    }
+
+    let date_index = headers
+        .iter()
+        .position(|header| header == YONDER_CSV_COLUMNS[0]);
+    let mut transactions = Vec::new();
+    let mut skipped = 0;
+    let mut footers = 0;
+    let mut invalid_rows = Vec::new();
+    let mut statement_period = None;
+    for record in reader.records() {
+        let record = record.wrap_err("failed to read Yonder transactions CSV")?;
+
+        // Concatenated exports contain the header row again in the middle
+        if record.iter().eq(headers.iter()) {
+            skipped += 1;
+            continue;
+        }
+
+        if let Some(period) = parse_statement_period(&record) {
+            statement_period = Some(period);
+            skipped += 1;
+            continue;
+        }
+
+        if is_footer_row(&record, date_index) {
+            footers += 1;
+            continue;
+        }
+
+        // Trailing fields missing from the row are defaulted rather than expected
+        let row_headers: csv::StringRecord = headers.iter().take(record.len()).collect();
+        let line = record.position().map_or(0, |position| position.line());
+        // Report all invalid rows at once rather than only the first one
+        match record.deserialize::<YonderTransaction>(Some(&row_headers)) {
+            Ok(transaction) => transactions.push(YonderTransaction {
+                line,
+                ..transaction
+            }),
+            Err(err) => invalid_rows.push(format!("line {line}: {err}")),
+        }
+    }
+
+    Ok(YonderCsv {
+        transactions,
+        skipped,
+        footers,
+        invalid_rows,
+        statement_period,
+    })
+}
+
+/// Start of the row with the statement period of the CSV
+const STATEMENT_PERIOD_MARKER: &str = "statement period";
+
+/// First and last date of the statement period row, e.g. `Statement period: 01/01/2026 - 31/01/2026`
+///
+/// Dates are either ISO 8601 or day first as in the Yonder app.
+fn parse_statement_period(record: &csv::StringRecord) -> Option<(NaiveDate, NaiveDate)> {
+    let row = record.iter().map(str::trim).collect::<Vec<_>>().join(" ");
+    if !row.to_lowercase().starts_with(STATEMENT_PERIOD_MARKER) {
+        return None;
+    }
+
+    let mut dates = row
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_digit()))
+        .filter_map(|word| {
+            NaiveDate::parse_from_str(word, "%Y-%m-%d")
+                .or_else(|_| NaiveDate::parse_from_str(word, "%d/%m/%Y"))
+                .ok()
+        });
+    let (from, to) = (dates.next()?, dates.next()?);
+    (from <= to).then_some((from, to))
+}
+
+/// Payee of the statement period marker transaction
+const STATEMENT_MARKER_PAYEE: &str = "Yonder statement";
+
+/// £0 transaction marking the end of the statement period, separating statements in YNAB
+///
+/// The marker belongs to the statement of the default account, so there's none without it.
+fn statement_marker(from: NaiveDate, to: NaiveDate, config: &Config) -> Option<NewTransaction> {
+    let account_id = config.ynab_account_id?;
+    let import_id = ImportIdBuilder::new("TGSTMT")
+        .segment(from.format("%Y%m%d"))
+        .segment(to.format("%Y%m%d"))
+        .build();
+    Some(NewTransaction {
+        account_id: Some(account_id),
+        amount: Some(0),
+        approved: None,
+        category_id: None,
+        cleared: Some(config.cleared_status()),
+        date: Some(to),
+        flag_color: None,
+        import_id: Some(import_id.parse().unwrap()),
+        memo: Some(format!("Statement period {from} to {to}").parse().unwrap()),
+        payee_id: None,
+        payee_name: Some(STATEMENT_MARKER_PAYEE.parse().unwrap()),
+        subtransactions: vec![],
+    })
+}
+
+/// Words marking summary rows of the CSV
+const FOOTER_MARKERS: [&str; 3] = ["total", "summary", "closing balance"];
+
+/// Whether the row is a summary like totals rather than a transaction, i.e. it has no valid date
+/// and one of its fields is marked as a summary
+fn is_footer_row(record: &csv::StringRecord, date_index: Option<usize>) -> bool {
+    let date = date_index
+        .and_then(|index| record.get(index))
+        .unwrap_or_default()
+        .trim();
+    let is_date = date.parse::<NaiveDateTime>().is_ok()
+        || date.parse::<DateTime<Utc>>().is_ok()
+        || date.parse::<NaiveDate>().is_ok();
+
+    !is_date
+        && record.iter().any(|field| {
+            let field = field.trim().to_lowercase();
+            FOOTER_MARKERS
+                .iter()
+                .any(|marker| field.starts_with(marker))
+        })
+}
+
+/// Byte order mark of UTF-16 little-endian text
+const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+/// Byte order mark of UTF-16 big-endian text
+const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Transcode the CSV to UTF-8 if it starts with a UTF-16 byte order mark, e.g. when saved by Excel
+fn transcode_utf16(csv: Cow<'_, [u8]>) -> eyre::Result<Cow<'_, [u8]>> {
+    let (text, from_bytes): (_, fn([u8; 2]) -> u16) =
+        if let Some(text) = csv.strip_prefix(UTF16_LE_BOM) {
+            (text, u16::from_le_bytes)
+        } else if let Some(text) = csv.strip_prefix(UTF16_BE_BOM) {
+            (text, u16::from_be_bytes)
+        } else {
+            return Ok(csv);
+        };
+
+    let units: Vec<u16> = text
+        .chunks_exact(2)
+        .map(|unit| from_bytes([unit[0], unit[1]]))
+        .collect();
+    let text = String::from_utf16(&units).wrap_err("invalid UTF-16 CSV")?;
+    Ok(Cow::Owned(text.into_bytes()))
+}
+
+/// Magic number at the start of zip archives
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Extract the CSV file if the upload is a zip archive, otherwise return the upload as is
+fn extract_csv(upload: &[u8]) -> eyre::Result<Cow<'_, [u8]>> {
+    if !upload.starts_with(ZIP_MAGIC) {
+        return Ok(Cow::Borrowed(upload));
+    }
+
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(upload)).wrap_err("failed to read zip archive")?;
+    let csv_names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.starts_with("__MACOSX/") && name.to_lowercase().ends_with(".csv"))
+        .map(str::to_string)
+        .collect();
+    let [csv_name] = csv_names.as_slice() else {
+        bail!(
+            "zip archive must contain exactly one CSV file, found {}",
+            csv_names.len()
+        );
+    };
+
+    let mut csv = Vec::new();
+    archive
+        .by_name(csv_name)?
+        .read_to_end(&mut csv)
+        .wrap_err_with(|| format!("failed to extract {csv_name} from zip archive"))?;
+    Ok(Cow::Owned(csv))
+}
+
+/// Per-request import options
+#[derive(Debug, Clone, Default)]
+struct ImportOptions {
+    /// Expected account balance after the import in milliunits
+    expected_balance: Option<i64>,
+    /// Cleared status overriding the configured one
+    cleared_status: Option<TransactionClearedStatus>,
+    /// Tag marking imported transactions, replacing their import_ids and added to their memos
+    tag: Option<String>,
+    /// import_ids already imported according to local state, which are not sent to YNAB
+    known_import_ids: BTreeSet<String>,
+    /// Import only the most recent transaction, e.g. to verify the setup
+    latest_only: bool,
+    /// Entries of payees imported before as made by `seen_payee`, approving only their transactions
+    seen_payees: Option<BTreeSet<String>>,
+    /// YNAB category IDs of the mapped category groups cached by `/refresh`, resolved from YNAB
+    /// if not set
+    group_category_ids: Option<BTreeMap<String, Uuid>>,
+    /// Execution context of the request, posting the import summary after the response if set
+    execution_context: Option<Arc<worker::Context>>,
+    /// YNAB server knowledge recorded after the last import, warning about transactions changed
+    /// in YNAB since then if set
+    last_server_knowledge: Option<i64>,
+}
+
+/// Yonder transactions mapped to YNAB format
+struct MappedCsv {
+    transactions: Vec<NewTransaction>,
+    /// Recurring transactions to create as scheduled ones, with their frequency
+    scheduled: Vec<(NewTransaction, ScheduledTransactionFrequency)>,
+    /// Rows that were not imported
+    skipped: usize,
+    /// Problems with the CSV that don't fail the import
+    warnings: Vec<String>,
+    /// Transactions above `LARGE_TRANSACTION_GBP`
+    large: Vec<String>,
+    /// £0 transaction marking the end of the statement period, imported with the transactions
+    statement_marker: Option<NewTransaction>,
+    /// Transactions without a YNAB account, by CSV line
+    unrouted: Vec<String>,
+    /// Most recent transaction with a YNAB account by the Yonder date and time, the last one in
+    /// the CSV on ties
+    latest: Option<NewTransaction>,
+}
+
+/// Parse Yonder transactions in CSV format and map them to YNAB format
+///
+/// import_ids depend only on the CSV and the config, never on the import context or map
+/// iteration order, so importing the same file again is deduplicated by YNAB.
+fn map_yonder_csv(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    context: &ImportContext,
+) -> eyre::Result<MappedCsv> {
+    // Parse CSV with Yonder transactions
+    let yonder_csv = read_yonder_csv(transcode_utf16(extract_csv(yonder_csv.as_ref())?)?)?;
+    if let Some(err) = yonder_csv
+        .invalid_rows_error()
+        .filter(|_| !config.partial_import)
+    {
+        return Err(err);
+    }
+    if config.footer_row_policy == FooterRowPolicy::Error && yonder_csv.footers > 0 {
+        bail!(
+            "CSV has {} summary rows that are not transactions",
+            yonder_csv.footers
+        );
+    }
+
+    // Guard against uploading an old export by mistake
+    if let Some(import_window_days) = config.import_window_days {
+        let newest = yonder_csv
+            .transactions
+            .iter()
+            .map(|transaction| transaction.date_time.clone().utc())
+            .max();
+        if let Some(newest) = newest {
+            if context.now - newest > chrono::Duration::days(import_window_days.into()) {
+                bail!(
+                    "CSV is stale: the newest transaction is from {}, older than {import_window_days} days",
+                    newest.date_naive()
+                );
+            }
+        }
+    }
+
+    let mut transactions = yonder_csv.transactions;
+    if !config.preserve_whitespace {
+        transactions.iter_mut().for_each(YonderTransaction::trim);
+    }
+    transactions.iter_mut().for_each(YonderTransaction::settle);
+
+    if config.sign_mismatch_policy == SignMismatchPolicy::Correct {
+        transactions
+            .iter_mut()
+            .for_each(YonderTransaction::correct_sign);
+    }
+    // YNAB amounts are in milliunits, so extra precision would be silently rounded
+    let mut warnings: Vec<String> = match config.amount_max_decimals {
+        Some(max_decimals) => transactions
+            .iter()
+            .filter(|transaction| transaction.exceeds_decimals(max_decimals))
+            .map(|transaction| {
+                format!(
+                    "line {}: {:?} amount {} has more than {max_decimals} decimal places",
+                    transaction.line, transaction.description, transaction.amount_gbp
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    warnings.extend(
+        yonder_csv
+            .invalid_rows
+            .iter()
+            .map(|row| format!("{row} (not imported)")),
+    );
+    // Negative amounts would flip the sign given by the kind, so such rows are left out
+    let mut sign_mismatches = BTreeSet::new();
+    if config.sign_mismatch_policy == SignMismatchPolicy::Error {
+        for (index, transaction) in transactions.iter().enumerate() {
+            if transaction.has_sign_mismatch() {
+                warnings.push(format!(
+                    "line {}: {:?} {:?} with amount {} is inconsistent with Debit or Credit (not imported)",
+                    transaction.line,
+                    transaction.kind,
+                    transaction.description,
+                    transaction.amount_gbp
+                ));
+                sign_mismatches.insert(index);
+            }
+        }
+    }
+
+    // Filter out transactions that shouldn't be imported, keeping their position in the upload
+    let total = transactions.len();
+    let (transactions, filtered): (Vec<_>, Vec<_>) = transactions
+        .into_iter()
+        .enumerate()
+        .partition(|(index, transaction)| {
+            !sign_mismatches.contains(index) && should_import(transaction, config, context)
+        });
+
+    // Map Yonder transactions to YNAB format
+    let mut mapped = MappedCsv {
+        transactions: Vec::new(),
+        scheduled: Vec::new(),
+        skipped: yonder_csv.skipped
+            + yonder_csv.footers
+            + yonder_csv.invalid_rows.len()
+            + filtered.len(),
+        warnings,
+        large: Vec::new(),
+        statement_marker: None,
+        unrouted: Vec::new(),
+        latest: None,
+    };
+    let mut latest_date_time = None;
+    let mut unmapped_rows = Vec::new();
+    for (index, transaction) in transactions {
+        let frequency = config.scheduled_frequency(&transaction.description);
+        let line = transaction.line;
+        let date_time = transaction.date_time.clone().utc();
+        let amount = transaction.amount(config.amount_scale());
+        let large = config
+            .large_transaction_gbp
+            .is_some_and(|large| amount.abs() > gbp_milliunits(large))
+            .then(|| {
+                format!(
+                    "line {line}: {:?} {}",
+                    transaction.description,
+                    format_gbp(amount)
+                )
+            });
+        let mut ynab_transaction = map_transaction(transaction, config, context);
+        // YNAB would reject the transaction, so it's reported like rows that fail to parse
+        if let Some(err) = split_mismatch(&ynab_transaction) {
+            unmapped_rows.push(format!("line {line}: {err}"));
+            continue;
+        }
+        // Only the import needs an account, previews and conversions don't
+        if ynab_transaction.account_id.is_none() {
+            mapped.unrouted.push(format!(
+                "line {line}: no YNAB account for transaction {:?}: set {ENV_YNAB_ACCOUNT_ID} or add a matching account route",
+                ynab_transaction.payee_name.as_ref().map_or("", |payee_name| payee_name.as_str())
+            ));
+        }
+        mapped.large.extend(large);
+        if config.memo_row_counter {
+            append_memo(&mut ynab_transaction, &format!("row {}/{total}", index + 1));
+        }
+        if config.memo_upload_id {
+            append_memo(
+                &mut ynab_transaction,
+                &format!("upload:{}", context.upload_id),
+            );
+        }
+        match frequency {
+            Some(frequency) => mapped.scheduled.push((ynab_transaction, frequency)),
+            None => {
+                // The mapped date has no time and may be shifted, so it can't order transactions
+                if ynab_transaction.account_id.is_some()
+                    && latest_date_time.is_none_or(|latest| date_time >= latest)
+                {
+                    latest_date_time = Some(date_time);
+                    mapped.latest = Some(ynab_transaction.clone());
+                }
+                mapped.transactions.push(ynab_transaction)
+            }
+        }
+    }
+    if !unmapped_rows.is_empty() {
+        if !config.partial_import {
+            bail!(
+                "failed to map {} rows to YNAB transactions:\n{}",
+                unmapped_rows.len(),
+                unmapped_rows.join("\n")
+            );
+        }
+        mapped.skipped += unmapped_rows.len();
+        mapped.warnings.extend(
+            unmapped_rows
+                .iter()
+                .map(|row| format!("{row} (not imported)")),
+        );
+    }
+
+    // YNAB rejects imports with future transactions, so the marker waits until the period ends
+    let today =
+        (context.now + chrono::Duration::minutes(config.tz_offset_minutes.into())).date_naive();
+    mapped.statement_marker = yonder_csv
+        .statement_period
+        .filter(|(_, to)| config.statement_period_marker && *to < today)
+        .and_then(|(from, to)| statement_marker(from, to, config));
+
+    Ok(mapped)
+}
+
+/// Error for splits whose subtransactions don't add up to the transaction amount, which YNAB
+/// rejects
+fn split_mismatch(transaction: &NewTransaction) -> Option<String> {
+    let total = transaction
+        .subtransactions
+        .iter()
+        .map(|subtransaction| subtransaction.amount)
+        .sum::<i64>();
+    (!transaction.subtransactions.is_empty() && Some(total) != transaction.amount).then(|| {
+        format!(
+            "subtransactions of {:?} don't add up to its amount",
+            transaction
+                .payee_name
+                .as_ref()
+                .map_or("", |payee_name| payee_name.as_str())
+        )
+    })
+}
+
+/// Whether the transaction should be imported according to the config
+fn should_import(
+    transaction: &YonderTransaction,
+    config: &Config,
+    context: &ImportContext,
+) -> bool {
+    // Future transactions are usually pending authorizations
+    if !config.import_future_transactions && transaction.date_time.clone().utc() > context.now {
+        return false;
+    }
+
+    // Spending in other currencies can be tracked elsewhere
+    if !config.is_currency_allowed(transaction.charged_currency()) {
+        return false;
+    }
+
+    // Tiny transactions are not worth tracking
+    if transaction.amount(config.amount_scale()).abs() < gbp_milliunits(config.min_amount_gbp) {
+        return false;
+    }
+
+    true
+}
+
+/// Parse Yonder transacitons in CSV format and import to YNAB
+async fn import_yonder_csv_to_ynab(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> eyre::Result<DocumentResult> {
+    let (mapped, context) = prepare_import(yonder_csv, config, ynab_client, options).await?;
+    import_mapped(mapped, &context, config, ynab_client, options).await
+}
+
+/// Map Yonder transactions in CSV format for the import, applying the import options
+///
+/// Transactions known according to local state are kept, they're left out by `import_mapped`.
+async fn prepare_import(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> eyre::Result<(MappedCsv, ImportContext)> {
+    let group_category_ids = match &options.group_category_ids {
+        Some(group_category_ids) => group_category_ids.clone(),
+        None => resolve_category_groups(config, ynab_client).await?,
+    };
+    let context = ImportContext {
+        group_category_ids,
+        ..ImportContext::new(Utc::now())
+    };
+    let mut mapped = map_yonder_csv(yonder_csv, config, &context)?;
+    // Neither the default account nor account routes provided the account
+    if !mapped.unrouted.is_empty() {
+        if !config.partial_import {
+            bail!(
+                "failed to map {} rows to YNAB transactions:\n{}",
+                mapped.unrouted.len(),
+                mapped.unrouted.join("\n")
+            );
+        }
+        mapped
+            .transactions
+            .retain(|transaction| transaction.account_id.is_some());
+        mapped
+            .scheduled
+            .retain(|(transaction, _)| transaction.account_id.is_some());
+        mapped.skipped += mapped.unrouted.len();
+        mapped.warnings.extend(
+            mapped
+                .unrouted
+                .iter()
+                .map(|row| format!("{row} (not imported)")),
+        );
+    }
+    if options.latest_only {
+        mapped.transactions = mapped.latest.take().into_iter().collect();
+        mapped.scheduled.clear();
+    } else {
+        mapped.transactions.extend(mapped.statement_marker.take());
+    }
+    if let Some(cleared_status) = options.cleared_status {
+        for transaction in &mut mapped.transactions {
+            transaction.cleared = Some(cleared_status);
+        }
+    }
+    // Tagged transactions are never deduplicated against untagged ones
+    if let Some(tag) = &options.tag {
+        for (index, transaction) in mapped.transactions.iter_mut().enumerate() {
+            transaction.import_id = Some(format!("{tag}:{index}").parse().unwrap());
+            append_memo(transaction, tag);
+        }
+    }
+    Ok((mapped, context))
+}
+
+/// Import Yonder transactions mapped by `prepare_import` to YNAB
+async fn import_mapped(
+    mut mapped: MappedCsv,
+    context: &ImportContext,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    options: &ImportOptions,
+) -> eyre::Result<DocumentResult> {
+    // All YNAB requests of the import share the limit
+    let ynab_client = &LimitedYnab::new(ynab_client, config.ynab_max_concurrent_requests());
+    let known_count = mapped.transactions.len();
+    mapped.transactions.retain(|transaction| {
+        transaction
+            .import_id
+            .as_ref()
+            .is_none_or(|import_id| !options.known_import_ids.contains(import_id.as_str()))
+    });
+    let known_count = known_count - mapped.transactions.len();
+    // Transactions of new payees are left unapproved for review
+    if let Some(seen_payees) = &options.seen_payees {
+        for transaction in &mut mapped.transactions {
+            transaction.approved =
+                Some(transaction.payee_name.as_ref().is_some_and(|payee_name| {
+                    seen_payees.contains(&seen_payee(payee_name.as_str()))
+                }));
+        }
+    }
+    let payees = mapped
+        .transactions
+        .iter()
+        .filter_map(|transaction| transaction.payee_name.as_ref())
+        .map(|payee_name| payee_name.to_string())
+        .collect();
+    // Amounts are in pounds, so a budget in another currency would get them in the wrong one
+    if let Some(expected_currency) = &config.budget_currency {
+        let currency = ynab_client
+            .budget_currency(&config.ynab_budget_id)
+            .await
+            .wrap_err("failed to fetch YNAB budget currency")?;
+        if !currency.eq_ignore_ascii_case(expected_currency) {
+            let message = format!(
+                "YNAB budget currency {currency} doesn't match {ENV_BUDGET_CURRENCY} {expected_currency}"
+            );
+            match config.budget_currency_policy {
+                CurrencyMismatchPolicy::Warn => mapped.warnings.push(message),
+                CurrencyMismatchPolicy::Error => bail!(message),
+            }
+        }
+    }
+
+    // Fetched before the import, so its own transactions are never reported as changed
+    let changed_knowledge = match options.last_server_knowledge {
+        Some(last_knowledge) => {
+            let changes = ynab_client
+                .transaction_changes(&config.ynab_budget_id, last_knowledge)
+                .await?;
+            if changes.changed > 0 {
+                mapped.warnings.push(format!(
+                    "{} transactions changed in YNAB since the last import (server knowledge {last_knowledge} → {})",
+                    changes.changed, changes.server_knowledge
+                ));
+            }
+            Some(changes.server_knowledge)
+        }
+        None => None,
+    };
+
+    let (scheduled, scheduled_duplicates) = schedule_transactions(
+        mapped.scheduled,
+        config,
+        ynab_client,
+        context.now.date_naive(),
+    )
+    .await?;
+
+    // Import transactions to YNAB in batches, skipping the request if there's nothing to import.
+    // All batches complete, so a failed one doesn't discard those YNAB saved.
+    let batches = import_batches(mapped.transactions, config);
+    let batch_results = futures::future::join_all(
+        batches
+            .iter()
+            .map(|batch| ynab_client.create_transactions(&config.ynab_budget_id, batch.clone())),
+    )
+    .await;
+    let mut saved = SavedTransactions::default();
+    let mut server_knowledge = None;
+    let mut net = 0;
+    let mut failures = Vec::new();
+    for (batch, batch_saved) in batches.iter().zip(batch_results) {
+        let batch_saved = match batch_saved {
+            Ok(batch_saved) => batch_saved,
+            Err(err) => {
+                failures.push((batch.len(), err));
+                continue;
+            }
+        };
+        // Duplicates were not imported, so they don't count towards the net total
+        net += batch
+            .iter()
+            .filter(|transaction| {
+                transaction
+                    .import_id
+                    .as_ref()
+                    .is_none_or(|import_id| !batch_saved.duplicate_import_ids.contains(import_id))
+            })
+            .map(|transaction| transaction.amount.unwrap_or_default())
+            .sum::<i64>();
+        saved.transaction_ids.extend(batch_saved.transaction_ids);
+        saved
+            .duplicate_import_ids
+            .extend(batch_saved.duplicate_import_ids);
+        server_knowledge = server_knowledge.max(Some(batch_saved.server_knowledge));
+    }
+    // Nothing was saved, so the error is the outcome of the whole import
+    if failures.len() == batches.len() {
+        if let Some((_, err)) = failures.pop() {
+            return Err(err);
+        }
+    }
+    let mut errors: Vec<_> = failures
+        .into_iter()
+        .map(|(count, err)| format!("failed to import {count} transactions: {err}"))
+        .collect();
+    // YNAB saved the new transactions already, so the result is kept for the bookkeeping
+    if config.strict_no_duplicates && !saved.duplicate_import_ids.is_empty() {
+        errors.push(format!(
+            "YNAB reported {} duplicate transactions: {}",
+            saved.duplicate_import_ids.len(),
+            saved.duplicate_import_ids.join(", ")
+        ));
+    }
+
+    // Verify the account balance after the import
+    let balance = match options.expected_balance {
+        Some(expected) => Some(BalanceCheck {
+            expected,
+            actual: ynab_client
+                .account(&config.ynab_budget_id, config.account_id()?)
+                .await
+                .wrap_err("failed to fetch YNAB account balance")?
+                .balance,
+        }),
+        None => None,
+    };
+
+    let result = DocumentResult {
+        imported: saved.transaction_ids.len(),
+        transaction_ids: saved.transaction_ids,
+        duplicates: saved.duplicate_import_ids.len() + scheduled_duplicates + known_count,
+        duplicate_import_ids: saved.duplicate_import_ids,
+        skipped: mapped.skipped,
+        scheduled,
+        balance,
+        net,
+        warnings: mapped.warnings,
+        errors,
+        large_transactions: mapped.large,
+        upload_id: config.memo_upload_id.then(|| context.upload_id.clone()),
+        server_knowledge: server_knowledge.or(changed_knowledge),
+        payees,
+    };
+
+    if let (Some(execution_context), Some(summary_webhook_url)) = (
+        &options.execution_context,
+        config.summary_webhook_url.clone(),
+    ) {
+        let summary = serde_json::to_value(ImportSummary {
+            result: &result,
+            timestamp: context.now,
+        })?;
+        // The summary is informational, so it doesn't delay the response or fail the import
+        execution_context.wait_until(async move {
+            if let Err(err) = post_import_summary(summary_webhook_url, summary, post_json).await {
+                worker::console_warn!("Failed to post import summary: {err}");
+            }
+        });
+    }
+
+    Ok(result)
+}
+
+/// Split transactions into batches imported with separate YNAB requests
+///
+/// Batches are imported concurrently up to the YNAB request limit, so with a limit of one, daily
+/// batches appear in YNAB as separate imports in date order. No batch is larger than the batch
+/// size, splitting large days and months further.
+fn import_batches(transactions: Vec<NewTransaction>, config: &Config) -> Vec<Vec<NewTransaction>> {
+    if transactions.is_empty() {
+        return Vec::new();
+    }
+
+    let batches = match config.import_batching {
+        ImportBatching::Fixed => vec![transactions],
+        ImportBatching::Daily => group_batches(transactions, |date| date),
+        ImportBatching::Monthly => group_batches(transactions, |date| {
+            date.map(|date| (date.year(), date.month()))
+        }),
+    };
+    batches
+        .iter()
+        .flat_map(|batch| batch.chunks(config.import_batch_size()))
+        .map(<[NewTransaction]>::to_vec)
+        .collect()
+}
+
+/// Group transactions into batches by the key of their date, ordered by the key
+fn group_batches<K: Ord>(
+    transactions: Vec<NewTransaction>,
+    key: impl Fn(Option<NaiveDate>) -> K,
+) -> Vec<Vec<NewTransaction>> {
+    let mut batches = BTreeMap::<_, Vec<_>>::new();
+    for transaction in transactions {
+        batches
+            .entry(key(transaction.date))
+            .or_default()
+            .push(transaction);
+    }
+    batches.into_values().collect()
+}
+
+/// Create recurring transactions as scheduled transactions, returning the number of created and
+/// already scheduled ones
+///
+/// YNAB scheduled transactions have no import_id, so a transaction is considered already scheduled
+/// if there's one in the same account with the same payee and amount.
+async fn schedule_transactions(
+    transactions: Vec<(NewTransaction, ScheduledTransactionFrequency)>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    today: NaiveDate,
+) -> eyre::Result<(usize, usize)> {
+    if transactions.is_empty() {
+        return Ok((0, 0));
+    }
+
+    let mut existing = ynab_client
+        .scheduled_transactions(&config.ynab_budget_id)
+        .await
+        .wrap_err("failed to fetch YNAB scheduled transactions")?;
+    let mut new = Vec::new();
+    let mut duplicates = 0;
+    for (transaction, frequency) in transactions {
+        let scheduled = scheduled_transaction(transaction, frequency, today)?;
+        let summary = ScheduledTransaction {
+            account_id: scheduled.account_id,
+            amount: scheduled.amount.unwrap_or_default(),
+            payee_name: scheduled
+                .payee_name
+                .as_ref()
+                .map(|payee_name| payee_name.to_string()),
+        };
+        if existing.contains(&summary) {
+            duplicates += 1;
+            continue;
+        }
+
+        existing.push(summary);
+        new.push(scheduled);
+    }
+
+    // YNAB creates a single scheduled transaction per request
+    let created = futures::future::try_join_all(
+        new.into_iter()
+            .map(|scheduled| ynab_client.schedule_transaction(&config.ynab_budget_id, scheduled)),
+    )
+    .await?
+    .len();
+
+    Ok((created, duplicates))
+}
+
+/// Map transaction to a scheduled transaction starting at its next occurrence after today
+fn scheduled_transaction(
+    transaction: NewTransaction,
+    frequency: ScheduledTransactionFrequency,
+    today: NaiveDate,
+) -> eyre::Result<SaveScheduledTransaction> {
+    let account_id = transaction
+        .account_id
+        .ok_or_eyre("scheduled transaction has no account")?;
+    Ok(SaveScheduledTransaction {
+        account_id,
+        date: next_occurrence(transaction.date.unwrap_or(today), frequency, today),
+        amount: transaction.amount,
+        payee_id: None,
+        payee_name: transaction
+            .payee_name
+            .map(|payee_name| payee_name.to_string().parse().unwrap()),
+        // Scheduled transactions can't be split, so they keep the category of the main part
+        category_id: transaction.category_id.or_else(|| {
+            transaction
+                .subtransactions
+                .first()
+                .and_then(|subtransaction| subtransaction.category_id)
+        }),
+        memo: transaction
+            .memo
+            .map(|memo| memo.to_string().parse().unwrap()),
+        flag_color: transaction.flag_color,
+        frequency: Some(frequency),
+    })
+}
+
+/// First date after today of a transaction repeating from the date with the frequency
+///
+/// Transactions that never repeat are scheduled for tomorrow if their date has passed.
+fn next_occurrence(
+    date: NaiveDate,
+    frequency: ScheduledTransactionFrequency,
+    today: NaiveDate,
+) -> NaiveDate {
+    use ScheduledTransactionFrequency::*;
+
+    // Occurrences are counted from the original date, so month ends don't drift
+    let occurrence = |n: u32| {
+        let days = |interval: u64| date.checked_add_days(Days::new(interval * u64::from(n)));
+        let months = |interval: u32| date.checked_add_months(Months::new(interval * n));
+        match frequency {
+            Never => (n == 0).then_some(date),
+            Daily => days(1),
+            Weekly => days(7),
+            EveryOtherWeek => days(14),
+            // Approximated with a fixed interval, as the days of the month aren't known
+            TwiceAMonth => days(15),
+            Every4Weeks => days(28),
+            Monthly => months(1),
+            EveryOtherMonth => months(2),
+            Every3Months => months(3),
+            Every4Months => months(4),
+            TwiceAYear => months(6),
+            Yearly => months(12),
+            EveryOtherYear => months(24),
+        }
+    };
+
+    (0..)
+        .map_while(occurrence)
+        .find(|next| *next > today)
+        .unwrap_or_else(|| today.succ_opt().unwrap_or(today))
+}
+
+/// Transactions of an import categorized against local dedup state and YNAB
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct ImportDiff {
+    /// Transactions imported for the first time
+    new: Vec<DiffTransaction>,
+    /// Transactions imported before according to local dedup state, which were not sent to YNAB
+    local_duplicates: Vec<DiffTransaction>,
+    /// Transactions YNAB reported as already imported
+    ynab_duplicates: Vec<DiffTransaction>,
+    /// Problems that don't fail the import
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Transaction listed in the import diff
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct DiffTransaction {
+    date: Option<NaiveDate>,
+    payee_name: Option<String>,
+    /// Amount in milliunits
+    amount: i64,
+    import_id: String,
+}
+
+/// Import the CSV, categorizing transactions by whether they're new, imported before according to
+/// local dedup state, or reported as duplicates by YNAB
+///
+/// import_ids of new and YNAB duplicate transactions are recorded in the store for next imports,
+/// along with the state recorded by `record_import`.
+async fn import_diff(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    store: &impl Store,
+    options: &ImportOptions,
+) -> eyre::Result<ImportDiff> {
+    let known_import_ids = read_dedup_import_ids(store).await?;
+
+    let options = ImportOptions {
+        known_import_ids: known_import_ids.clone(),
+        ..options.clone()
+    };
+    // The transactions are the ones the import sends, with the same options applied
+    let (mapped, context) = prepare_import(yonder_csv, config, ynab_client, &options).await?;
+    let entries: Vec<_> = mapped
+        .transactions
+        .iter()
+        .filter_map(|transaction| {
+            Some(DiffTransaction {
+                date: transaction.date,
+                payee_name: transaction
+                    .payee_name
+                    .as_ref()
+                    .map(|payee_name| payee_name.to_string()),
+                amount: transaction.amount.unwrap_or_default(),
+                import_id: transaction.import_id.as_ref()?.to_string(),
+            })
+        })
+        .collect();
+    let result = import_mapped(mapped, &context, config, ynab_client, &options).await?;
+    record_import(config, Some(store), &result).await;
+
+    let mut diff = ImportDiff::default();
+    for entry in entries {
+        if known_import_ids.contains(&entry.import_id) {
+            diff.local_duplicates.push(entry);
+        } else if result.duplicate_import_ids.contains(&entry.import_id) {
+            diff.ynab_duplicates.push(entry);
+        } else {
+            diff.new.push(entry);
+        }
+    }
+
+    // A single put, as KV limits writes per request. Concurrent imports may drop each other's
+    // import IDs, which YNAB still deduplicates.
+    let mut import_ids = known_import_ids;
+    let count = import_ids.len();
+    import_ids.extend(
+        diff.new
+            .iter()
+            .chain(&diff.ynab_duplicates)
+            .map(|entry| entry.import_id.clone()),
+    );
+    if import_ids.len() > count {
+        let record = async {
+            store
+                .put(DEDUP_KEY, &serde_json::to_string(&import_ids)?, None)
+                .await
+        };
+        // YNAB saved the transactions already, so the import succeeded regardless
+        if let Err(err) = record.await {
+            diff.warnings.push(format!(
+                "failed to record import IDs for local deduplication: {err}"
+            ));
+        }
+    }
+
+    Ok(diff)
+}
+
+/// KV key of YNAB server knowledge recorded after the last import
+const SERVER_KNOWLEDGE_KEY: &str = "server_knowledge";
+
+/// Server knowledge recorded after the last import if changes in YNAB are tracked
+async fn last_server_knowledge(config: &Config, store: Option<&impl Store>) -> Option<i64> {
+    let store = store.filter(|_| config.track_server_knowledge)?;
+    match store.get(SERVER_KNOWLEDGE_KEY).await {
+        Ok(knowledge) => knowledge.and_then(|knowledge| knowledge.parse().ok()),
+        Err(err) => {
+            // Changes are only reported, so they're not worth failing the import
+            worker::console_warn!("Failed to read server knowledge: {err}");
+            None
+        }
+    }
+}
+
+/// Record the server knowledge after the import, so the next one only reports later changes
+async fn record_server_knowledge(
+    config: &Config,
+    store: Option<&impl Store>,
+    result: &DocumentResult,
+) {
+    let Some(store) = store.filter(|_| config.track_server_knowledge) else {
+        return;
+    };
+    let Some(knowledge) = result.server_knowledge else {
+        return;
+    };
+    if let Err(err) = store
+        .put(SERVER_KNOWLEDGE_KEY, &knowledge.to_string(), None)
+        .await
+    {
+        worker::console_warn!("Failed to record server knowledge: {err}");
+    }
+}
+
+/// Summary of the import posted to the summary webhook
+#[derive(Serialize)]
+struct ImportSummary<'a> {
+    #[serde(flatten)]
+    result: &'a DocumentResult,
+    timestamp: DateTime<Utc>,
+}
+
+/// POST the import summary as JSON to the summary webhook
+async fn post_import_summary<F>(
+    url: reqwest::Url,
+    summary: serde_json::Value,
+    post: impl FnOnce(reqwest::Url, serde_json::Value) -> F,
+) -> eyre::Result<()>
+where
+    F: Future<Output = eyre::Result<u16>>,
+{
+    let status = post(url, summary).await?;
+    if !(200..300).contains(&status) {
+        bail!("summary webhook responded with HTTP {status}");
+    }
+    Ok(())
+}
+
+/// POST JSON, returning the response status
+async fn post_json(url: reqwest::Url, json: serde_json::Value) -> eyre::Result<u16> {
+    let response = reqwest::Client::new().post(url).json(&json).send().await?;
+    Ok(response.status().as_u16())
+}
+
+/// Comparison of CSV and YNAB totals over a date range
+#[derive(Debug, Clone, PartialEq)]
+struct Reconciliation {
+    from: NaiveDate,
+    to: NaiveDate,
+    /// Total of CSV transactions in milliunits
+    csv_total: i64,
+    /// Total of YNAB account transactions in milliunits
+    ynab_total: i64,
+}
+
+impl Reconciliation {
+    fn discrepancy(&self) -> i64 {
+        self.csv_total - self.ynab_total
+    }
+}
+
+impl Display for Reconciliation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Period: {} to {}", self.from, self.to)?;
+        writeln!(f, "CSV total: {}", format_gbp(self.csv_total))?;
+        writeln!(f, "YNAB total: {}", format_gbp(self.ynab_total))?;
+        match self.discrepancy() {
+            0 => write!(f, "Totals match"),
+            discrepancy => write!(f, "Discrepancy: {}", format_gbp(discrepancy)),
+        }
+    }
+}
+
+/// Compare the total of CSV transactions with YNAB account transactions in the date range
+///
+/// The range defaults to the dates of the CSV transactions.
+async fn reconcile_yonder_csv(
+    yonder_csv: impl AsRef<[u8]>,
+    config: &Config,
+    ynab_client: &impl YnabApi,
+    range: Option<(NaiveDate, NaiveDate)>,
+) -> eyre::Result<Reconciliation> {
+    let account_id = config.account_id()?;
+    let context = ImportContext::new(Utc::now());
+    let mapped = map_yonder_csv(yonder_csv, config, &context)?;
+
+    // Only transactions of the configured account are compared
+    let transactions: Vec<_> = mapped
+        .transactions
+        .into_iter()
+        .filter(|transaction| transaction.account_id == Some(account_id))
+        .filter_map(|transaction| Some((transaction.date?, transaction.amount.unwrap_or_default())))
+        .collect();
+
+    let (from, to) = match range {
+        Some(range) => range,
+        None => {
+            let dates = transactions.iter().map(|(date, _)| *date);
+            (
+                dates.clone().min().ok_or_eyre("no transactions found")?,
+                dates.max().ok_or_eyre("no transactions found")?,
+            )
+        }
+    };
+    let in_range = |date: &NaiveDate| (from..=to).contains(date);
+
+    let csv_total = transactions
+        .iter()
+        .filter(|(date, _)| in_range(date))
+        .map(|(_, amount)| amount)
+        .sum();
+    let ynab_total = ynab_client
+        .account_transactions(&config.ynab_budget_id, account_id, Some(from))
+        .await
+        .wrap_err("failed to fetch YNAB account transactions")?
+        .iter()
+        .filter(|transaction| in_range(&transaction.date))
+        .map(|transaction| transaction.amount)
+        .sum();
+
+    Ok(Reconciliation {
+        from,
+        to,
+        csv_total,
+        ynab_total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        future::Future,
+        pin::Pin,
+        task::Poll,
+        time::Duration,
+    };
+
+    use chrono::{NaiveDate, Weekday};
+    use eyre::OptionExt;
+    use tgbot_worker_rs::frankenstein::ParseMode;
+    use uuid::Uuid;
+
+    use crate::{
+        archive::memory::MemoryArchive,
+        archive_upload, cached_category_groups, chat_cleared_status, cleared_status_key,
+        config::{
+            AccountRoute, CategoryRule, CategorySplit, Config, CurrencyMismatchPolicy,
+            DescriptionCase, FlagPrecedence, FooterRowPolicy, ImportBatching, ImportIdField,
+            ImportIdVersion, MemoField, MessageParseMode, ScheduledRule, SignMismatchPolicy,
+            SplitShare,
+        },
+        cors_headers, decode_webhook_body, download_source_csv, error_json, escape_reply,
+        extract_csv, fits_telegram_message, format_gbp, format_preview_rows, format_preview_table,
+        hashed_flag_color, import_batches, import_diff, import_document, import_latest_transaction,
+        import_manifest, import_mapped, import_result_json, import_yonder_csv_to_ynab, inline_csv,
+        is_rate_limited, last_server_knowledge, map_transaction, map_yonder_csv,
+        map_yonder_csv_with_groups, next_occurrence, on_telegram_balance, on_telegram_categorize,
+        on_telegram_cleared, on_telegram_command, on_telegram_refresh, on_telegram_reset,
+        onboarding_message, oversized_document_message, parse_command, parse_date_range,
+        parse_yonder_csv, post_import_summary, preview, read_body, read_dedup_import_ids,
+        read_seen_payees, recent_transactions, reconcile_yonder_csv, record_imported_ids,
+        record_seen_payees, record_server_knowledge, reset_dedup, resolve_category_groups,
+        retry_with_backoff, seen_payee, seen_payees, self_test, split_message,
+        store::{memory::MemoryStore, Store},
+        transcode_utf16, validate_rules, validate_source_url, version, versioned_json,
+        webhook_error, webhook_import_status,
+        ynab::types::{
+            NewTransaction, SaveScheduledTransaction, ScheduledTransactionFrequency,
+            TransactionClearedStatus, TransactionFlagColor,
+        },
+        ynab_api::{
+            budget_error, ynab_error, AccountSummary, AccountTransaction, CategoryBalance,
+            SavedTransactions, ScheduledTransaction, TransactionChanges, YnabApi,
+        },
+        ynab_import_csv, BalanceCheck, BodyTooLarge, DiffTransaction, DocumentResult,
+        ImportContext, ImportIdBuilder, ImportOptions, ImportSummary, ManifestFile, MappedCsv,
+        Preview, Reconciliation, ResponseFormat, SourceResponse, WebhookBody, YonderTransaction,
+        YonderTransactionDateTime, YonderTransactionKind, COMMAND_NOT_ALLOWED_MESSAGE, DEDUP_KEY,
+        ONBOARDING_MESSAGE, PREVIEW_MAX_ROWS, PREVIEW_PAYEE_WIDTH, SELFTEST_CSV,
+        SERVER_KNOWLEDGE_KEY, WEBHOOK_BODY_MAX_SIZE,
+    };
+
+    /// In-memory YNAB API recording created transactions and deduplicating them by import_id
+    #[derive(Default)]
+    struct MockYnab {
+        transactions: RefCell<Vec<NewTransaction>>,
+        scheduled: RefCell<Vec<SaveScheduledTransaction>>,
+        /// IDs of created transactions, in creation order
+        transaction_ids: RefCell<Vec<String>>,
+        deleted: RefCell<Vec<String>>,
+        account_name: String,
+        balance: i64,
+        account_requests: Cell<usize>,
+        /// Scheduled transaction requests in flight
+        in_flight: Cell<usize>,
+        max_in_flight: Cell<usize>,
+        /// Reject requests like YNAB does with an invalid API key
+        unauthorized: bool,
+        /// Reject requests like YNAB does for an archived budget
+        budget_archived: bool,
+        categories: Vec<CategoryBalance>,
+        /// Knowledge of the YNAB server at each transaction change, in order
+        changes: RefCell<Vec<i64>>,
+        /// ISO code of the budget currency, `GBP` if empty
+        currency: String,
+        /// Transaction IDs with the categories set to them, in order
+        categorized: RefCell<Vec<(String, Uuid)>>,
+        /// Reject batches with a transaction of this payee, like a failed request
+        failing_payee: Option<String>,
+    }
+
+    impl MockYnab {
+        /// Change a transaction like editing it in YNAB, returning the new server knowledge
+        fn change_transaction(&self) -> i64 {
+            let mut changes = self.changes.borrow_mut();
+            let knowledge = changes.last().copied().unwrap_or_default() + 1;
+            changes.push(knowledge);
+            knowledge
+        }
+    }
+
+    /// Future yielding to the executor once, so that concurrent requests overlap
+    #[derive(Default)]
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    impl MockYnab {
+        /// Keep a request in flight until the executor polls other requests, recording the most
+        /// requests in flight at once
+        async fn request_in_flight(&self) {
+            self.in_flight.set(self.in_flight.get() + 1);
+            self.max_in_flight
+                .set(self.max_in_flight.get().max(self.in_flight.get()));
+            YieldNow::default().await;
+            self.in_flight.set(self.in_flight.get() - 1);
+        }
+    }
+
+    impl YnabApi for MockYnab {
+        async fn create_transactions(
+            &self,
+            _budget_id: &str,
+            transactions: Vec<NewTransaction>,
+        ) -> eyre::Result<SavedTransactions> {
+            if self.unauthorized {
+                return Err(ynab_error(
+                    Some(reqwest::StatusCode::UNAUTHORIZED),
+                    "Unauthorized".to_string(),
+                ));
+            }
+            if self.budget_archived {
+                return Err(budget_error(
+                    Some(reqwest::StatusCode::NOT_FOUND),
+                    "Not Found".to_string(),
+                ));
+            }
+            self.request_in_flight().await;
+            if transactions.iter().any(|transaction| {
+                transaction.payee_name.as_ref().is_some_and(|payee_name| {
+                    Some(payee_name.as_str()) == self.failing_payee.as_deref()
+                })
+            }) {
+                eyre::bail!("request failed");
+            }
+            let mut saved = SavedTransactions::default();
+            let mut existing = self.transactions.borrow_mut();
+            for transaction in transactions {
+                let import_id = transaction.import_id.clone();
+                // Like YNAB, skip transactions with already imported import_id
+                if import_id.is_some()
+                    && existing
+                        .iter()
+                        .any(|existing| existing.import_id == import_id)
+                {
+                    saved
+                        .duplicate_import_ids
+                        .extend(import_id.map(|import_id| import_id.to_string()));
+                } else {
+                    let transaction_id = Uuid::new_v4().to_string();
+                    self.transaction_ids
+                        .borrow_mut()
+                        .push(transaction_id.clone());
+                    saved.transaction_ids.push(transaction_id);
+                    existing.push(transaction);
+                    saved.server_knowledge = self.change_transaction();
+                }
+            }
+            saved.server_knowledge = saved
+                .server_knowledge
+                .max(self.changes.borrow().last().copied().unwrap_or_default());
+            Ok(saved)
+        }
+
+        async fn account(
+            &self,
+            _budget_id: &str,
+            _account_id: Uuid,
+        ) -> eyre::Result<AccountSummary> {
+            self.account_requests.set(self.account_requests.get() + 1);
+            Ok(AccountSummary {
+                name: self.account_name.clone(),
+                balance: self.balance,
+                closed: false,
+            })
+        }
+
+        async fn account_transactions(
+            &self,
+            _budget_id: &str,
+            account_id: Uuid,
+            since_date: Option<NaiveDate>,
+        ) -> eyre::Result<Vec<AccountTransaction>> {
+            Ok(self
+                .transactions
+                .borrow()
+                .iter()
+                .enumerate()
+                .filter(|(_, transaction)| transaction.account_id == Some(account_id))
+                .filter(|(_, transaction)| {
+                    since_date.is_none_or(|since| transaction.date >= Some(since))
+                })
+                .map(|(index, transaction)| AccountTransaction {
+                    id: index.to_string(),
+                    date: transaction.date.unwrap_or_default(),
+                    amount: transaction.amount.unwrap_or_default(),
+                    payee_name: transaction.payee_name.as_ref().map(|name| name.to_string()),
+                    import_id: transaction.import_id.as_ref().map(|id| id.to_string()),
+                })
+                .collect())
+        }
+
+        async fn scheduled_transactions(
+            &self,
+            _budget_id: &str,
+        ) -> eyre::Result<Vec<ScheduledTransaction>> {
+            Ok(self
+                .scheduled
+                .borrow()
+                .iter()
+                .map(|transaction| ScheduledTransaction {
+                    account_id: transaction.account_id,
+                    amount: transaction.amount.unwrap_or_default(),
+                    payee_name: transaction.payee_name.as_ref().map(|name| name.to_string()),
+                })
+                .collect())
+        }
+
+        async fn schedule_transaction(
+            &self,
+            _budget_id: &str,
+            transaction: SaveScheduledTransaction,
+        ) -> eyre::Result<String> {
+            self.request_in_flight().await;
+            self.scheduled.borrow_mut().push(transaction);
+            Ok(Uuid::new_v4().to_string())
+        }
+
+        async fn delete_transaction(
+            &self,
+            _budget_id: &str,
+            transaction_id: &str,
+        ) -> eyre::Result<()> {
+            self.deleted.borrow_mut().push(transaction_id.to_string());
+            Ok(())
+        }
+
+        async fn categories(&self, _budget_id: &str) -> eyre::Result<Vec<CategoryBalance>> {
+            Ok(self.categories.clone())
+        }
+
+        async fn transaction_changes(
+            &self,
+            _budget_id: &str,
+            last_knowledge_of_server: i64,
+        ) -> eyre::Result<TransactionChanges> {
+            let changes = self.changes.borrow();
+            Ok(TransactionChanges {
+                changed: changes
+                    .iter()
+                    .filter(|knowledge| **knowledge > last_knowledge_of_server)
+                    .count(),
+                server_knowledge: changes.last().copied().unwrap_or_default(),
+            })
+        }
+
+        async fn budget_currency(&self, _budget_id: &str) -> eyre::Result<String> {
+            Ok(if self.currency.is_empty() {
+                "GBP".to_string()
+            } else {
+                self.currency.clone()
+            })
+        }
+
+        async fn categorize_transactions(
+            &self,
+            _budget_id: &str,
+            transaction_ids: &[String],
+            category_id: Uuid,
+        ) -> eyre::Result<usize> {
+            self.categorized.borrow_mut().extend(
+                transaction_ids
+                    .iter()
+                    .map(|transaction_id| (transaction_id.clone(), category_id)),
+            );
+            Ok(transaction_ids.len())
+        }
+    }
+
+    const YONDER_CSV_HEADER: &str = r#""Date/Time of transaction","Description","Amount (GBP)","Amount (in Charged Currency)","Currency","Category","Debit or Credit","Country""#;
+
+    /// Build Yonder CSV from the header and given rows
+    fn yonder_csv(rows: &[&str]) -> String {
+        std::iter::once(YONDER_CSV_HEADER)
+            .chain(rows.iter().copied())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Config with the default YNAB account required for imports
+    fn test_config() -> Config {
+        Config {
+            ynab_account_id: Some(Uuid::nil()),
+            ..Default::default()
+        }
+    }
+
+    fn test_context() -> eyre::Result<ImportContext> {
+        Ok(ImportContext::new("2026-01-02T12:00:00Z".parse()?))
+    }
+
+    #[test]
+    fn test_parse_yonder() -> eyre::Result<()> {
+        let yonder_transactions: Vec<YonderTransaction> =
+            csv::Reader::from_reader(std::fs::read("yonder.csv")?.as_slice())
+                .into_deserialize()
+                .collect::<Result<_, _>>()?;
+
+        assert_eq!(
+            yonder_transactions,
+            vec![
+                YonderTransaction {
+                    date_time: YonderTransactionDateTime::Utc(
+                        "2026-01-01T10:34:50.211697Z".parse()?
+                    ),
+                    description: "TFL - Transport for London".to_string(),
+                    amount_gbp: 3.0,
+                    amount_charged: 3.0,
+                    currency: "GBP".to_string(),
+                    category: "Transport".to_string(),
+                    kind: YonderTransactionKind::Debit,
+                    country: "GBR".to_string(),
+                    channel: None,
+                    pending_amount_gbp: None,
+                    settled_amount_gbp: None,
+                    unsettled_amount_gbp: None,
+                    line: 0,
+                },
+                YonderTransaction {
+                    date_time: YonderTransactionDateTime::Naive(
+                        "2026-01-01T10:34:50.211697".parse()?
+                    ),
+                    description: "TFL - Transport for London".to_string(),
+                    amount_gbp: 3.00,
+                    amount_charged: 3.00,
+                    currency: "GBP".to_string(),
+                    category: "Transport".to_string(),
+                    kind: YonderTransactionKind::Debit,
+                    country: "GBR".to_string(),
+                    channel: None,
+                    pending_amount_gbp: None,
+                    settled_amount_gbp: None,
+                    unsettled_amount_gbp: None,
+                    line: 0,
+                }
+            ]
+        );
+
+        assert_eq!(
+            NewTransaction::from(yonder_transactions[0].clone()),
+            NewTransaction::from(yonder_transactions[1].clone())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ynab_import_id_length() -> eyre::Result<()> {
+        let yonder_transactions: Vec<YonderTransaction> =
+            csv::Reader::from_reader(std::fs::read("yonder.csv")?.as_slice())
+                .into_deserialize()
+                .collect::<Result<_, _>>()?;
+
+        for transaction in yonder_transactions {
+            let import_id = NewTransaction::from(transaction).import_id;
+            assert!(
+                import_id.expect("import_id must be set").len() < 36,
+                "import_id must be no longer than 36 characters"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_result_headers() {
+        let result = DocumentResult {
+            imported: 3,
+            transaction_ids: vec![],
+            duplicates: 1,
+            duplicate_import_ids: vec![],
+            skipped: 0,
+            scheduled: 0,
+            balance: None,
+            net: 0,
+            warnings: vec![],
+            errors: vec![],
+            large_transactions: vec![],
+            upload_id: None,
+            server_knowledge: None,
+            payees: vec![],
+        };
+
+        assert_eq!(
+            result.headers(),
+            [
+                ("X-Imported-Count", "3".to_string()),
+                ("X-Duplicate-Count", "1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_memo() -> eyre::Result<()> {
+        let config = Config {
+            default_memo: Some("Imported via bot on {date}".to_string()),
+            ..Default::default()
+        };
+
+        let transactions = parse_yonder_csv(std::fs::read("yonder.csv")?)?.transactions;
+        let transaction = map_transaction(transactions[0].clone(), &config, &test_context()?);
+
+        assert_eq!(
+            transaction.memo.as_ref().map(|memo| memo.as_str()),
+            Some("Imported via bot on 2026-01-02")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_concatenated_yonder() -> eyre::Result<()> {
+        let csv = std::fs::read_to_string("yonder.csv")?;
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap_or_default();
+        let rows: Vec<_> = lines.collect();
+        let concatenated = format!("{header}\n{}\n{header}\n{}\n", rows[0], rows[1]);
+
+        let yonder_csv = parse_yonder_csv(concatenated)?;
+
+        assert_eq!(yonder_csv.transactions.len(), 2);
+        assert_eq!(yonder_csv.skipped, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tz_offset() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-06-01T23:30:00","Pub","5.00","5.00","GBP","Entertainment","Debit","GBR""#,
+        ]))?
+        .transactions;
+
+        let utc = map_transaction(
+            transactions[0].clone(),
+            &Config::default(),
+            &test_context()?,
+        );
+        assert_eq!(utc.date, Some("2026-06-01".parse()?));
+
+        let config = Config {
+            tz_offset_minutes: 60,
+            ..Default::default()
+        };
+        let shifted = map_transaction(transactions[0].clone(), &config, &test_context()?);
+        assert_eq!(shifted.date, Some("2026-06-02".parse()?));
+        assert_eq!(shifted.import_id, utc.import_id);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_command() {
+        let config = Config {
+            ynab_api_key: "ynab-secret".to_string(),
+            telegram_allowed_chat_ids: vec![42],
+            ..Default::default()
+        };
+
+        assert_eq!(parse_command("/debug@yonder_bot"), Some(("debug", "")));
+
+        let reply = on_telegram_command(&config, 42, "debug", "").expect("debug is a command");
+        assert!(reply.contains("YNAB_API_KEY: set"));
+        assert!(!reply.contains("ynab-secret"));
+
+        let reply = on_telegram_command(&config, 1, "debug", "").expect("debug is a command");
+        assert!(!reply.contains("YNAB_API_KEY"));
+    }
+
+    #[test]
+    fn test_extract_zipped_csv() -> eyre::Result<()> {
+        use std::io::Write;
+
+        fn zip(files: &[(&str, &[u8])]) -> eyre::Result<Vec<u8>> {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for (name, content) in files {
+                writer.start_file(*name, zip::write::SimpleFileOptions::default())?;
+                writer.write_all(content)?;
+            }
+            Ok(writer.finish()?.into_inner())
+        }
+
+        let csv = std::fs::read("yonder.csv")?;
+
+        let archive = zip(&[("yonder.csv", &csv)])?;
+        let yonder_csv = parse_yonder_csv(extract_csv(&archive)?)?;
+        assert_eq!(yonder_csv.transactions.len(), 2);
+
+        assert_eq!(extract_csv(&csv)?.as_ref(), csv.as_slice());
+
+        let archive = zip(&[("a.csv", &csv), ("b.csv", &csv)])?;
+        assert!(extract_csv(&archive).is_err());
+
+        let archive = zip(&[("readme.txt", b"hello")])?;
+        assert!(extract_csv(&archive).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_balance_check() -> eyre::Result<()> {
+        let ynab = MockYnab {
+            balance: -6000,
+            ..Default::default()
+        };
+        let csv = std::fs::read("yonder.csv")?;
+        let import = |expected_balance| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                &test_config(),
+                &ynab,
+                &ImportOptions {
+                    expected_balance,
+                    ..Default::default()
+                },
+            ))
+        };
+
+        let result = import(None)?;
+        assert_eq!(result.balance, None);
+
+        let result = import(Some(-6000))?;
+        assert_eq!(
+            result.balance,
+            Some(BalanceCheck {
+                expected: -6000,
+                actual: -6000
+            })
+        );
+        assert!(result
+            .to_string()
+            .contains("Account balance matches: -£6.00"));
+
+        let result = import(Some(-3000))?;
+        assert!(result
+            .to_string()
+            .contains("WARNING: account balance -£6.00 differs from expected -£3.00 by -£3.00"));
+
+        assert_eq!(format_gbp(-123450), "-£123.45");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_webhook_json_body() -> eyre::Result<()> {
+        use base64::{prelude::BASE64_STANDARD, Engine};
+
+        let csv = std::fs::read("yonder.csv")?;
+        let body = serde_json::to_vec(&serde_json::json!({
+            "csv_base64": BASE64_STANDARD.encode(&csv)
+        }))?;
+
+        let decoded = decode_webhook_body(Some("application/json; charset=utf-8"), body)?;
+        assert_eq!(decoded, WebhookBody::Csv(csv.clone()));
+
+        assert_eq!(
+            decode_webhook_body(Some("text/csv"), csv.clone())?,
+            WebhookBody::Csv(csv.clone())
+        );
+        assert_eq!(
+            decode_webhook_body(None, csv.clone())?,
+            WebhookBody::Csv(csv.clone())
+        );
+
+        assert!(decode_webhook_body(
+            Some("application/json"),
+            br#"{"csv_base64": "not base64!"}"#.to_vec()
+        )
+        .is_err());
+        assert!(decode_webhook_body(Some("application/json"), b"{}".to_vec()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunked_webhook_body() -> eyre::Result<()> {
+        let csv = std::fs::read("yonder.csv")?;
+        // Chunked transfer encoding delivers the body in chunks without a Content-Length
+        let chunks = || {
+            futures::stream::iter(
+                csv.chunks(7)
+                    .map(|chunk| Ok(chunk.to_vec()))
+                    .collect::<Vec<worker::Result<_>>>(),
+            )
+        };
+
+        let body = futures::executor::block_on(read_body(chunks(), csv.len()))?;
+        assert_eq!(body, csv);
+        let WebhookBody::Csv(body) = decode_webhook_body(Some("text/csv"), body)? else {
+            panic!("expected CSV body");
+        };
+        assert!(!parse_yonder_csv(body)?.transactions.is_empty());
+
+        let err = futures::executor::block_on(read_body(chunks(), csv.len() - 1)).unwrap_err();
+        assert!(err.is::<BodyTooLarge>());
+
+        let err = futures::executor::block_on(read_body(
+            futures::stream::iter(vec![
+                Ok(csv.clone()),
+                Err(worker::Error::RustError("connection reset".to_string())),
+            ]),
+            csv.len(),
+        ))
+        .unwrap_err();
+        assert!(!err.is::<BodyTooLarge>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_id_version() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50.211697","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-03-01T10:34:50.211697","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let import_ids = |config: &Config| -> eyre::Result<Vec<String>> {
+            transactions
+                .iter()
+                .map(|transaction| {
+                    let transaction =
+                        map_transaction(transaction.clone(), config, &test_context()?);
+                    Ok(transaction
+                        .import_id
+                        .expect("import_id must be set")
+                        .to_string())
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            import_ids(&Config::default())?,
+            ["TG:-3000:1767263690211", "TG:-3000:1772361290211"]
+        );
+
+        let config = Config {
+            import_id_version: ImportIdVersion::V2,
+            ..Default::default()
+        };
+        let v2 = import_ids(&config)?;
+        assert_eq!(v2, ["TG:2:-3000:1767263690211", "TG:2:-3000:1772361290211"]);
+        assert!(v2.iter().all(|import_id| import_id.len() <= 36));
+
+        let config = Config {
+            import_id_version: ImportIdVersion::V2,
+            import_id_transition_until: Some("2026-02-01".parse()?),
+            ..Default::default()
+        };
+        assert_eq!(
+            import_ids(&config)?,
+            ["TG:-3000:1767263690211", "TG:2:-3000:1772361290211"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generic_description_payee() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","CONTACTLESS PAYMENT","12.00","12.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            generic_descriptions: vec!["Contactless Payment".to_string()],
+            ..Default::default()
+        };
+
+        let payees: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| -> eyre::Result<_> {
+                Ok(map_transaction(transaction, &config, &test_context()?)
+                    .payee_name
+                    .map(|payee_name| payee_name.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        assert_eq!(
+            payees,
+            [Some("Groceries".to_string()), Some("Tesco".to_string())]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limit() -> eyre::Result<()> {
+        let store = MemoryStore::default();
+        let now = "2026-01-02T12:00:00Z".parse()?;
+        let is_rate_limited = |client, limit| {
+            futures::executor::block_on(is_rate_limited(&store, client, limit, now))
+        };
+
+        assert!(!is_rate_limited("key-a", 2));
+        assert!(!is_rate_limited("key-a", 2));
+        assert!(is_rate_limited("key-a", 2));
+        assert!(!is_rate_limited("key-b", 2));
+        assert!(!is_rate_limited("key-a", 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_balance_adjustment() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Balance Correction","1.50","1.50","GBP","","Credit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            adjustment_descriptions: vec!["balance correction".to_string()],
+            adjustment_payee: "Balance Adjustment".to_string(),
+            adjustment_flag_color: Some("yellow".parse()?),
+            ..Default::default()
+        };
+
+        let transaction = map_transaction(transactions[0].clone(), &config, &test_context()?);
+        assert_eq!(
+            transaction
+                .payee_name
+                .map(|payee_name| payee_name.to_string()),
+            Some("Balance Adjustment".to_string())
+        );
+        assert_eq!(transaction.flag_color, Some("yellow".parse()?));
+        assert_eq!(transaction.amount, Some(1500));
+
+        let transaction = map_transaction(
+            transactions[0].clone(),
+            &Config::default(),
+            &test_context()?,
+        );
+        assert_eq!(transaction.flag_color, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_table() -> eyre::Result<()> {
+        let mapped = map_yonder_csv(
+            yonder_csv(&[
+                r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+                r#""2026-01-02T10:34:50","M&S <Food>","12.50","12.50","GBP","Groceries","Credit","GBR""#,
+            ]),
+            &Config::default(),
+            &test_context()?,
+        )?;
+
+        assert_eq!(
+            format_preview_table(&format_preview_rows(&mapped.transactions, PREVIEW_MAX_ROWS)),
+            "<pre>Date        Payee                         Amount
+2026-01-01  TFL - Transport for Lond      -£3.00
+2026-01-02  M&amp;S &lt;Food&gt;                    £12.50</pre>"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_help_command() {
+        let reply =
+            on_telegram_command(&Config::default(), 1, "start", "").expect("start is a command");
+        assert!(reply.contains("Download CSV"));
+        assert_eq!(
+            on_telegram_command(&Config::default(), 1, "help", ""),
+            Some(reply)
+        );
+        assert_eq!(
+            on_telegram_command(&Config::default(), 1, "unknown", ""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_inline_csv() -> eyre::Result<()> {
+        let csv = std::fs::read_to_string("yonder.csv")?;
+        let csv = csv.trim();
+
+        assert_eq!(inline_csv(csv), Some(csv));
+        assert_eq!(inline_csv(&format!("```csv\n{csv}\n```")), Some(csv));
+        assert_eq!(inline_csv(&format!("```\n{csv}\n```")), Some(csv));
+        assert_eq!(inline_csv("hello"), None);
+
+        let text = format!("```{csv}```");
+        let inline = inline_csv(&text).expect("inline CSV");
+        assert_eq!(parse_yonder_csv(inline)?.transactions.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_route_memo_hint() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","AWS EMEA","20.00","20.00","GBP","Business","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let business_account_id = Uuid::new_v4();
+        let config = Config {
+            account_routes: vec![AccountRoute {
+                name: "business".to_string(),
+                account_id: business_account_id,
+                description_contains: Some("aws".to_string()),
+                category: None,
+            }],
+            memo_route_hint: true,
+            ..Default::default()
+        };
+
+        let routed = map_transaction(transactions[0].clone(), &config, &test_context()?);
+        assert_eq!(routed.account_id, Some(business_account_id));
+        assert_eq!(
+            routed.memo.as_ref().map(|memo| memo.as_str()),
+            Some("routed: business")
+        );
+
+        let default = map_transaction(transactions[1].clone(), &config, &test_context()?);
+        assert_eq!(default.account_id, config.ynab_account_id);
+        assert_eq!(default.memo, None);
+
+        let config = Config {
+            memo_route_hint: false,
+            ..config
+        };
+        let routed = map_transaction(transactions[0].clone(), &config, &test_context()?);
+        assert_eq!(routed.account_id, Some(business_account_id));
+        assert_eq!(routed.memo, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cors_headers() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://example.com".to_string()],
+            ..Default::default()
+        };
+
+        let headers = cors_headers(&config, Some("https://example.com"));
+        assert!(headers.contains(&(
+            "Access-Control-Allow-Origin",
+            "https://example.com".to_string()
+        )));
+        assert!(headers.contains(&(
+            "Access-Control-Allow-Methods",
+            "POST, HEAD, OPTIONS".to_string()
+        )));
+        assert!(headers.contains(&("Access-Control-Allow-Headers", "Content-Type".to_string())));
+
+        assert!(cors_headers(&config, Some("https://evil.com")).is_empty());
+        assert!(cors_headers(&config, None).is_empty());
+        assert!(cors_headers(&Config::default(), Some("https://example.com")).is_empty());
+
+        let config = Config {
+            cors_allowed_origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        assert!(cors_headers(&config, Some("https://evil.com")).contains(&(
+            "Access-Control-Allow-Origin",
+            "https://evil.com".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_skip_future_transactions() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-05T10:34:50","Shell","40.00","40.00","GBP","Transport","Debit","GBR""#,
+        ]);
+
+        let mapped = map_yonder_csv(&csv, &Config::default(), &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 1);
+        assert_eq!(mapped.transactions[0].date, Some("2026-01-01".parse()?));
+        assert_eq!(mapped.skipped, 1);
+
+        let config = Config {
+            import_future_transactions: true,
+            ..Default::default()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 2);
+        assert_eq!(mapped.skipped, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_total() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-02T10:34:50","Refund","10.25","10.25","GBP","Shopping","Credit","GBR""#,
+            r#""2026-01-03T10:34:50","Tesco","130.75","130.75","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let ynab = MockYnab::default();
+        let import = || {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                &test_config(),
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        let result = import()?;
+        assert_eq!(result.net, -123500);
+        assert!(result.to_string().contains("Net: -£123.50"));
+
+        // Duplicates are not counted
+        let result = import()?;
+        assert_eq!(result.duplicates, 3);
+        assert_eq!(result.net, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_flag_colors() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Dishoom","45.00","45.00","GBP","Dining","Debit","GBR""#,
+            r#""2026-01-01T20:34:50","Le Bistro","30.00","35.00","EUR","Dining","Debit","FRA""#,
+            r#""2026-01-01T21:34:50","Louvre","15.00","17.50","EUR","Entertainment","Debit","FRA""#,
+        ]))?
+        .transactions;
+        let mut config = Config {
+            category_flag_colors: [("dining".to_string(), "red".parse()?)].into(),
+            foreign_flag_color: Some("blue".parse()?),
+            ..Default::default()
+        };
+        let flags = |config: &Config| -> eyre::Result<Vec<_>> {
+            transactions
+                .iter()
+                .map(|transaction| {
+                    Ok(map_transaction(transaction.clone(), config, &test_context()?).flag_color)
+                })
+                .collect()
+        };
+
+        let red = Some("red".parse::<TransactionFlagColor>()?);
+        let blue = Some("blue".parse::<TransactionFlagColor>()?);
+
+        assert_eq!(flags(&config)?, [red.clone(), red.clone(), blue.clone()]);
+
+        config.flag_precedence = FlagPrecedence::Foreign;
+        assert_eq!(flags(&config)?, [red, blue.clone(), blue]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_weekday_flag_colors() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-05T10:34:50","Tesco","12.00","12.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-06T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-05T20:34:50","Dishoom","45.00","45.00","GBP","Dining","Debit","GBR""#,
+            r#""2026-01-04T23:34:50","Tesco","20.00","20.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            category_flag_colors: [("dining".to_string(), "red".parse()?)].into(),
+            weekday_flag_colors: vec![(Weekday::Mon, "green".parse()?)],
+            tz_offset_minutes: 60,
+            ..Default::default()
+        };
+        let flags = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?).flag_color)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let green = Some("green".parse::<TransactionFlagColor>()?);
+        let red = Some("red".parse::<TransactionFlagColor>()?);
+
+        // Monday is flagged, other flags take precedence, and Sunday night in UTC is Monday at
+        // UTC+1, e.g. CET in January
+        assert_eq!(flags, [green.clone(), None, red, green]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_url() -> eyre::Result<()> {
+        let config = Config {
+            source_url_allowed_hosts: vec!["docs.google.com".to_string()],
+            ..Default::default()
+        };
+
+        let source_url = validate_source_url(
+            &config,
+            "https://docs.google.com/spreadsheets/d/abc/export?format=csv",
+        )?;
+        assert_eq!(source_url.host_str(), Some("docs.google.com"));
+
+        assert!(validate_source_url(&config, "http://docs.google.com/export").is_err());
+        assert!(validate_source_url(&config, "https://169.254.169.254/latest").is_err());
+        assert!(validate_source_url(&config, "https://docs.google.com.evil.com/").is_err());
+        assert!(validate_source_url(&Config::default(), "https://docs.google.com/").is_err());
+        assert!(validate_source_url(&config, "not a url").is_err());
+
+        let config = Config {
+            source_url_allowed_hosts: vec!["*.googleusercontent.com".to_string()],
+            ..Default::default()
+        };
+        assert!(
+            validate_source_url(&config, "https://doc-0s-sheets.googleusercontent.com/").is_ok()
+        );
+        assert!(validate_source_url(&config, "https://googleusercontent.com/").is_err());
+        assert!(validate_source_url(&config, "https://evilgoogleusercontent.com/").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_source_csv() -> eyre::Result<()> {
+        let config = Config {
+            source_url_allowed_hosts: vec![
+                "docs.google.com".to_string(),
+                "*.googleusercontent.com".to_string(),
+            ],
+            ..Default::default()
+        };
+        let requests = RefCell::new(Vec::new());
+        // Google Sheets redirects exports to googleusercontent.com, which redirects as configured
+        let fetch = |redirect: &'static str, body: Vec<u8>| {
+            let requests = &requests;
+            move |url: reqwest::Url| {
+                requests.borrow_mut().push(url.to_string());
+                let response = if url.host_str() == Some("docs.google.com") {
+                    SourceResponse {
+                        status: 307,
+                        location: Some(redirect.to_string()),
+                        body: futures::stream::iter(Vec::new()),
+                    }
+                } else {
+                    SourceResponse {
+                        status: 200,
+                        location: None,
+                        body: futures::stream::iter(vec![Ok(body.clone())]),
+                    }
+                };
+                async move { Ok(response) }
+            }
+        };
+        let source_url: reqwest::Url =
+            "https://docs.google.com/spreadsheets/d/abc/export".parse()?;
+
+        let csv = futures::executor::block_on(download_source_csv(
+            &config,
+            source_url.clone(),
+            fetch(
+                "https://doc-0s-sheets.googleusercontent.com/export/abc",
+                b"csv".to_vec(),
+            ),
+        ))?;
+        assert_eq!(csv, b"csv");
+        assert_eq!(
+            *requests.borrow(),
+            [
+                "https://docs.google.com/spreadsheets/d/abc/export",
+                "https://doc-0s-sheets.googleusercontent.com/export/abc"
+            ]
+        );
+
+        // Redirects to hosts that are not allowed are never fetched
+        requests.borrow_mut().clear();
+        let err = futures::executor::block_on(download_source_csv(
+            &config,
+            source_url.clone(),
+            fetch("https://169.254.169.254/latest", b"secret".to_vec()),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "source URL redirected to a disallowed URL: source URL host 169.254.169.254 is not allowed"
+        );
+        assert_eq!(requests.borrow().len(), 1);
+
+        // Redirect loops are cut off
+        let err = futures::executor::block_on(download_source_csv(
+            &config,
+            source_url.clone(),
+            fetch("/spreadsheets/d/abc/export", Vec::new()),
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "source URL redirected more than 5 times");
+
+        // The body is limited like webhook bodies
+        let err = futures::executor::block_on(download_source_csv(
+            &config,
+            source_url,
+            fetch(
+                "https://doc-0s-sheets.googleusercontent.com/export/abc",
+                vec![0; WEBHOOK_BODY_MAX_SIZE + 1],
+            ),
+        ))
+        .unwrap_err();
+        assert!(err.is::<BodyTooLarge>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_id_builder() {
+        assert_eq!(
+            ImportIdBuilder::new("TG")
+                .segment(-3000)
+                .segment(1767263690211i64)
+                .build(),
+            "TG:-3000:1767263690211"
+        );
+
+        let long = || {
+            ImportIdBuilder::new("TG")
+                .segment("a very long description exceeding the limit")
+                .segment(-3000)
+                .build()
+        };
+        let import_id = long();
+        assert!(import_id.len() <= 36, "{import_id} is too long");
+        assert!(import_id.starts_with("TG:h:"));
+        assert_eq!(import_id, long());
+        assert_ne!(
+            import_id,
+            ImportIdBuilder::new("TG")
+                .segment("a very long description exceeding the limit")
+                .segment(-4000)
+                .build()
+        );
+    }
+
+    #[test]
+    fn test_default_category() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Amazon","20.00","20.00","GBP","Shopping","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let transport = Uuid::new_v4();
+        let uncategorized = Uuid::new_v4();
+        let config = Config {
+            category_map: [("Transport".to_string(), transport)].into(),
+            default_category_id: Some(uncategorized),
+            ..Default::default()
+        };
+
+        let categories = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?).category_id)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        assert_eq!(categories, [Some(transport), Some(uncategorized)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_command() -> eyre::Result<()> {
+        let utilities = Uuid::new_v4();
+        let mut ynab = MockYnab {
+            account_name: "Yonder".to_string(),
+            balance: -3000,
+            categories: vec![CategoryBalance {
+                id: utilities,
+                name: "Utilities".to_string(),
+                group: "Monthly Bills".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let store = MemoryStore::default();
+        let mut config = Config {
+            category_group_map: [("Bills".to_string(), "Monthly Bills".to_string())].into(),
+            ..test_config()
+        };
+
+        // Nothing is cached before the first refresh
+        assert_eq!(
+            futures::executor::block_on(cached_category_groups(&config, Some(&store))),
+            None
+        );
+
+        let reply = futures::executor::block_on(on_telegram_refresh(&config, &ynab, Some(&store)))?;
+        assert!(reply.contains("Account: Yonder"));
+        assert!(reply.contains("Balance: -£3.00"));
+        assert!(reply.contains("Category groups: 1 resolved"));
+        assert_eq!(ynab.account_requests.get(), 1);
+        assert_eq!(
+            futures::executor::block_on(cached_category_groups(&config, Some(&store))),
+            Some([("monthly bills".to_string(), utilities)].into())
+        );
+
+        // Refreshing re-queries YNAB and replaces the cached category of the renamed group
+        let groceries = Uuid::new_v4();
+        ynab.categories[0] = CategoryBalance {
+            id: groceries,
+            name: "Groceries".to_string(),
+            group: "Everyday".to_string(),
+            ..Default::default()
+        };
+        config.category_group_map = [("Bills".to_string(), "Everyday".to_string())].into();
+        // The cache doesn't cover the newly mapped group until refreshed
+        assert_eq!(
+            futures::executor::block_on(cached_category_groups(&config, Some(&store))),
+            None
+        );
+        futures::executor::block_on(on_telegram_refresh(&config, &ynab, Some(&store)))?;
+        assert_eq!(ynab.account_requests.get(), 2);
+        assert_eq!(
+            futures::executor::block_on(cached_category_groups(&config, Some(&store))),
+            Some([("everyday".to_string(), groceries)].into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_travel_account() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Le Bistro","30.00","35.00","EUR","Dining","Debit","FRA""#,
+            r#""2026-01-01T11:34:50","Macy's","40.00","50.00","USD","Shopping","Debit","USA""#,
+            r#""2026-01-01T12:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let travel_account_id = Uuid::new_v4();
+        let config = Config {
+            ynab_account_id: Some(Uuid::new_v4()),
+            travel_account_id: Some(travel_account_id),
+            account_routes: vec![AccountRoute {
+                name: "dining".to_string(),
+                account_id: Uuid::new_v4(),
+                description_contains: None,
+                category: Some("Dining".to_string()),
+            }],
+            ..Default::default()
+        };
+
+        let accounts = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?).account_id)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        assert_eq!(
+            accounts,
+            [
+                Some(travel_account_id),
+                Some(travel_account_id),
+                config.ynab_account_id
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_whitespace() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","  TFL - Transport for London ","3.00","3.00"," GBP "," Transport ","Debit"," GBR""#,
+        ]);
+        let transport = Uuid::new_v4();
+        let config = Config {
+            category_map: [("Transport".to_string(), transport)].into(),
+            ..Default::default()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let transaction = &mapped.transactions[0];
+        assert_eq!(
+            transaction.payee_name.as_ref().map(|payee| payee.as_str()),
+            Some("TFL - Transport for London")
+        );
+        assert_eq!(transaction.category_id, Some(transport));
+
+        let config = Config {
+            preserve_whitespace: true,
+            ..config
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            mapped.transactions[0]
+                .payee_name
+                .as_ref()
+                .map(|payee| payee.as_str()),
+            Some("  TFL - Transport for London ")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_window() -> eyre::Result<()> {
+        let config = Config {
+            import_window_days: Some(30),
+            ..Default::default()
+        };
+
+        let stale = yonder_csv(&[
+            r#""2025-10-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2025-11-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+        ]);
+        let err = map_yonder_csv(&stale, &config, &test_context()?)
+            .err()
+            .expect("stale CSV must be rejected");
+        assert!(err.to_string().contains("2025-11-01"), "{err}");
+        assert!(map_yonder_csv(&stale, &Config::default(), &test_context()?).is_ok());
+
+        let fresh = yonder_csv(&[
+            r#""2025-10-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2025-12-20T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+        ]);
+        assert_eq!(
+            map_yonder_csv(&fresh, &config, &test_context()?)?
+                .transactions
+                .len(),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile() -> eyre::Result<()> {
+        let config = test_config();
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+        ]);
+
+        // Totals match after importing the CSV
+        futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+        let reconciliation =
+            futures::executor::block_on(reconcile_yonder_csv(&csv, &config, &ynab, None))?;
+        assert_eq!(
+            reconciliation,
+            Reconciliation {
+                from: "2025-12-30".parse()?,
+                to: "2025-12-31".parse()?,
+                csv_total: -13250,
+                ynab_total: -13250,
+            }
+        );
+        assert!(reconciliation.to_string().ends_with("Totals match"));
+
+        // Transaction missing from YNAB
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T10:00:00","Lunch","7.50","7.50","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let reconciliation =
+            futures::executor::block_on(reconcile_yonder_csv(&csv, &config, &ynab, None))?;
+        assert_eq!(reconciliation.csv_total, -20750);
+        assert_eq!(reconciliation.ynab_total, -13250);
+        assert!(reconciliation.to_string().ends_with("Discrepancy: -£7.50"));
+
+        // Explicit range excludes the missing transaction
+        let range = parse_date_range("2025-12-31 2025-12-31")?;
+        let reconciliation =
+            futures::executor::block_on(reconcile_yonder_csv(&csv, &config, &ynab, range))?;
+        assert_eq!(reconciliation.discrepancy(), 0);
+
+        assert_eq!(parse_date_range("")?, None);
+        assert!(parse_date_range("2026-01-02 2026-01-01").is_err());
+        assert!(parse_date_range("yesterday").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_webhook_empty_status() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let import = |csv: &str, config: &Config| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                csv,
+                config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        // Empty CSV uses the default status
+        let config = test_config();
+        let empty = import(&yonder_csv(&[]), &config)?;
+        let (status, message) = webhook_import_status(&config, &empty);
+        assert_eq!(status, 200);
+        assert!(message.starts_with("No transactions found"));
+        assert!(ynab.transactions.borrow().is_empty());
+
+        // Configured status for the empty CSV
+        let config = Config {
+            webhook_empty_status: Some(422),
+            ..test_config()
+        };
+        assert_eq!(webhook_import_status(&config, &empty).0, 422);
+
+        // All-duplicates CSV is not empty
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let imported = import(&csv, &config)?;
+        assert_eq!(webhook_import_status(&config, &imported).0, 200);
+        let duplicates = import(&csv, &config)?;
+        assert_eq!(duplicates.duplicates, 1);
+        let (status, message) = webhook_import_status(&config, &duplicates);
+        assert_eq!(status, 200);
+        assert!(!message.starts_with("No transactions found"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memo_row_counter() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-05T10:00:00","Pending","5.00","5.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let memos = |config: &Config| -> eyre::Result<Vec<Option<String>>> {
+            Ok(map_yonder_csv(&csv, config, &test_context()?)?
+                .transactions
+                .into_iter()
+                .map(|transaction| transaction.memo.map(|memo| memo.to_string()))
+                .collect())
+        };
+
+        // Future transaction is skipped, but keeps its position
+        let config = Config {
+            memo_row_counter: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            memos(&config)?,
+            [Some("row 1/3".to_string()), Some("row 3/3".to_string())]
+        );
+
+        // Counter is kept when the memo is truncated
+        let config = Config {
+            memo_row_counter: true,
+            default_memo: Some("x".repeat(600)),
+            ..Default::default()
+        };
+        let memo = memos(&config)?[0].clone().unwrap_or_default();
+        assert_eq!(memo.chars().count(), 500);
+        assert!(memo.ends_with("x row 1/3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_no_duplicates() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let import = |config: &Config| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        let strict = Config {
+            strict_no_duplicates: true,
+            ..test_config()
+        };
+        assert_eq!(import(&strict)?.imported, 1);
+
+        // Re-sent transactions succeed by default
+        assert_eq!(import(&test_config())?.duplicates, 1);
+
+        // But fail in strict mode, keeping the result
+        let result = import(&strict)?;
+        assert_eq!(result.duplicates, 1);
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].starts_with("YNAB reported 1 duplicate transactions: "));
+        assert_eq!(webhook_import_status(&strict, &result).0, 500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_batch() -> eyre::Result<()> {
+        let ynab = MockYnab {
+            failing_payee: Some("Coffee".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            import_batching: ImportBatching::Daily,
+            ..test_config()
+        };
+        let import = |rows: &[&str]| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                yonder_csv(rows),
+                &config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+        let tesco =
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#;
+        let coffee =
+            r#""2026-01-02T09:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#;
+
+        // The batch saved before the failure is reported along with it
+        let result = import(&[tesco, coffee])?;
+        assert_eq!(result.imported, 1);
+        assert_eq!(result.net, -8000);
+        assert_eq!(
+            result.errors,
+            ["failed to import 1 transactions: request failed"]
+        );
+        assert!(result
+            .to_string()
+            .ends_with("\nERROR: failed to import 1 transactions: request failed"));
+        assert_eq!(webhook_import_status(&config, &result).0, 500);
+
+        // Nothing was saved, so the import fails
+        assert!(import(&[coffee]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_summary() -> eyre::Result<()> {
+        let result = DocumentResult {
+            imported: 2,
+            transaction_ids: vec![],
+            duplicates: 1,
+            duplicate_import_ids: vec![],
+            skipped: 0,
+            scheduled: 0,
+            balance: None,
+            net: -13250,
+            warnings: vec![],
+            errors: vec![],
+            large_transactions: vec![],
+            upload_id: None,
+            server_knowledge: None,
+            payees: vec![],
+        };
+        let summary = serde_json::to_value(ImportSummary {
+            result: &result,
+            timestamp: test_context()?.now,
+        })?;
+        let url: reqwest::Url = "https://example.com/summary".parse()?;
+
+        let posted = RefCell::new(None);
+        let post = |url, json| {
+            posted.replace(Some((url, json)));
+            async { Ok(200) }
+        };
+        futures::executor::block_on(post_import_summary(url.clone(), summary.clone(), post))?;
+        assert_eq!(
+            posted.into_inner(),
+            Some((
+                url.clone(),
+                serde_json::json!({
+                    "imported": 2,
+                    "duplicates": 1,
+                    "skipped": 0,
+                    "scheduled": 0,
+                    "net": -13250,
+                    "timestamp": "2026-01-02T12:00:00Z",
+                })
+            ))
+        );
+
+        // Endpoint errors are reported, to be logged by the caller
+        let err = futures::executor::block_on(post_import_summary(url, summary, |_, _| async {
+            Ok(500)
+        }))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "summary webhook responded with HTTP 500");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_account() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Tesco","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let import = |config: &Config| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        // Account route covers only one of the transactions
+        let mut config = Config {
+            account_routes: vec![AccountRoute {
+                name: "groceries".to_string(),
+                account_id: Uuid::new_v4(),
+                description_contains: None,
+                category: Some("Groceries".to_string()),
+            }],
+            ..Default::default()
+        };
+        let err = import(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to map 1 rows to YNAB transactions:\n\
+             line 2: no YNAB account for transaction \"Coffee\": set YNAB_ACCOUNT_ID or add a matching account route"
+        );
+        assert!(ynab.transactions.borrow().is_empty());
+
+        // Partial import skips the transaction without an account
+        config.partial_import = true;
+        let result = import(&config)?;
+        assert_eq!((result.imported, result.skipped), (1, 1));
+        assert_eq!(
+            result.warnings,
+            ["line 2: no YNAB account for transaction \"Coffee\": set YNAB_ACCOUNT_ID or add a matching account route (not imported)"]
+        );
+        config.partial_import = false;
+        ynab.transactions.borrow_mut().clear();
+
+        // Account routes alone are enough when they cover all transactions
+        config.account_routes[0].category = None;
+        assert_eq!(import(&config)?.imported, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_only() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+
+        assert_eq!(
+            transactions[0].date_time,
+            YonderTransactionDateTime::Date("2026-01-01".parse()?)
+        );
+        assert_eq!(
+            transactions[0].date_time.clone().utc(),
+            "2026-01-01T00:00:00Z".parse::<chrono::DateTime<chrono::Utc>>()?
+        );
+        assert_eq!(
+            transactions[1].date_time.clone().utc(),
+            "2026-01-01T10:34:50Z".parse::<chrono::DateTime<chrono::Utc>>()?
+        );
+
+        let transaction = map_transaction(
+            transactions[0].clone(),
+            &Config::default(),
+            &test_context()?,
+        );
+        assert_eq!(transaction.date, Some("2026-01-01".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payee_country() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Hotel Paris","120.00","140.00","EUR","Travel","Debit","FRA""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            payee_country: true,
+            ..Default::default()
+        };
+        let payee = |transaction: &YonderTransaction| -> eyre::Result<String> {
+            Ok(
+                map_transaction(transaction.clone(), &config, &test_context()?)
+                    .payee_name
+                    .map(|payee_name| payee_name.to_string())
+                    .unwrap_or_default(),
+            )
+        };
+
+        assert_eq!(payee(&transactions[0])?, "Hotel Paris (FRA)");
+        assert_eq!(payee(&transactions[1])?, "Tesco");
+
+        // Country is kept when the payee is truncated
+        let long = YonderTransaction {
+            description: "x".repeat(250),
+            ..transactions[0].clone()
+        };
+        let payee_name = payee(&long)?;
+        assert_eq!(payee_name.chars().count(), 200);
+        assert!(payee_name.ends_with("x (FRA)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_transactions() -> eyre::Result<()> {
+        let config = test_config();
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T10:00:00","Lunch","7.50","7.50","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+        ]);
+        futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+
+        let recent = futures::executor::block_on(recent_transactions(&config, &ynab, 2))?;
+        let expected = futures::executor::block_on(ynab.account_transactions(
+            &config.ynab_budget_id,
+            config.account_id()?,
+            None,
+        ))?;
+        assert_eq!(
+            recent
+                .iter()
+                .map(|transaction| (transaction.date, transaction.amount))
+                .collect::<Vec<_>>(),
+            [
+                ("2026-01-01".parse::<NaiveDate>()?, -7500),
+                ("2025-12-31".parse::<NaiveDate>()?, -10250),
+            ]
+        );
+        assert!(recent
+            .iter()
+            .all(|transaction| expected.contains(transaction)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allowed_currencies() -> eyre::Result<()> {
+        let config = Config {
+            allowed_currencies: vec!["GBP".to_string()],
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Le Bistro","30.00","35.00","EUR","Dining","Debit","FRA""#,
+            r#""2025-12-30T11:00:00","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Macy's","40.00","50.00","USD","Shopping","Debit","USA""#,
+            r#""2025-12-31T11:00:00","Pret","4.50","4.50","gbp","Eating out","Debit","GBR""#,
+        ]);
+
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+
+        assert_eq!(result.imported, 2);
+        assert_eq!(result.skipped, 2);
+        assert_eq!(
+            ynab.transactions
+                .borrow()
+                .iter()
+                .map(|transaction| transaction.amount)
+                .collect::<Vec<_>>(),
+            [Some(-8000), Some(-4500)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleared_command() -> eyre::Result<()> {
+        let config = Config {
+            cleared_status: Some(TransactionClearedStatus::Uncleared),
+            ..test_config()
+        };
+        let store = MemoryStore::default();
+        let cleared = |chat_id, args| {
+            futures::executor::block_on(on_telegram_cleared(&config, Some(&store), chat_id, args))
+        };
+
+        assert_eq!(
+            cleared(1, "")?,
+            "Cleared status of future imports: uncleared"
+        );
+        assert_eq!(
+            cleared(1, "Reconciled")?,
+            "Cleared status of future imports: reconciled"
+        );
+        assert!(cleared(1, "pending").is_err());
+        // Other chats keep the configured status
+        assert_eq!(
+            cleared(2, "")?,
+            "Cleared status of future imports: uncleared"
+        );
+
+        // Runtime status is applied to imports from the chat
+        let ynab = MockYnab::default();
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let import = |chat_id| -> eyre::Result<Option<TransactionClearedStatus>> {
+            let options = ImportOptions {
+                cleared_status: futures::executor::block_on(chat_cleared_status(
+                    Some(&store),
+                    chat_id,
+                )),
+                ..Default::default()
+            };
+            futures::executor::block_on(import_yonder_csv_to_ynab(&csv, &config, &ynab, &options))?;
+            Ok(ynab
+                .transactions
+                .borrow_mut()
+                .pop()
+                .and_then(|transaction| transaction.cleared))
+        };
+        assert_eq!(import(1)?, Some(TransactionClearedStatus::Reconciled));
+        assert_eq!(import(2)?, Some(TransactionClearedStatus::Uncleared));
+
+        // Resetting to the configured status
+        assert_eq!(
+            cleared(1, "default")?,
+            "Cleared status of future imports: uncleared"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_command() -> eyre::Result<()> {
+        let config = test_config();
+        let store = MemoryStore::default();
+        let reset = |chat_id, args| {
+            futures::executor::block_on(on_telegram_reset(Some(&store), chat_id, args))
+        };
+
+        assert_eq!(
+            reset(1, "")?,
+            "This chat uses the default settings, nothing to reset"
+        );
+
+        futures::executor::block_on(on_telegram_cleared(&config, Some(&store), 1, "reconciled"))?;
+        futures::executor::block_on(on_telegram_cleared(&config, Some(&store), 2, "uncleared"))?;
+
+        // Nothing is deleted without confirmation
+        assert_eq!(
+            reset(1, "")?,
+            "This resets the settings of this chat to the defaults: cleared status\n\nSend /reset confirm to proceed"
+        );
+        assert!(reset(1, "now").is_err());
+        assert!(futures::executor::block_on(store.get(&cleared_status_key(1)))?.is_some());
+
+        assert_eq!(
+            reset(1, "confirm")?,
+            "Reset the settings of this chat to the defaults: cleared status"
+        );
+        assert_eq!(
+            futures::executor::block_on(store.get(&cleared_status_key(1)))?,
+            None
+        );
+        assert_eq!(
+            futures::executor::block_on(chat_cleared_status(Some(&store), 1)),
+            None
+        );
+        // Other chats keep their settings
+        assert_eq!(
+            futures::executor::block_on(chat_cleared_status(Some(&store), 2)),
+            Some(TransactionClearedStatus::Uncleared)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_header_validation() -> eyre::Result<()> {
+        // Reordered columns are still matched by name
+        let reordered = "\"Description\",\"Date/Time of transaction\",\"Amount (GBP)\",\"Amount (in Charged Currency)\",\"Currency\",\"Category\",\"Debit or Credit\",\"Country\"
+\"Tesco\",\"2026-01-01T10:34:50\",\"8.00\",\"8.00\",\"GBP\",\"Groceries\",\"Debit\",\"GBR\"";
+        assert_eq!(parse_yonder_csv(reordered)?.transactions.len(), 1);
+
+        let renamed = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ])
+        .replace("\"Amount (GBP)\"", "\"Amount GBP\"");
+        let err = parse_yonder_csv(renamed)
+            .err()
+            .expect("renamed column is reported");
+        assert_eq!(
+            err.to_string(),
+            r#"CSV columns don't match the Yonder export, missing: "Amount (GBP)"; unexpected: "Amount GBP""#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_amount_scale() -> eyre::Result<()> {
+        let amounts = |csv: String, config: &Config| -> eyre::Result<Vec<Option<i64>>> {
+            Ok(map_yonder_csv(csv, config, &test_context()?)?
+                .transactions
+                .into_iter()
+                .map(|transaction| transaction.amount)
+                .collect())
+        };
+
+        // Pounds by default
+        let pounds = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.25","8.25","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Refund","3.00","3.00","GBP","Groceries","Credit","GBR""#,
+        ]);
+        assert_eq!(
+            amounts(pounds, &Config::default())?,
+            [Some(-8250), Some(3000)]
+        );
+
+        // Pence with the scale of 10
+        let pence = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","825","825","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Refund","300","300","GBP","Groceries","Credit","GBR""#,
+        ]);
+        let config = Config {
+            amount_scale: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(amounts(pence, &config)?, [Some(-8250), Some(3000)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_manifest() -> eyre::Result<()> {
+        use base64::{prelude::BASE64_STANDARD, Engine};
+
+        let december = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let january = yonder_csv(&[
+            r#""2025-12-31T10:00:00","Groceries","10.25","10.25","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T10:00:00","Salary","100.00","100.00","GBP","Income","Credit","GBR""#,
+        ]);
+        let body = serde_json::to_vec(&serde_json::json!({
+            "files": [
+                {"name": "december.csv", "csv_base64": BASE64_STANDARD.encode(&december)},
+                {"name": "january.csv", "csv_base64": BASE64_STANDARD.encode(&january)},
+            ]
+        }))?;
+
+        let WebhookBody::Manifest(files) = decode_webhook_body(Some("application/json"), body)?
+        else {
+            eyre::bail!("expected manifest");
+        };
+        assert_eq!(
+            files,
+            [
+                ManifestFile {
+                    name: "december.csv".to_string(),
+                    csv: december.into_bytes(),
+                },
+                ManifestFile {
+                    name: "january.csv".to_string(),
+                    csv: january.into_bytes(),
+                },
+            ]
+        );
+
+        let ynab = MockYnab::default();
+        let result = futures::executor::block_on(import_manifest(
+            files,
+            &test_config(),
+            &ynab,
+            &ImportOptions::default(),
+        ));
+        assert!(result.is_success());
+        assert_eq!(
+            serde_json::to_value(&result)?,
+            serde_json::json!({
+                "files": [
+                    {"name": "december.csv", "imported": 2, "duplicates": 0, "skipped": 0, "scheduled": 0, "net": -13250},
+                    {"name": "january.csv", "imported": 1, "duplicates": 1, "skipped": 0, "scheduled": 0, "net": 100000},
+                ],
+                "total": {"imported": 3, "duplicates": 1, "skipped": 0, "scheduled": 0, "net": 86750},
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_utf16_csv() -> eyre::Result<()> {
+        let csv = std::fs::read_to_string("yonder.csv")?;
+        let utf16le: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(csv.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+
+        assert_eq!(
+            transcode_utf16(utf16le.as_slice().into())?.as_ref(),
+            csv.as_bytes()
+        );
+        let mapped = map_yonder_csv(&utf16le, &Config::default(), &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 2);
+
+        // UTF-8 is returned as is
+        assert_eq!(
+            transcode_utf16(csv.as_bytes().into())?.as_ref(),
+            csv.as_bytes()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_description_rules() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","UBER *TRIP","12.00","12.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Pret","4.50","4.50","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let (transport, eating_out, default) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let config = Config {
+            category_description_rules: vec![CategoryRule {
+                pattern: regex::Regex::new("(?i)uber")?,
+                category_id: transport,
+            }],
+            category_map: [("Eating out".to_string(), eating_out)].into(),
+            default_category_id: Some(default),
+            ..Default::default()
+        };
+
+        let categories = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?).category_id)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        assert_eq!(
+            categories,
+            [Some(transport), Some(eating_out), Some(default)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_dedup() -> eyre::Result<()> {
+        let store = MemoryStore::default();
+        futures::executor::block_on(async {
+            store
+                .put(
+                    DEDUP_KEY,
+                    r#"["TG:-3000:1767263690211","TG:-8000:1767267290211"]"#,
+                    None,
+                )
+                .await?;
+            store.put("cleared:1", "uncleared", None).await
+        })?;
+
+        assert_eq!(futures::executor::block_on(reset_dedup(&store))?, 2);
+        assert_eq!(store.0.borrow().keys().collect::<Vec<_>>(), ["cleared:1"]);
+        assert_eq!(futures::executor::block_on(reset_dedup(&store))?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fx_fee_split() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Le Bistro","30.00","34.00","EUR","Dining","Debit","FRA""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let (dining, fx_fee) = (Uuid::new_v4(), Uuid::new_v4());
+        let config = Config {
+            category_map: [("Dining".to_string(), dining)].into(),
+            fx_fee_category_id: Some(fx_fee),
+            fx_reference_rates: [("EUR".to_string(), 0.875)].into(),
+            ..Default::default()
+        };
+
+        // 34 EUR at 0.875 is £29.75, so £0.25 is the FX fee
+        let foreign = map_transaction(transactions[0].clone(), &config, &test_context()?);
+        assert_eq!(foreign.amount, Some(-30000));
+        assert_eq!(foreign.category_id, None);
+        assert_eq!(
+            foreign
+                .subtransactions
+                .iter()
+                .map(|subtransaction| (subtransaction.amount, subtransaction.category_id))
+                .collect::<Vec<_>>(),
+            [(-29750, Some(dining)), (-250, Some(fx_fee))]
+        );
+        assert_eq!(
+            foreign
+                .subtransactions
+                .iter()
+                .map(|subtransaction| subtransaction.amount)
+                .sum::<i64>(),
+            -30000
+        );
+
+        let domestic = map_transaction(transactions[1].clone(), &config, &test_context()?);
+        assert!(domestic.subtransactions.is_empty());
+        assert_eq!(domestic.category_id, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rules_command() -> eyre::Result<()> {
+        let (transport, business) = (Uuid::new_v4(), Uuid::new_v4());
+        let config = Config {
+            telegram_allowed_chat_ids: vec![42],
+            category_map: [("Transport".to_string(), transport)].into(),
+            generic_descriptions: vec!["Contactless Payment".to_string()],
+            account_routes: vec![AccountRoute {
+                name: "business".to_string(),
+                account_id: business,
+                description_contains: Some("AWS".to_string()),
+                category: None,
+            }],
+            allowed_currencies: vec!["GBP".to_string()],
+            ..Default::default()
+        };
+
+        let reply = on_telegram_command(&config, 42, "rules", "").expect("rules is a command");
+        assert!(reply.contains(&format!("- category Transport → {transport}")));
+        assert!(reply.contains("- generic descriptions use the category: Contactless Payment"));
+        assert!(reply.contains(&format!(
+            "- business (description contains \"AWS\") → {business}"
+        )));
+        assert!(reply.contains("- future transactions"));
+        assert!(reply.contains("- currencies other than GBP"));
+
+        let reply = on_telegram_command(&config, 1, "rules", "").expect("rules is a command");
+        assert_eq!(reply, COMMAND_NOT_ALLOWED_MESSAGE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_message() {
+        let line = "x".repeat(99);
+        let text = vec![line.as_str(); 100].join("\n");
+        assert_eq!(text.chars().count(), 9999);
+
+        let chunks = split_message(&text, 4096);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 4096));
+        // Chunks end at line boundaries
+        assert!(chunks[..2].iter().all(|chunk| chunk.ends_with('\n')));
+        assert_eq!(chunks.concat(), text);
+
+        // Lines longer than the limit are split at the limit
+        let long = "£".repeat(10);
+        assert_eq!(split_message(&long, 4), ["££££", "££££", "££"]);
+
+        assert_eq!(split_message("short", 4096), ["short"]);
+    }
+
+    #[test]
+    fn test_escape_reply() {
+        let reply = "Imported 1 transaction <TFL>\nBalance: -£3.00 (row 1)".to_string();
+        assert_eq!(
+            escape_reply(reply.clone(), MessageParseMode::Plain),
+            (reply.clone(), None)
+        );
+        assert_eq!(
+            escape_reply(reply.clone(), MessageParseMode::Html),
+            (
+                "Imported 1 transaction &lt;TFL&gt;\nBalance: -£3.00 (row 1)".to_string(),
+                Some(ParseMode::Html)
+            )
+        );
+        assert_eq!(
+            escape_reply(reply, MessageParseMode::MarkdownV2),
+            (
+                "Imported 1 transaction <TFL\\>\nBalance: \\-£3\\.00 \\(row 1\\)".to_string(),
+                Some(ParseMode::MarkdownV2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_archive_upload() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let archive = MemoryArchive::default();
+
+        futures::executor::block_on(archive_upload(
+            Some(&archive),
+            "telegram-1",
+            test_context()?.now,
+            csv.as_bytes(),
+        ));
+        assert_eq!(
+            archive.0.into_inner(),
+            std::collections::BTreeMap::from([(
+                "uploads/2026-01-02T12:00:00.000Z/telegram-1".to_string(),
+                csv.into_bytes()
+            )])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_mismatch() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","-8.00","-8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Refund","-5.00","-5.00","GBP","Groceries","Credit","GBR""#,
+            r#""2026-01-01T12:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+
+        let amounts = |mapped: &MappedCsv| -> Vec<Option<i64>> {
+            mapped
+                .transactions
+                .iter()
+                .map(|transaction| transaction.amount)
+                .collect()
+        };
+
+        // Amounts are imported as exported by default
+        let mapped = map_yonder_csv(&csv, &Config::default(), &test_context()?)?;
+        assert_eq!(amounts(&mapped), [Some(8000), Some(-5000), Some(-3000)]);
+        assert!(mapped.warnings.is_empty());
+
+        // Inconsistent rows are reported and the rest are imported
+        let config = Config {
+            sign_mismatch_policy: SignMismatchPolicy::Error,
+            ..Default::default()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(amounts(&mapped), [Some(-3000)]);
+        assert_eq!(mapped.skipped, 2);
+        assert_eq!(
+            mapped.warnings,
+            [
+                "line 2: Debit \"Tesco\" with amount -8 is inconsistent with Debit or Credit (not imported)",
+                "line 3: Credit \"Refund\" with amount -5 is inconsistent with Debit or Credit (not imported)"
+            ]
+        );
+
+        let config = Config {
+            sign_mismatch_policy: SignMismatchPolicy::Correct,
+            ..Default::default()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(amounts(&mapped), [Some(-8000), Some(5000), Some(-3000)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_amount() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Round up","0.45","0.45","GBP","Savings","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Cashback","0.99","0.99","GBP","Rewards","Credit","GBR""#,
+            r#""2026-01-01T13:34:50","Coffee","1.00","1.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let config = Config {
+            min_amount_gbp: 1.0,
+            ..Default::default()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let amounts: Vec<_> = mapped
+            .transactions
+            .iter()
+            .map(|transaction| transaction.amount)
+            .collect();
+        assert_eq!(amounts, [Some(-8000), Some(-1000)]);
+        assert_eq!(mapped.skipped, 2);
+
+        // Nothing is filtered by default
+        let mapped = map_yonder_csv(&csv, &Config::default(), &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 4);
+
+        // The minimum is in pounds even if the CSV amounts are in pence
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","800","800","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Round up","45","45","GBP","Savings","Debit","GBR""#,
+        ]);
+        let config = Config {
+            min_amount_gbp: 1.0,
+            amount_scale: Some(10),
+            ..Default::default()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            mapped
+                .transactions
+                .iter()
+                .map(|transaction| transaction.amount)
+                .collect::<Vec<_>>(),
+            [Some(-8000)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheduled_transactions() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2025-12-05T10:00:00","NETFLIX.COM","10.99","10.99","GBP","Entertainment","Debit","GBR""#,
+            r#""2025-12-30T10:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let config = Config {
+            scheduled_rules: vec![ScheduledRule {
+                description_contains: "netflix".to_string(),
+                frequency: ScheduledTransactionFrequency::Monthly,
+            }],
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        // Scheduled dates depend on today, so the import uses the test clock
+        let context = test_context()?;
+        let import = || {
+            let mapped = map_yonder_csv(&csv, &config, &context)?;
+            futures::executor::block_on(import_mapped(
+                mapped,
+                &context,
+                &config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        let result = import()?;
+        assert_eq!((result.imported, result.scheduled), (1, 1));
+        assert_eq!(ynab.transactions.borrow().len(), 1);
+        let scheduled = ynab.scheduled.borrow()[0].clone();
+        assert_eq!(scheduled.amount, Some(-10990));
+        assert_eq!(
+            scheduled.frequency,
+            Some(ScheduledTransactionFrequency::Monthly)
+        );
+        assert_eq!(scheduled.date, "2026-01-05".parse()?);
+
+        // Uploading the same transaction again doesn't schedule it twice
+        let result = import()?;
+        assert_eq!((result.scheduled, result.duplicates), (0, 2));
+        assert_eq!(ynab.scheduled.borrow().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_occurrence() -> eyre::Result<()> {
+        let today = "2026-01-02".parse()?;
+        let next = |date: &str, frequency| -> eyre::Result<NaiveDate> {
+            Ok(next_occurrence(date.parse()?, frequency, today))
+        };
+
+        assert_eq!(
+            next("2025-12-05", ScheduledTransactionFrequency::Monthly)?,
+            "2026-01-05".parse()?
+        );
+        // Month ends don't drift to shorter months
+        assert_eq!(
+            next("2025-10-31", ScheduledTransactionFrequency::Monthly)?,
+            "2026-01-31".parse()?
+        );
+        assert_eq!(
+            next("2025-12-30", ScheduledTransactionFrequency::Weekly)?,
+            "2026-01-06".parse()?
+        );
+        assert_eq!(
+            next("2025-12-30", ScheduledTransactionFrequency::Never)?,
+            "2026-01-03".parse()?
+        );
+        assert_eq!(
+            next("2026-02-01", ScheduledTransactionFrequency::Yearly)?,
+            "2026-02-01".parse()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_concurrent_requests() -> eyre::Result<()> {
+        let rows: Vec<_> = (1..=10)
+            .map(|day| {
+                format!(
+                    r#""2025-12-{day:02}T10:00:00","Gym {day}","{day}.00","{day}.00","GBP","Sports","Debit","GBR""#
+                )
+            })
+            .collect();
+        let csv = yonder_csv(&rows.iter().map(String::as_str).collect::<Vec<_>>());
+        let config = Config {
+            import_batching: ImportBatching::Daily,
+            ynab_max_concurrent_requests: Some(2),
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+
+        // A batch per day is imported with no more than two requests at once
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!(result.imported, 10);
+        assert_eq!(ynab.max_in_flight.get(), 2);
+
+        // Scheduled transactions are created under the same limit
+        let config = Config {
+            scheduled_rules: vec![ScheduledRule {
+                description_contains: "gym".to_string(),
+                frequency: ScheduledTransactionFrequency::Monthly,
+            }],
+            ..config
+        };
+        let ynab = MockYnab::default();
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!(result.scheduled, 10);
+        assert_eq!(ynab.max_in_flight.get(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_test() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let now = test_context()?.now;
+
+        let result = futures::executor::block_on(self_test(&test_config(), &ynab, true, now))?;
+        assert_eq!(result.tag, "SELFTEST:1767355200000");
+        assert_eq!(result.result.imported, 2);
+        assert_eq!(result.deleted, 2);
+        assert_eq!(*ynab.deleted.borrow(), *ynab.transaction_ids.borrow());
+        for transaction in ynab.transactions.borrow().iter() {
+            let import_id = transaction.import_id.as_ref().expect("import_id is set");
+            assert!(import_id.starts_with("SELFTEST:1767355200000:"));
+            let memo = transaction.memo.as_ref().expect("memo is set");
+            assert!(memo.contains("SELFTEST:1767355200000"));
+        }
+
+        // Without deletion, the sample transactions are kept in YNAB
+        let ynab = MockYnab::default();
+        let result = futures::executor::block_on(self_test(&test_config(), &ynab, false, now))?;
+        assert_eq!((result.result.imported, result.deleted), (2, 0));
+        assert!(ynab.deleted.borrow().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_travel_category() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Boulangerie","4.00","4.60","EUR","Eating out","Debit","FRA""#,
+            r#""2026-01-01T11:34:50","Hotel Paris","120.00","140.00","EUR","Travel","Debit","FRA""#,
+            r#""2026-01-01T12:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let hotels = Uuid::new_v4();
+        let travel = Uuid::new_v4();
+        let uncategorized = Uuid::new_v4();
+        let config = Config {
+            category_map: [("Travel".to_string(), hotels)].into(),
+            travel_category_id: Some(travel),
+            default_category_id: Some(uncategorized),
+            ..Default::default()
+        };
+
+        let categories = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?).category_id)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        // The specific category mapping wins over the travel category
+        assert_eq!(
+            categories,
+            [Some(travel), Some(hotels), Some(uncategorized)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_diff() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let store = MemoryStore::default();
+        let config = test_config();
+        let diff = |rows: &[&str]| {
+            futures::executor::block_on(import_diff(
+                yonder_csv(rows),
+                &config,
+                &ynab,
+                &store,
+                &ImportOptions::default(),
+            ))
+        };
+        let payees = |transactions: &[DiffTransaction]| -> Vec<String> {
+            transactions
+                .iter()
+                .filter_map(|transaction| transaction.payee_name.clone())
+                .collect()
+        };
+
+        let tesco =
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#;
+        let coffee =
+            r#""2026-01-01T11:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#;
+        let cinema =
+            r#""2026-01-01T12:34:50","Cinema","12.00","12.00","GBP","Entertainment","Debit","GBR""#;
+        let taxi =
+            r#""2026-01-01T13:34:50","Taxi","15.00","15.00","GBP","Transport","Debit","GBR""#;
+
+        // Imported to YNAB outside of the diff mode, so it's unknown locally
+        futures::executor::block_on(import_yonder_csv_to_ynab(
+            yonder_csv(&[taxi]),
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+
+        let first = diff(&[tesco, coffee])?;
+        assert_eq!(payees(&first.new), ["Tesco", "Coffee"]);
+        assert!(first.local_duplicates.is_empty());
+        assert!(first.ynab_duplicates.is_empty());
+
+        // The second upload overlaps with the first one
+        let second = diff(&[coffee, cinema, taxi])?;
+        assert_eq!(payees(&second.new), ["Cinema"]);
+        assert_eq!(payees(&second.local_duplicates), ["Coffee"]);
+        assert_eq!(payees(&second.ynab_duplicates), ["Taxi"]);
+        // Local duplicates are not sent to YNAB
+        assert_eq!(ynab.transactions.borrow().len(), 4);
+        // import_ids are tracked under a single key
+        assert_eq!(store.0.borrow().keys().collect::<Vec<_>>(), [DEDUP_KEY]);
+        assert_eq!(
+            futures::executor::block_on(read_dedup_import_ids(&store))?.len(),
+            4
+        );
+
+        // Import options apply to the diff, e.g. tagged import_ids are never known locally
+        let tagged = futures::executor::block_on(import_diff(
+            yonder_csv(&[tesco]),
+            &config,
+            &ynab,
+            &store,
+            &ImportOptions {
+                tag: Some("TEST".to_string()),
+                ..Default::default()
+            },
+        ))?;
+        assert_eq!(
+            tagged
+                .new
+                .iter()
+                .map(|transaction| transaction.import_id.as_str())
+                .collect::<Vec<_>>(),
+            ["TEST:0"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_daily_import_batches() -> eyre::Result<()> {
+        let mapped = map_yonder_csv(
+            yonder_csv(&[
+                r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+                r#""2026-01-02T10:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+                r#""2026-01-01T12:34:50","Cinema","12.00","12.00","GBP","Entertainment","Debit","GBR""#,
+                r#""2026-01-02T11:34:50","Taxi","15.00","15.00","GBP","Transport","Debit","GBR""#,
+                r#""2025-12-31T09:00:00","Bakery","2.50","2.50","GBP","Eating out","Debit","GBR""#,
+            ]),
+            &test_config(),
+            &test_context()?,
+        )?;
+        let dates = |batches: &[Vec<NewTransaction>]| -> Vec<Vec<Option<NaiveDate>>> {
+            batches
+                .iter()
+                .map(|batch| batch.iter().map(|transaction| transaction.date).collect())
+                .collect()
+        };
+
+        // Imports smaller than the default batch size are sent at once
+        let batches = import_batches(mapped.transactions.clone(), &test_config());
+        assert_eq!(batches.len(), 1);
+
+        let config = Config {
+            import_batching: ImportBatching::Daily,
+            ..test_config()
+        };
+        let batches = import_batches(mapped.transactions.clone(), &config);
+        assert_eq!(
+            dates(&batches),
+            [
+                vec![Some("2025-12-31".parse()?)],
+                vec![Some("2026-01-01".parse()?); 2],
+                vec![Some("2026-01-02".parse()?); 2],
+            ]
+        );
+
+        let config = Config {
+            import_batch_size: Some(2),
+            ..test_config()
+        };
+        let batches = import_batches(mapped.transactions, &config);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_ynab_token() -> eyre::Result<()> {
+        let ynab = MockYnab {
+            unauthorized: true,
+            ..Default::default()
+        };
+
+        let err = futures::executor::block_on(import_yonder_csv_to_ynab(
+            SELFTEST_CSV,
+            &test_config(),
+            &ynab,
+            &ImportOptions::default(),
+        ))
+        .expect_err("import must fail");
+        assert_eq!(
+            webhook_error(&err),
+            (
+                401,
+                "YNAB token invalid or expired — update YNAB_API_KEY".to_string()
+            )
+        );
+
+        // The rejected token is recognized through added context
+        let err = futures::executor::block_on(self_test(
+            &test_config(),
+            &ynab,
+            false,
+            test_context()?.now,
+        ))
+        .expect_err("self-test must fail");
+        assert_eq!(webhook_error(&err).0, 401);
+
+        let err = eyre::eyre!("YNAB is down");
+        assert_eq!(webhook_error(&err), (500, "YNAB is down".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_latest_transaction() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-02T09:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2025-12-31T12:34:50","Cinema","12.00","12.00","GBP","Entertainment","Debit","GBR""#,
+        ]);
+        let ynab = MockYnab::default();
+
+        let (reply, _) = futures::executor::block_on(import_latest_transaction(
+            &csv,
+            &test_config(),
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+        assert!(reply.starts_with("Latest transaction:\n2026-01-02 Coffee -£3.00"));
+        assert!(reply.contains("Imported new transactions: 1"));
+
+        let transactions = ynab.transactions.borrow();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(
+            transactions[0]
+                .payee_name
+                .as_ref()
+                .map(|payee_name| payee_name.as_str()),
+            Some("Coffee")
+        );
+
+        // Transactions on the same date are ordered by time, not by their order in the CSV
+        let csv = yonder_csv(&[
+            r#""2026-01-02T18:00:00","Dinner","30.00","30.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-02T09:00:00","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let (reply, _) = futures::executor::block_on(import_latest_transaction(
+            &csv,
+            &test_config(),
+            &MockYnab::default(),
+            &ImportOptions::default(),
+        ))?;
+        assert!(reply.starts_with("Latest transaction:\n2026-01-02 Dinner -£30.00"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_amount_max_decimals() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Petrol","45.125","45.125","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco","8.10","8.10","GBP","Groceries","Debit","GBR""#,
+        ]);
+
+        let mapped = map_yonder_csv(&csv, &test_config(), &test_context()?)?;
+        assert!(mapped.warnings.is_empty());
+
+        let config = Config {
+            amount_max_decimals: Some(2),
+            ..test_config()
+        };
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &MockYnab::default(),
+            &ImportOptions::default(),
+        ))?;
+        // The amount is still imported, only with a warning
+        assert_eq!(result.imported, 2);
+        assert_eq!(
+            result.warnings,
+            ["line 2: \"Petrol\" amount 45.125 has more than 2 decimal places"]
+        );
+        assert!(result
+            .to_string()
+            .contains("WARNING: line 2: \"Petrol\" amount 45.125 has more than 2 decimal places"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_document_mime_type() -> eyre::Result<()> {
+        let ynab = MockYnab::default();
+        let import = |document: &[u8], mime_type| {
+            futures::executor::block_on(import_document(
+                document.to_vec(),
+                mime_type,
+                &test_config(),
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        // Telegram reports some CSVs as binary files
+        let result = import(SELFTEST_CSV.as_bytes(), Some("application/octet-stream"))?;
+        // The sample CSV has the same transaction with and without the timezone
+        assert_eq!((result.imported, result.duplicates), (1, 1));
+
+        let err = import(b"\x89PNG\r\n\x1a\n", Some("image/png")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("document of type image/png is not a Yonder CSV export"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_credit_payee() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","ASOS","25.00","25.00","GBP","Shopping","Credit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            credit_payee: Some("Refund".to_string()),
+            ..Default::default()
+        };
+
+        let payees = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?)
+                    .payee_name
+                    .map(|payee_name| payee_name.to_string()))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        assert_eq!(
+            payees,
+            [Some("Refund".to_string()), Some("Tesco".to_string())]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memo_category_hashtag() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Pizza Express","25.00","25.00","GBP","Eating Out","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Balance Correction","1.00","1.00","GBP","","Credit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            memo_category_hashtag: true,
+            ..Default::default()
+        };
+        let memos = |config: &Config| {
+            transactions
+                .iter()
+                .map(|transaction| {
+                    Ok(
+                        map_transaction(transaction.clone(), config, &test_context()?)
+                            .memo
+                            .map(|memo| memo.to_string()),
+                    )
+                })
+                .collect::<eyre::Result<Vec<_>>>()
+        };
+
+        assert_eq!(memos(&config)?, [Some("#eating_out".to_string()), None]);
+
+        // Combined with other memo parts, the hashtag survives truncation
+        let config = Config {
+            default_memo: Some("x".repeat(600)),
+            ..config
+        };
+        let memo = memos(&config)?[0].clone().expect("memo is set");
+        assert_eq!(memo.chars().count(), 500);
+        assert!(memo.ends_with("x #eating_out"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archived_budget() -> eyre::Result<()> {
+        let ynab = MockYnab {
+            budget_archived: true,
+            ..Default::default()
+        };
+
+        let err = futures::executor::block_on(import_yonder_csv_to_ynab(
+            SELFTEST_CSV,
+            &test_config(),
+            &ynab,
+            &ImportOptions::default(),
+        ))
+        .expect_err("import must fail");
+        assert_eq!(
+            err.to_string(),
+            "YNAB budget is archived or inaccessible — check YNAB_BUDGET_ID"
+        );
+
+        // The cause is reported instead of the added context
+        let err = futures::executor::block_on(self_test(
+            &test_config(),
+            &ynab,
+            false,
+            test_context()?.now,
+        ))
+        .expect_err("self-test must fail");
+        assert_eq!(
+            webhook_error(&err),
+            (
+                500,
+                "YNAB budget is archived or inaccessible — check YNAB_BUDGET_ID".to_string()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_webhook_schema_version() -> eyre::Result<()> {
+        let result = DocumentResult {
+            imported: 2,
+            ..Default::default()
+        };
+        let json = versioned_json(&result)?;
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["imported"], 2);
+
+        let json = versioned_json(&serde_json::json!({ "removed": 3 }))?;
+        assert_eq!(
+            json,
+            serde_json::json!({ "schema_version": 1, "removed": 3 })
+        );
+
+        assert_eq!(
+            error_json("Invalid API key"),
+            serde_json::json!({ "schema_version": 1, "error": "Invalid API key" })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_description_prefixes() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","POS Pret A Manger","4.50","4.50","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","visa  Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Post Office","2.00","2.00","GBP","Shopping","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let config = Config {
+            description_prefixes: vec!["VISA".to_string(), "POS".to_string()],
+            ..Default::default()
+        };
+
+        let payees = transactions
+            .into_iter()
+            .map(|transaction| {
+                Ok(map_transaction(transaction, &config, &test_context()?)
+                    .payee_name
+                    .map(|payee_name| payee_name.to_string()))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        // Prefixes are only stripped as whole words
+        assert_eq!(
+            payees,
+            [
+                Some("Pret A Manger".to_string()),
+                Some("Tesco".to_string()),
+                Some("Post Office".to_string())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_monthly_import_id() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2025-11-30T23:59:59","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2025-12-01T10:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T10:34:50","Salary","12345678901.00","12345678901.00","GBP","Income","Credit","GBR""#,
+        ]);
+        let config = Config {
+            import_id_version: ImportIdVersion::V3,
+            import_batching: ImportBatching::Monthly,
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let import_ids: Vec<_> = mapped
+            .transactions
+            .iter()
+            .map(|transaction| {
+                transaction
+                    .import_id
+                    .as_ref()
+                    .expect("import_id must be set")
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(import_ids[0], "TG202511:-8000:1764547199000");
+        assert_eq!(import_ids[1], "TG202512:-3000:1764585290000");
+        // Hashed to fit the length limit, keeping the month prefix
+        assert!(import_ids[2].starts_with("TG202601:h:"));
+        assert!(import_ids.iter().all(|import_id| import_id.len() <= 36));
+
+        let batches = import_batches(mapped.transactions, &config);
+        assert_eq!(batches.len(), 3);
+
+        // The month is the one of the YNAB date, like the batch, rather than the UTC one
+        let config = Config {
+            tz_offset_minutes: 60,
+            ..config
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            mapped.transactions[0]
+                .import_id
+                .as_ref()
+                .map(|import_id| import_id.to_string()),
+            Some("TG202512:-8000:1764547199000".to_string())
+        );
+        let batches = import_batches(mapped.transactions, &config);
+        assert_eq!(batches.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_id_fields() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T18:12:05","TESCO ","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T18:12:05","Costa","8.00","8.00","GBP","Eating out","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let import_ids = |config: &Config| -> eyre::Result<Vec<String>> {
+            transactions
+                .iter()
+                .map(|transaction| {
+                    let transaction =
+                        map_transaction(transaction.clone(), config, &test_context()?);
+                    Ok(transaction
+                        .import_id
+                        .expect("import_id must be set")
+                        .to_string())
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            import_ids(&test_config())?,
+            [
+                "TG:-8000:1767263690000",
+                "TG:-8000:1767291125000",
+                "TG:-8000:1767291125000"
+            ]
+        );
+
+        // Same-amount purchases at the same time are distinguished by the description, while
+        // repeated purchases from the same merchant on the same day are deduplicated
+        let config = Config {
+            import_id_fields: vec![
+                ImportIdField::Amount,
+                ImportIdField::Date,
+                ImportIdField::Description,
+            ],
+            ..test_config()
+        };
+        assert_eq!(
+            import_ids(&config)?,
+            [
+                "TG:-8000:20260101:tesco",
+                "TG:-8000:20260101:tesco",
+                "TG:-8000:20260101:costa"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_footer_row() -> eyre::Result<()> {
+        let csv = format!(
+            "{}\n{}",
+            yonder_csv(&[
+                r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+                r#""2026-01-01T11:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+            ]),
+            r#""Total","","11.00""#
+        );
+
+        let yonder = parse_yonder_csv(&csv)?;
+        assert_eq!((yonder.transactions.len(), yonder.footers), (2, 1));
+
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &test_config(),
+            &MockYnab::default(),
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!((result.imported, result.skipped), (2, 1));
+
+        let config = Config {
+            footer_row_policy: FooterRowPolicy::Error,
+            ..test_config()
+        };
+        let err = map_yonder_csv(&csv, &config, &test_context()?).err();
+        assert!(err.is_some_and(|err| err.to_string().contains("1 summary rows")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_period_marker() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""Statement period: 01/01/2026 - 31/01/2026""#,
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-02T11:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+
+        let yonder = parse_yonder_csv(&csv)?;
+        assert_eq!(yonder.transactions.len(), 2);
+        assert_eq!(
+            yonder.statement_period,
+            Some((
+                NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 1, 31).unwrap()
+            ))
+        );
+
+        let mapped = map_yonder_csv(&csv, &test_config(), &test_context()?)?;
+        assert_eq!((mapped.transactions.len(), mapped.skipped), (2, 1));
+
+        let config = Config {
+            statement_period_marker: true,
+            ..test_config()
+        };
+        // No marker is imported until the period ends, as YNAB rejects future transactions
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 2);
+        assert!(mapped.statement_marker.is_none());
+
+        let ended = ImportContext::new("2026-02-01T12:00:00Z".parse()?);
+        let mapped = map_yonder_csv(&csv, &config, &ended)?;
+        assert_eq!(mapped.transactions.len(), 2);
+        let marker = mapped.statement_marker.as_ref().expect("period has ended");
+        assert_eq!(marker.account_id, Some(Uuid::nil()));
+        assert_eq!(marker.amount, Some(0));
+        assert_eq!(marker.date, NaiveDate::from_ymd_opt(2026, 1, 31));
+        assert_eq!(
+            marker.payee_name.as_ref().map(|payee| payee.to_string()),
+            Some("Yonder statement".to_string())
+        );
+        assert_eq!(
+            marker.memo.as_ref().map(|memo| memo.to_string()),
+            Some("Statement period 2026-01-01 to 2026-01-31".to_string())
+        );
+        assert_eq!(
+            marker
+                .import_id
+                .as_ref()
+                .map(|import_id| import_id.to_string()),
+            Some("TGSTMT:20260101:20260131".to_string())
+        );
+        assert_eq!(marker.category_id, None);
+        assert_eq!(marker.cleared, Some(TransactionClearedStatus::Cleared));
+
+        // The marker is imported with the transactions, but isn't the latest transaction
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &MockYnab::default(),
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!(result.imported, 3);
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &MockYnab::default(),
+            &ImportOptions {
+                latest_only: true,
+                ..Default::default()
+            },
+        ))?;
+        assert_eq!(result.payees, ["Coffee"]);
+
+        // The period ends at midnight in the configured timezone
+        let config = Config {
+            tz_offset_minutes: -60,
+            ..config
+        };
+        let midnight = ImportContext::new("2026-02-01T00:30:00Z".parse()?);
+        let mapped = map_yonder_csv(&csv, &config, &midnight)?;
+        assert!(mapped.statement_marker.is_none());
+
+        // There's no marker without the default account
+        let config = Config {
+            ynab_account_id: None,
+            ..config
+        };
+        let mapped = map_yonder_csv(&csv, &config, &ended)?;
+        assert!(mapped.statement_marker.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_balance_command() -> eyre::Result<()> {
+        let ynab = MockYnab {
+            categories: vec![CategoryBalance {
+                name: "Eating Out".to_string(),
+                budgeted: 100000,
+                activity: -32500,
+                balance: 67500,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let reply = futures::executor::block_on(on_telegram_balance(
+            &Config::default(),
+            &ynab,
+            "eating out",
+        ))?;
+        assert_eq!(
+            reply,
+            "Category: Eating Out\nBudgeted: £100.00\nActivity: -£32.50\nBalance: £67.50"
+        );
+
+        let err = futures::executor::block_on(on_telegram_balance(
+            &Config::default(),
+            &ynab,
+            "Groceries",
+        ))
+        .err();
+        assert!(err.is_some_and(|err| err.to_string().contains("not found")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_description_case() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","TFL STATION LONDON","2.80","2.80","GBP","Transport","Debit","GBR""#,
+        ]);
+        let mut config = Config {
+            description_case: DescriptionCase::Title,
+            description_case_exceptions: vec!["TFL".to_string(), "ATM".to_string()],
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            mapped.transactions[0]
+                .payee_name
+                .as_ref()
+                .map(|payee| payee.as_str()),
+            Some("TFL Station London")
+        );
+
+        config.description_case = DescriptionCase::Sentence;
+        assert_eq!(
+            config.convert_description_case("ATM WITHDRAWAL TFL"),
+            "ATM withdrawal TFL"
+        );
+        assert_eq!(
+            config.convert_description_case("PRET A MANGER"),
+            "Pret a manger"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_rules() -> eyre::Result<()> {
+        assert_eq!(
+            validate_rules(SELFTEST_CSV, &test_config()),
+            Vec::<String>::new()
+        );
+
+        let config = Config {
+            category_description_rules: vec![
+                CategoryRule {
+                    pattern: regex::Regex::new("(?i)tfl")?,
+                    category_id: Uuid::nil(),
+                },
+                CategoryRule {
+                    pattern: regex::Regex::new("(?i)uber")?,
+                    category_id: Uuid::nil(),
+                },
+            ],
+            ynab_account_id: None,
+            ..test_config()
+        };
+        let warnings = validate_rules(SELFTEST_CSV, &config);
+        assert_eq!(
+            warnings,
+            [
+                r#"category rule "(?i)uber" matches no rows"#,
+                r#"line 2: no YNAB account for transaction "TFL - Transport for London": set YNAB_ACCOUNT_ID or add a matching account route"#,
+                r#"line 3: no YNAB account for transaction "TFL - Transport for London": set YNAB_ACCOUNT_ID or add a matching account route"#,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memo_upload_id() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let config = Config {
+            memo_upload_id: true,
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        let import = || {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                &config,
+                &ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        let first = import()?.upload_id.ok_or_eyre("no upload ID")?;
+        let memos = ynab
+            .transactions
+            .borrow()
+            .iter()
+            .map(|transaction| transaction.memo.as_ref().map(|memo| memo.to_string()))
+            .collect::<Vec<_>>();
+        let expected = Some(format!("upload:{first}"));
+        assert_eq!(memos, [expected.clone(), expected]);
+
+        let second = import()?.upload_id.ok_or_eyre("no upload ID")?;
+        assert_ne!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_knowledge() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let config = Config {
+            track_server_knowledge: true,
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        let store = MemoryStore::default();
+        let import = |csv: &str| {
+            futures::executor::block_on(async {
+                let options = ImportOptions {
+                    last_server_knowledge: last_server_knowledge(&config, Some(&store)).await,
+                    ..Default::default()
+                };
+                let result = import_yonder_csv_to_ynab(csv, &config, &ynab, &options).await?;
+                record_server_knowledge(&config, Some(&store), &result).await;
+                eyre::Ok(result)
+            })
+        };
+
+        assert!(import(&csv)?.warnings.is_empty());
+        assert_eq!(
+            futures::executor::block_on(store.get(SERVER_KNOWLEDGE_KEY))?,
+            Some("1".to_string())
+        );
+
+        // Re-importing the same transactions changes nothing
+        assert!(import(&csv)?.warnings.is_empty());
+
+        ynab.change_transaction();
+        let result = import(&csv)?;
+        assert_eq!(
+            result.warnings,
+            ["1 transactions changed in YNAB since the last import (server knowledge 1 → 2)"]
+        );
+        assert_eq!(
+            futures::executor::block_on(store.get(SERVER_KNOWLEDGE_KEY))?,
+            Some("2".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_knowledge_import_paths() -> eyre::Result<()> {
+        let row = |date: &str, payee: &str| {
+            format!(r#""{date}","{payee}","8.00","8.00","GBP","Groceries","Debit","GBR""#)
+        };
+        let config = Config {
+            track_server_knowledge: true,
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        let store = MemoryStore::default();
+        let options = || ImportOptions {
+            last_server_knowledge: futures::executor::block_on(last_server_knowledge(
+                &config,
+                Some(&store),
+            )),
+            ..Default::default()
+        };
+        futures::executor::block_on(store.put(SERVER_KNOWLEDGE_KEY, "0", None))?;
+
+        // Telegram `/importlast`
+        let (_, result) = futures::executor::block_on(import_latest_transaction(
+            yonder_csv(&[&row("2026-01-01T10:34:50", "Tesco")]),
+            &config,
+            &ynab,
+            &options(),
+        ))?;
+        assert!(result.warnings.is_empty());
+        futures::executor::block_on(record_server_knowledge(&config, Some(&store), &result));
+
+        // Files of the manifest don't report the previous ones as changed
+        let files = [
+            ("2026-01-01T11:34:50", "Coffee"),
+            ("2026-01-02T09:00:00", "Cinema"),
+        ]
+        .map(|(date, payee)| ManifestFile {
+            name: format!("{payee}.csv"),
+            csv: yonder_csv(&[&row(date, payee)]).into_bytes(),
+        })
+        .into();
+        let result =
+            futures::executor::block_on(import_manifest(files, &config, &ynab, &options()));
+        assert!(result.is_success());
+        assert_eq!(result.total.imported, 2);
+        assert!(result.total.warnings.is_empty());
+        futures::executor::block_on(record_server_knowledge(
+            &config,
+            Some(&store),
+            &result.total,
+        ));
+
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            yonder_csv(&[&row("2026-01-01T12:34:50", "Bakery")]),
+            &config,
+            &ynab,
+            &options(),
+        ))?;
+        assert_eq!(result.imported, 1);
+        assert!(result.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memo_fields() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Le Bistro","30.00","35.00","EUR","Eating out","Debit","FRA""#,
+        ]);
+        let memo = |config: &Config| -> eyre::Result<Option<String>> {
+            Ok(
+                map_yonder_csv(&csv, config, &test_context()?)?.transactions[0]
+                    .memo
+                    .as_ref()
+                    .map(|memo| memo.to_string()),
+            )
+        };
+
+        let mut config = Config {
+            memo_fields: vec![MemoField::Category, MemoField::Country, MemoField::Currency],
+            ..test_config()
+        };
+        assert_eq!(memo(&config)?.as_deref(), Some("Eating out | FRA | EUR"));
+
+        config.memo_fields = vec![MemoField::ChargedAmount, MemoField::Kind];
+        config.memo_separator = Some(", ".to_string());
+        assert_eq!(memo(&config)?.as_deref(), Some("35.00 EUR, Debit"));
+
+        assert_eq!(
+            "charged_amount".parse::<MemoField>(),
+            Ok(MemoField::ChargedAmount)
+        );
+        assert!("amount".parse::<MemoField>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ynab_import_csv() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.25","8.25","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Salary","1200.00","1200.00","GBP","Income","Credit","GBR""#,
+        ]);
+        let config = Config {
+            default_memo: Some("Yonder".to_string()),
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            ynab_import_csv(&mapped)?,
+            "Date,Payee,Memo,Outflow,Inflow\n\
+             2026-01-01,Tesco,Yonder,8.25,\n\
+             2026-01-01,Salary,Yonder,,1200.00\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_approve_seen_payees() -> eyre::Result<()> {
+        let config = Config {
+            approve_seen_payees: true,
+            ..test_config()
+        };
+        let ynab = MockYnab::default();
+        let store = MemoryStore::default();
+        let import = |csv: &str| -> eyre::Result<()> {
+            futures::executor::block_on(async {
+                let options = ImportOptions {
+                    seen_payees: seen_payees(&config, Some(&store)).await,
+                    ..Default::default()
+                };
+                let result = import_yonder_csv_to_ynab(csv, &config, &ynab, &options).await?;
+                record_seen_payees(&config, Some(&store), &result).await;
+                Ok(())
+            })
+        };
+
+        import(&yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]))?;
+        import(&yonder_csv(&[
+            r#""2026-01-02T10:34:50","TESCO","9.00","9.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-02T11:34:50","Pret","4.00","4.00","GBP","Eating out","Debit","GBR""#,
+        ]))?;
+
+        let approved = ynab
+            .transactions
+            .borrow()
+            .iter()
+            .map(|transaction| {
+                (
+                    transaction
+                        .payee_name
+                        .as_ref()
+                        .map(|payee_name| payee_name.to_string()),
+                    transaction.approved,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            approved,
+            [
+                (Some("Tesco".to_string()), Some(false)),
+                (Some("TESCO".to_string()), Some(true)),
+                (Some("Pret".to_string()), Some(false)),
+            ]
+        );
+        // Payees are tracked under a single key, whatever their number
+        assert_eq!(store.0.borrow().keys().collect::<Vec<_>>(), ["seen_payees"]);
+
+        // Long payees are tracked by their hash, case-insensitively as well
+        let long = "x".repeat(100);
+        assert_eq!(seen_payee(&long), seen_payee(&long.to_uppercase()));
+        assert_eq!(seen_payee(&long).len(), 17);
+        assert_eq!(seen_payee("Tesco"), "tesco");
+
+        // Diff imports record their payees as well
+        futures::executor::block_on(import_diff(
+            yonder_csv(&[
+                r#""2026-01-03T10:34:50","Cinema","12.00","12.00","GBP","Entertainment","Debit","GBR""#,
+            ]),
+            &config,
+            &ynab,
+            &store,
+            &ImportOptions::default(),
+        ))?;
+        assert!(futures::executor::block_on(read_seen_payees(&store))?.contains("cinema"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_budget_currency() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+        ]);
+        let mut config = Config {
+            budget_currency: Some("GBP".to_string()),
+            ..test_config()
+        };
+        let import = |config: &Config, ynab: &MockYnab| {
+            futures::executor::block_on(import_yonder_csv_to_ynab(
+                &csv,
+                config,
+                ynab,
+                &ImportOptions::default(),
+            ))
+        };
+
+        let result = import(&config, &MockYnab::default())?;
+        assert!(result.warnings.is_empty());
+
+        let ynab = MockYnab {
+            currency: "EUR".to_string(),
+            ..Default::default()
+        };
+        let result = import(&config, &ynab)?;
+        assert_eq!(result.imported, 1);
+        assert_eq!(
+            result.warnings,
+            ["YNAB budget currency EUR doesn't match BUDGET_CURRENCY GBP"]
+        );
+
+        config.budget_currency_policy = CurrencyMismatchPolicy::Error;
+        let ynab = MockYnab {
+            currency: "EUR".to_string(),
+            ..Default::default()
+        };
+        let err = import(&config, &ynab).err();
+        assert!(err.is_some_and(|err| err.to_string().contains("doesn't match")));
+        assert!(ynab.transactions.borrow().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_preview_file() -> eyre::Result<()> {
+        let rows = (1..=40)
+            .map(|day| {
+                format!(
+                    r#""2026-01-01T10:{day:02}:00","Coffee {day}","3.00","3.00","GBP","Eating out","Debit","GBR""#
+                )
+            })
+            .collect::<Vec<_>>();
+        let csv = yonder_csv(&rows.iter().map(String::as_str).collect::<Vec<_>>());
+        let mapped = map_yonder_csv(&csv, &test_config(), &test_context()?)?;
+
+        // Without the threshold the preview is truncated to fit a message
+        let Preview::Inline(inline) = preview(&mapped.transactions, &test_config(), |_| true)
+        else {
+            panic!("expected inline preview");
+        };
+        assert!(inline.ends_with("... and 10 more"));
+
+        let config = Config {
+            preview_file_threshold: Some(1000),
+            ..test_config()
+        };
+        let Preview::File(file) = preview(&mapped.transactions, &config, |_| true) else {
+            panic!("expected preview file");
+        };
+        assert_eq!(file.lines().count(), 41);
+        assert!(file.contains("Coffee 40"));
+
+        let config = Config {
+            preview_file_threshold: Some(10000),
+            ..test_config()
+        };
+        assert!(matches!(
+            preview(&mapped.transactions, &config, |_| true),
+            Preview::Inline(preview) if preview.lines().count() == 41
+        ));
+
+        // A threshold above the Telegram limit still sends previews too long for a message as files,
+        // measured after escaping, which makes ampersands five times longer
+        let rows = (1..=100)
+            .map(|row| {
+                format!(
+                    r#""2026-01-01T10:{:02}:{:02}","{}","3.00","3.00","GBP","Groceries","Debit","GBR""#,
+                    row / 60,
+                    row % 60,
+                    "&".repeat(PREVIEW_PAYEE_WIDTH)
+                )
+            })
+            .collect::<Vec<_>>();
+        let csv = yonder_csv(&rows.iter().map(String::as_str).collect::<Vec<_>>());
+        let mapped = map_yonder_csv(&csv, &test_config(), &test_context()?)?;
+        let config = Config {
+            preview_file_threshold: Some(100_000),
+            ..test_config()
+        };
+        let Preview::File(file) = preview(&mapped.transactions, &config, fits_telegram_message)
+        else {
+            panic!("expected preview file");
+        };
+        assert_eq!(file.lines().count(), 101);
+        // The truncated preview doesn't fit a message either once escaped
+        assert!(matches!(
+            preview(&mapped.transactions, &test_config(), fits_telegram_message),
+            Preview::File(_)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_flag_hash() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Sainsbury's","5.00","5.00","GBP","groceries ","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Pret","4.00","4.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T13:34:50","Cinema","9.00","9.00","GBP","Entertainment","Debit","GBR""#,
+        ]);
+        let mut config = Config {
+            category_flag_hash: true,
+            ..test_config()
+        };
+        let flags = |config: &Config| -> eyre::Result<Vec<Option<TransactionFlagColor>>> {
+            Ok(map_yonder_csv(&csv, config, &test_context()?)?
+                .transactions
+                .into_iter()
+                .map(|transaction| transaction.flag_color)
+                .collect())
+        };
+
+        let first = flags(&config)?;
+        assert_eq!(first, flags(&config)?);
+        assert!(first.iter().all(Option::is_some));
+        assert_eq!(first[0], first[1]);
+        assert_eq!(first[0], hashed_flag_color("Groceries"));
+
+        // Configured flag colors take precedence
+        config
+            .category_flag_colors
+            .insert("Eating out".to_string(), "red".parse()?);
+        assert_eq!(flags(&config)?[2], Some("red".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_with_backoff() -> eyre::Result<()> {
+        let attempts = Cell::new(0);
+        let delays = RefCell::new(Vec::new());
+        let download = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt == 1 {
+                    eyre::bail!("timed out");
+                }
+                Ok(b"csv".to_vec())
+            }
+        };
+        let sleep = |delay: Duration| {
+            delays.borrow_mut().push(delay);
+            async {}
+        };
+
+        let csv = futures::executor::block_on(retry_with_backoff(2, download, sleep))?;
+        assert_eq!(csv, b"csv");
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(*delays.borrow(), [Duration::from_millis(500)]);
+
+        attempts.set(0);
+        let err = futures::executor::block_on(retry_with_backoff(0, download, sleep)).err();
+        assert!(err.is_some_and(|err| err.to_string() == "failed after 1 attempts"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_command() {
+        let reply = on_telegram_command(&Config::default(), 1, "version", "");
+        assert_eq!(reply, Some(format!("Version: {}", version())));
+        assert!(version().starts_with(&format!("{} (", env!("CARGO_PKG_VERSION"))));
+    }
+
+    #[test]
+    fn test_payment_transfer() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Cashback","5.00","5.00","GBP","Rewards","Credit","GBR""#,
+            r#""2026-01-01T11:34:50","Payment received - thank you","250.00","250.00","GBP","Payment","Credit","GBR""#,
+            r#""2026-01-01T12:34:50","Payment services ltd","12.00","12.00","GBP","Shopping","Debit","GBR""#,
+        ]);
+        let transfer_payee_id = Uuid::new_v4();
+        let config = Config {
+            payment_descriptions: vec!["payment received".to_string()],
+            payment_transfer_payee_id: Some(transfer_payee_id),
+            default_category_id: Some(Uuid::nil()),
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let [cashback, payment, debit] = mapped.transactions.as_slice() else {
+            panic!("expected 3 transactions");
+        };
+
+        assert_eq!(cashback.amount, Some(5000));
+        assert_eq!(cashback.payee_id, None);
+        assert_eq!(cashback.category_id, Some(Uuid::nil()));
+
+        assert_eq!(payment.amount, Some(250000));
+        assert_eq!(payment.payee_id, Some(transfer_payee_id));
+        assert_eq!(payment.payee_name, None);
+        assert_eq!(payment.category_id, None);
+
+        assert_eq!(debit.payee_id, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_ids_deterministic() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-01T10:34:50","TFL - Transport for London","3.00","3.00","GBP","Transport","Debit","GBR""#,
+            r#""2026-01-01T12:00:00","Pret A Manger","4.50","5.20","EUR","Eating out","Debit","FRA""#,
+            r#""2026-01-02T09:00:00","Cashback","1.00","1.00","GBP","Rewards","Credit","GBR""#,
+        ]);
+        let config = Config {
+            category_map: [
+                ("Transport".to_string(), Uuid::new_v4()),
+                ("Eating out".to_string(), Uuid::new_v4()),
+            ]
+            .into(),
+            memo_fields: vec![MemoField::Category, MemoField::Country, MemoField::Currency],
+            memo_upload_id: true,
+            ..test_config()
+        };
+
+        // Each import gets a new upload ID in the memo, which mustn't leak into import_ids
+        let import_ids = || -> eyre::Result<Vec<String>> {
+            Ok(map_yonder_csv(&csv, &config, &test_context()?)?
+                .transactions
+                .into_iter()
+                .filter_map(|transaction| transaction.import_id)
+                .map(|import_id| import_id.to_string())
+                .collect())
+        };
+
+        let first = import_ids()?;
+        let second = import_ids()?;
+        assert_eq!(first.len(), 4);
+        assert_eq!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_split() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco Superstore","33.33","33.33","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco Express","5.00","5.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Pret A Manger","4.50","4.50","GBP","Eating out","Debit","GBR""#,
+        ]);
+        let groceries = Uuid::new_v4();
+        let fuel = Uuid::new_v4();
+        let config = Config {
+            category_map: [("Groceries".to_string(), groceries)].into(),
+            category_splits: vec![
+                CategorySplit {
+                    description_contains: "superstore".to_string(),
+                    category_id: fuel,
+                    share: SplitShare::Percent(30.0),
+                },
+                CategorySplit {
+                    description_contains: "express".to_string(),
+                    category_id: fuel,
+                    share: SplitShare::Amount(2.0),
+                },
+            ],
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let [superstore, express, pret] = mapped.transactions.as_slice() else {
+            panic!("expected 3 transactions");
+        };
+
+        // 70/30 split rounded to milliunits still sums exactly to the parent
+        assert_eq!(superstore.amount, Some(-33330));
+        assert_eq!(superstore.category_id, None);
+        let splits = superstore
+            .subtransactions
+            .iter()
+            .map(|subtransaction| (subtransaction.amount, subtransaction.category_id))
+            .collect::<Vec<_>>();
+        assert_eq!(splits, [(-23331, Some(groceries)), (-9999, Some(fuel))]);
+        assert_eq!(
+            superstore
+                .subtransactions
+                .iter()
+                .map(|subtransaction| subtransaction.amount)
+                .sum::<i64>(),
+            -33330
+        );
+
+        let splits = express
+            .subtransactions
+            .iter()
+            .map(|subtransaction| (subtransaction.amount, subtransaction.category_id))
+            .collect::<Vec<_>>();
+        assert_eq!(splits, [(-3000, Some(groceries)), (-2000, Some(fuel))]);
+
+        assert!(pret.subtransactions.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_document() {
+        let reply = oversized_document_message(Some(25 * 1024 * 1024)).unwrap();
+        assert!(reply.starts_with("The document is 25.0 MB"));
+        assert!(reply.contains("up to 20 MB"));
+        assert!(reply.contains("/import webhook"));
+
+        assert_eq!(oversized_document_message(Some(20 * 1024 * 1024)), None);
+        assert_eq!(oversized_document_message(None), None);
+    }
+
+    #[test]
+    fn test_category_group_map() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Thames Water","30.00","30.00","GBP","Bills","Debit","GBR""#,
+        ]);
+        let hidden = Uuid::new_v4();
+        let utilities = Uuid::new_v4();
+        let category = |id, name: &str, group: &str, hidden| CategoryBalance {
+            id,
+            name: name.to_string(),
+            group: group.to_string(),
+            hidden,
+            ..Default::default()
+        };
+        let ynab = MockYnab {
+            categories: vec![
+                category(Uuid::new_v4(), "Groceries", "Everyday", false),
+                category(hidden, "Council Tax", "Monthly Bills", true),
+                category(utilities, "Utilities", "Monthly Bills", false),
+            ],
+            ..Default::default()
+        };
+        let mut config = Config {
+            category_group_map: [("Bills".to_string(), "monthly bills".to_string())].into(),
+            ..test_config()
+        };
+
+        let group_category_ids =
+            futures::executor::block_on(resolve_category_groups(&config, &ynab))?;
+        assert_eq!(
+            group_category_ids,
+            [("monthly bills".to_string(), utilities)].into()
+        );
+
+        futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &ynab,
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!(ynab.transactions.borrow()[0].category_id, Some(utilities));
+
+        // Mapping outside of an import, e.g. for a preview, resolves the groups as well
+        let mapped = futures::executor::block_on(map_yonder_csv_with_groups(
+            &csv,
+            &config,
+            &ynab,
+            None::<&MemoryStore>,
+        ))?;
+        assert_eq!(mapped.transactions[0].category_id, Some(utilities));
+
+        config.category_group_map = [("Bills".to_string(), "Savings".to_string())].into();
+        let err = futures::executor::block_on(resolve_category_groups(&config, &ynab)).err();
+        assert!(err.is_some_and(|err| err.to_string().contains("not found")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_amount() -> eyre::Result<()> {
+        let csv = [
+            r#""Date/Time of transaction","Description","Amount (GBP)","Amount (in Charged Currency)","Currency","Category","Debit or Credit","Country","Pending Amount (GBP)","Settled Amount (GBP)""#,
+            r#""2026-01-01T10:34:50","Dishoom","45.00","45.00","GBP","Dining","Debit","GBR","45.00","52.00""#,
+            r#""2026-01-01T11:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR","8.00","8.00""#,
+            r#""2026-01-01T12:34:50","Amazon","12.00","12.00","GBP","Shopping","Debit","GBR","","""#,
+        ]
+        .join("\n");
+        let mut config = Config {
+            memo_fields: vec![MemoField::PendingAmount],
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let transactions = mapped
+            .transactions
+            .iter()
+            .map(|transaction| {
+                (
+                    transaction.amount,
+                    transaction.memo.as_ref().map(|memo| memo.to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The settled amount is imported, noting the difference from the authorized one
+        assert_eq!(
+            transactions,
+            [
+                (
+                    Some(-52000),
+                    Some("settled 52.00 GBP (pending 45.00 GBP)".to_string())
+                ),
+                (Some(-8000), None),
+                (Some(-12000), None)
+            ]
+        );
+
+        // The difference is noted even without the memo field
+        config.memo_fields = Vec::new();
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(
+            mapped.transactions[0]
+                .memo
+                .as_ref()
+                .map(|memo| memo.to_string()),
+            Some("settled 52.00 GBP (pending 45.00 GBP)".to_string())
+        );
+
+        // A tip settles the charged amount as well, rather than being split as an FX fee
+        let csv = [
+            r#""Date/Time of transaction","Description","Amount (GBP)","Amount (in Charged Currency)","Currency","Category","Debit or Credit","Country","Pending Amount (GBP)","Settled Amount (GBP)""#,
+            r#""2026-01-01T10:34:50","Le Bistro","30.00","34.00","EUR","Dining","Debit","FRA","30.00","36.00""#,
+        ]
+        .join("\n");
+        let fx_fee = Uuid::new_v4();
+        let config = Config {
+            fx_fee_category_id: Some(fx_fee),
+            fx_reference_rates: [("EUR".to_string(), 0.875)].into(),
+            ..test_config()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        // 40.80 EUR at 0.875 is £35.70, so £0.30 is the FX fee
+        assert_eq!(
+            mapped.transactions[0]
+                .subtransactions
+                .iter()
+                .map(|subtransaction| (subtransaction.amount, subtransaction.category_id))
+                .collect::<Vec<_>>(),
+            [(-35700, None), (-300, Some(fx_fee))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memo_channel() -> eyre::Result<()> {
+        let csv = [
+            r#""Date/Time of transaction","Description","Amount (GBP)","Amount (in Charged Currency)","Currency","Category","Debit or Credit","Country","Channel""#,
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR","Contactless""#,
+            r#""2026-01-01T11:34:50","Amazon","12.00","12.00","GBP","Shopping","Debit","GBR","""#,
+        ]
+        .join("\n");
+        let config = Config {
+            memo_fields: vec![MemoField::Category, MemoField::Channel],
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let memos = mapped
+            .transactions
+            .iter()
+            .map(|transaction| transaction.memo.as_ref().map(|memo| memo.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            memos,
+            [
+                Some("Groceries | Contactless".to_string()),
+                Some("Shopping".to_string())
+            ]
+        );
+
+        // Exports without the column still parse
+        let mapped = map_yonder_csv(SELFTEST_CSV, &config, &test_context()?)?;
+        assert_eq!(
+            mapped.transactions[0]
+                .memo
+                .as_ref()
+                .map(|memo| memo.to_string())
+                .as_deref(),
+            Some("Transport")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_rows() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Pret","four","4.00","GBP","Eating out","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Amazon","12.00","12.00","GBP","Shopping","Refund","GBR""#,
+        ]);
+        let mut config = test_config();
+
+        // Both invalid rows are reported together with their line numbers
+        let err = map_yonder_csv(&csv, &config, &test_context()?)
+            .err()
+            .ok_or_eyre("expected an error")?
+            .to_string();
+        assert!(err.starts_with("failed to deserialize 2 rows as Yonder transactions:\nline 3: "));
+        assert!(err.contains("\nline 4: "));
+
+        config.partial_import = true;
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 1);
+        assert_eq!(mapped.skipped, 2);
+        assert_eq!(mapped.warnings.len(), 2);
+        assert!(mapped.warnings[0].starts_with("line 3: "));
+        assert!(mapped.warnings[1].ends_with("(not imported)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_onboarding_message() {
+        let config = Config {
+            onboarding: true,
+            ..test_config()
+        };
+        let store = MemoryStore::default();
+        let onboarding = |config: &Config, chat_id| {
+            futures::executor::block_on(onboarding_message(config, Some(&store), chat_id))
+        };
+
+        assert_eq!(onboarding(&config, 1).as_deref(), Some(ONBOARDING_MESSAGE));
+        assert_eq!(onboarding(&config, 1), None);
+        assert_eq!(onboarding(&config, 2).as_deref(), Some(ONBOARDING_MESSAGE));
+
+        // Disabled by default
+        assert_eq!(onboarding(&test_config(), 3), None);
+    }
+
+    #[test]
+    fn test_categorize_command() -> eyre::Result<()> {
+        let config = Config {
+            store_imported_ids: true,
+            ..test_config()
+        };
+        let eating_out = Uuid::new_v4();
+        let ynab = MockYnab {
+            categories: vec![CategoryBalance {
+                id: eating_out,
+                name: "Eating Out".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let store = MemoryStore::default();
+        let categorize = |chat_id, args| {
+            futures::executor::block_on(on_telegram_categorize(
+                &config,
+                &ynab,
+                Some(&store),
+                chat_id,
+                args,
+            ))
+        };
+
+        let err = categorize(1, "Eating out").err();
+        assert!(err.is_some_and(|err| err.to_string().contains("import a CSV first")));
+
+        let result = DocumentResult {
+            imported: 2,
+            transaction_ids: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        futures::executor::block_on(record_imported_ids(&config, Some(&store), 1, &result));
+
+        assert_eq!(
+            categorize(1, "eating out")?,
+            "Categorized 2 transactions as Eating Out"
+        );
+        assert_eq!(
+            *ynab.categorized.borrow(),
+            [("a".to_string(), eating_out), ("b".to_string(), eating_out)]
+        );
+
+        // Other chats have no imported transactions
+        assert!(categorize(2, "Eating out").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_transaction() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Rolex","1500.00","1500.00","GBP","Shopping","Debit","GBR""#,
+            r#""2026-01-01T12:34:50","Refund","500.00","500.00","GBP","Shopping","Credit","GBR""#,
+        ]);
+        let config = Config {
+            large_transaction_gbp: Some(500.0),
+            ..test_config()
+        };
+
+        let result = futures::executor::block_on(import_yonder_csv_to_ynab(
+            &csv,
+            &config,
+            &MockYnab::default(),
+            &ImportOptions::default(),
+        ))?;
+        assert_eq!(result.imported, 3);
+        assert_eq!(result.large_transactions, [r#"line 3: "Rolex" -£1500.00"#]);
+        assert!(result
+            .to_string()
+            .ends_with(r#"LARGE TRANSACTION: line 3: "Rolex" -£1500.00"#));
+        assert_eq!(
+            serde_json::to_value(&result)?["large_transactions"],
+            serde_json::json!([r#"line 3: "Rolex" -£1500.00"#])
+        );
+
+        // The threshold is in pounds even if the CSV amounts are in pence
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","800","800","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Rolex","150000","150000","GBP","Shopping","Debit","GBR""#,
+        ]);
+        let config = Config {
+            amount_scale: Some(10),
+            ..config
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(mapped.large, [r#"line 3: "Rolex" -£1500.00"#]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_import_batch_size() -> eyre::Result<()> {
+        let rows = (0..500)
+            .map(|index| {
+                format!(
+                    r#""2026-01-01T10:{:02}:{:02}","Coffee","3.00","3.00","GBP","Eating out","Debit","GBR""#,
+                    index / 60,
+                    index % 60
+                )
+            })
+            .collect::<Vec<_>>();
+        let csv = yonder_csv(&rows.iter().map(String::as_str).collect::<Vec<_>>());
+        let mapped = map_yonder_csv(&csv, &test_config(), &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 500);
+
+        let batches = import_batches(mapped.transactions.clone(), &test_config());
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            [200, 200, 100]
+        );
+
+        // Daily batches are split to the batch size as well
+        let config = Config {
+            import_batching: ImportBatching::Daily,
+            import_batch_size: Some(300),
+            ..test_config()
+        };
+        let batches = import_batches(mapped.transactions, &config);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [300, 200]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_payee() -> eyre::Result<()> {
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Hotel Paris","120.00","140.00","EUR","Travel","Debit","FRA""#,
+        ]);
+        let config = Config {
+            single_payee: Some("Yonder Card".to_string()),
+            default_memo: Some("Yonder".to_string()),
+            payee_country: true,
+            ..test_config()
+        };
+
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        let payees_and_memos = mapped
+            .transactions
+            .iter()
+            .map(|transaction| {
+                (
+                    transaction
+                        .payee_name
+                        .as_ref()
+                        .map(|payee| payee.to_string()),
+                    transaction.memo.as_ref().map(|memo| memo.to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payees_and_memos,
+            [
+                (
+                    Some("Yonder Card".to_string()),
+                    Some("Tesco Yonder".to_string())
+                ),
+                (
+                    Some("Yonder Card".to_string()),
+                    Some("Hotel Paris Yonder".to_string())
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_currency_symbol_amounts() -> eyre::Result<()> {
+        let transactions = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","£3.00","£3.00","GBP","Groceries","Debit","GBR""#,
+            r#""2026-01-01T11:34:50","Tesco","-£3.00","-€3.50","EUR","Groceries","Credit","FRA""#,
+            r#""2026-01-01T12:34:50","Tesco","3.00","3.00","GBP","Groceries","Debit","GBR""#,
+        ]))?
+        .transactions;
+        let amounts = transactions
+            .iter()
+            .map(|transaction| (transaction.amount_gbp, transaction.amount_charged))
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, [(3.0, 3.0), (-3.0, -3.5), (3.0, 3.0)]);
+
+        let err = parse_yonder_csv(yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","£three","3.00","GBP","Groceries","Debit","GBR""#,
+        ]))
+        .err();
+        assert!(err.is_some_and(|err| err.to_string().contains(r#"invalid amount "£three""#)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_optional_fields() -> eyre::Result<()> {
+        // Rows of domestic transactions lacking the trailing country field
+        let csv = yonder_csv(&[
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit""#,
+            r#""2026-01-01T11:34:50","Hotel Paris","120.00","140.00","EUR","Travel","Debit","FRA""#,
+            r#""2026-01-01T12:34:50","Pret","4.50","4.50","","Eating out","Debit","""#,
+        ]);
+        let transactions = parse_yonder_csv(&csv)?.transactions;
+        let fields = transactions
+            .iter()
+            .map(|transaction| {
+                (
+                    transaction.currency.as_str(),
+                    transaction.country.as_str(),
+                    transaction.is_foreign(),
+                    transaction.is_domestic(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                ("GBP", "", false, true),
+                ("EUR", "FRA", true, false),
+                ("", "", false, true),
+            ]
+        );
+
+        let config = Config {
+            allowed_currencies: vec!["GBP".to_string()],
+            ..test_config()
+        };
+        let mapped = map_yonder_csv(&csv, &config, &test_context()?)?;
+        assert_eq!(mapped.transactions.len(), 2);
+
+        // Exports without the country column at all are accepted as well
+        let csv = [
+            r#""Date/Time of transaction","Description","Amount (GBP)","Amount (in Charged Currency)","Currency","Category","Debit or Credit""#,
+            r#""2026-01-01T10:34:50","Tesco","8.00","8.00","GBP","Groceries","Debit""#,
+        ]
+        .join("\n");
+        assert_eq!(parse_yonder_csv(&csv)?.transactions[0].country, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_format() -> eyre::Result<()> {
+        let result = DocumentResult {
+            imported: 2,
+            duplicates: 1,
+            net: -13250,
+            ..Default::default()
+        };
+        let (_, message) = webhook_import_status(&test_config(), &result);
+        let body = |accept| -> eyre::Result<String> {
+            Ok(match ResponseFormat::negotiate(accept) {
+                ResponseFormat::Json => import_result_json(&result, &message)?.to_string(),
+                ResponseFormat::Text => message.clone(),
+            })
+        };
+
+        let text = body(Some("text/plain"))?;
+        assert_eq!(text, result.to_string());
+        assert!(text.starts_with("Imported new transactions: 2"));
+
+        let json: serde_json::Value = serde_json::from_str(&body(Some("application/json"))?)?;
+        assert_eq!(json["imported"], 2);
+        assert_eq!(json["duplicates"], 1);
+        assert_eq!(json["net"], -13250);
+        assert_eq!(json["message"], result.to_string());
+
+        // JSON unless plain text is preferred
+        assert_eq!(ResponseFormat::negotiate(None), ResponseFormat::Json);
+        assert_eq!(ResponseFormat::negotiate(Some("*/*")), ResponseFormat::Json);
+        assert_eq!(
+            ResponseFormat::negotiate(Some("text/html, text/plain;q=0.9, application/json;q=0.8")),
+            ResponseFormat::Text
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(Some("application/json, text/plain")),
+            ResponseFormat::Json
+        );
 
         Ok(())
     }
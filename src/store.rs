@@ -0,0 +1,71 @@
+use worker::kv::KvStore;
+
+/// Name of the optional Workers KV namespace binding
+pub const KV_BINDING: &str = "KV";
+
+/// Key-value storage, implemented by Workers KV and by an in-memory store in tests
+pub trait Store {
+    /// Value of the key, if present
+    async fn get(&self, key: &str) -> eyre::Result<Option<String>>;
+
+    /// Set the value of the key, expiring after `ttl_seconds` if set
+    async fn put(&self, key: &str, value: &str, ttl_seconds: Option<u64>) -> eyre::Result<()>;
+
+    /// Delete the key
+    async fn delete(&self, key: &str) -> eyre::Result<()>;
+}
+
+impl Store for KvStore {
+    async fn get(&self, key: &str) -> eyre::Result<Option<String>> {
+        KvStore::get(self, key)
+            .text()
+            .await
+            .map_err(|err| eyre::eyre!("failed to get {key} from KV: {err}"))
+    }
+
+    async fn put(&self, key: &str, value: &str, ttl_seconds: Option<u64>) -> eyre::Result<()> {
+        let mut put = KvStore::put(self, key, value)
+            .map_err(|err| eyre::eyre!("failed to put {key} to KV: {err}"))?;
+        if let Some(ttl_seconds) = ttl_seconds {
+            put = put.expiration_ttl(ttl_seconds);
+        }
+        put.execute()
+            .await
+            .map_err(|err| eyre::eyre!("failed to put {key} to KV: {err}"))
+    }
+
+    async fn delete(&self, key: &str) -> eyre::Result<()> {
+        KvStore::delete(self, key)
+            .await
+            .map_err(|err| eyre::eyre!("failed to delete {key} from KV: {err}"))
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    use super::Store;
+
+    /// In-memory store for tests, ignoring TTLs
+    #[derive(Default)]
+    pub struct MemoryStore(pub RefCell<BTreeMap<String, String>>);
+
+    impl Store for MemoryStore {
+        async fn get(&self, key: &str) -> eyre::Result<Option<String>> {
+            Ok(self.0.borrow().get(key).cloned())
+        }
+
+        async fn put(&self, key: &str, value: &str, _ttl_seconds: Option<u64>) -> eyre::Result<()> {
+            self.0
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> eyre::Result<()> {
+            self.0.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+}
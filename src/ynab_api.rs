@@ -0,0 +1,535 @@
+use std::{fmt::Display, str::FromStr};
+
+use async_lock::Semaphore;
+use chrono::NaiveDate;
+use reqwest::StatusCode;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    config::{ENV_YNAB_API_KEY, ENV_YNAB_BUDGET_ID},
+    ynab::{
+        self,
+        types::{
+            NewTransaction, PatchTransactionsWrapper, PostScheduledTransactionWrapper,
+            PostTransactionsWrapper, SaveScheduledTransaction, SaveTransactionWithIdOrImportId,
+            TransactionFlagColor, TransactionFlagColorInner,
+        },
+    },
+};
+
+// The generated flag color wraps an optional color, as YNAB returns null for unflagged transactions
+impl FromStr for TransactionFlagColor {
+    type Err = <TransactionFlagColorInner as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(|color| Self(Some(color)))
+    }
+}
+
+impl Display for TransactionFlagColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(color) => color.fmt(f),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+/// Transactions saved to YNAB
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SavedTransactions {
+    pub transaction_ids: Vec<String>,
+    pub duplicate_import_ids: Vec<String>,
+    /// Knowledge of the YNAB server after saving
+    pub server_knowledge: i64,
+}
+
+/// Transactions changed in the budget since the given server knowledge
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransactionChanges {
+    /// Number of changed transactions, including deleted ones
+    pub changed: usize,
+    /// Current knowledge of the YNAB server
+    pub server_knowledge: i64,
+}
+
+/// YNAB account details
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountSummary {
+    pub name: String,
+    /// Current balance in milliunits
+    pub balance: i64,
+    pub closed: bool,
+}
+
+/// YNAB category amounts in the current budget month
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CategoryBalance {
+    pub id: Uuid,
+    pub name: String,
+    /// Name of the category group
+    pub group: String,
+    pub hidden: bool,
+    /// Budgeted amount in milliunits
+    pub budgeted: i64,
+    /// Activity amount in milliunits
+    pub activity: i64,
+    /// Balance in milliunits
+    pub balance: i64,
+}
+
+/// Transaction already stored in a YNAB account
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountTransaction {
+    pub id: String,
+    pub date: NaiveDate,
+    /// Amount in milliunits
+    pub amount: i64,
+    pub payee_name: Option<String>,
+    pub import_id: Option<String>,
+}
+
+/// Scheduled transaction stored in YNAB
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledTransaction {
+    pub account_id: Uuid,
+    /// Amount in milliunits
+    pub amount: i64,
+    pub payee_name: Option<String>,
+}
+
+/// YNAB rejected the API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidYnabToken;
+
+impl Display for InvalidYnabToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "YNAB token invalid or expired — update {ENV_YNAB_API_KEY}"
+        )
+    }
+}
+
+impl std::error::Error for InvalidYnabToken {}
+
+/// Error of a YNAB request, distinguishing rejected API keys from other failures
+pub fn ynab_error(status: Option<StatusCode>, message: String) -> eyre::Report {
+    if status == Some(StatusCode::UNAUTHORIZED) {
+        eyre::Report::new(InvalidYnabToken)
+    } else {
+        eyre::Report::msg(message)
+    }
+}
+
+/// YNAB didn't find the budget, e.g. because it was archived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetUnavailable;
+
+impl Display for BudgetUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "YNAB budget is archived or inaccessible — check {ENV_YNAB_BUDGET_ID}"
+        )
+    }
+}
+
+impl std::error::Error for BudgetUnavailable {}
+
+/// Error of a request to a budget-wide endpoint, where not found can only refer to the budget
+pub fn budget_error(status: Option<StatusCode>, message: String) -> eyre::Report {
+    if status == Some(StatusCode::NOT_FOUND) {
+        eyre::Report::new(BudgetUnavailable)
+    } else {
+        ynab_error(status, message)
+    }
+}
+
+/// Subset of the YNAB API used by the importer
+///
+/// Implemented by the generated [`ynab::Client`] and by mocks in tests.
+pub trait YnabApi {
+    /// Create transactions in the budget
+    async fn create_transactions(
+        &self,
+        budget_id: &str,
+        transactions: Vec<NewTransaction>,
+    ) -> eyre::Result<SavedTransactions>;
+
+    /// Account details
+    async fn account(&self, budget_id: &str, account_id: Uuid) -> eyre::Result<AccountSummary>;
+
+    /// Non-deleted account transactions on or after `since_date`
+    async fn account_transactions(
+        &self,
+        budget_id: &str,
+        account_id: Uuid,
+        since_date: Option<NaiveDate>,
+    ) -> eyre::Result<Vec<AccountTransaction>>;
+
+    /// Non-deleted scheduled transactions in the budget
+    async fn scheduled_transactions(
+        &self,
+        budget_id: &str,
+    ) -> eyre::Result<Vec<ScheduledTransaction>>;
+
+    /// Create a scheduled transaction in the budget, returning its ID
+    async fn schedule_transaction(
+        &self,
+        budget_id: &str,
+        transaction: SaveScheduledTransaction,
+    ) -> eyre::Result<String>;
+
+    /// Delete the transaction
+    async fn delete_transaction(&self, budget_id: &str, transaction_id: &str) -> eyre::Result<()>;
+
+    /// Non-deleted categories of the budget with their amounts in the current month, in YNAB order
+    async fn categories(&self, budget_id: &str) -> eyre::Result<Vec<CategoryBalance>>;
+
+    /// Transactions of the budget changed after `last_knowledge_of_server`
+    async fn transaction_changes(
+        &self,
+        budget_id: &str,
+        last_knowledge_of_server: i64,
+    ) -> eyre::Result<TransactionChanges>;
+
+    /// ISO code of the budget currency, e.g. `GBP`
+    async fn budget_currency(&self, budget_id: &str) -> eyre::Result<String>;
+
+    /// Set the category of the transactions, returning the number of updated ones
+    async fn categorize_transactions(
+        &self,
+        budget_id: &str,
+        transaction_ids: &[String],
+        category_id: Uuid,
+    ) -> eyre::Result<usize>;
+}
+
+impl YnabApi for ynab::Client {
+    async fn create_transactions(
+        &self,
+        budget_id: &str,
+        transactions: Vec<NewTransaction>,
+    ) -> eyre::Result<SavedTransactions> {
+        let response = self
+            .create_transaction(
+                budget_id,
+                &PostTransactionsWrapper {
+                    transaction: None,
+                    transactions,
+                },
+            )
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner();
+
+        Ok(SavedTransactions {
+            transaction_ids: response.data.transaction_ids,
+            duplicate_import_ids: response.data.duplicate_import_ids,
+            server_knowledge: response.data.server_knowledge,
+        })
+    }
+
+    async fn account(&self, budget_id: &str, account_id: Uuid) -> eyre::Result<AccountSummary> {
+        let account = self
+            .get_account_by_id(budget_id, &account_id)
+            .await
+            .map_err(|err| ynab_error(err.status(), err.to_string()))?
+            .into_inner()
+            .data
+            .account;
+
+        Ok(AccountSummary {
+            name: account.name,
+            balance: account.balance,
+            closed: account.closed,
+        })
+    }
+
+    async fn account_transactions(
+        &self,
+        budget_id: &str,
+        account_id: Uuid,
+        since_date: Option<NaiveDate>,
+    ) -> eyre::Result<Vec<AccountTransaction>> {
+        let transactions = self
+            .get_transactions_by_account(
+                budget_id,
+                &account_id.to_string(),
+                None,
+                since_date.as_ref(),
+                None,
+            )
+            .await
+            .map_err(|err| ynab_error(err.status(), err.to_string()))?
+            .into_inner()
+            .data
+            .transactions;
+
+        Ok(transactions
+            .into_iter()
+            .filter(|transaction| !transaction.deleted)
+            .map(|transaction| AccountTransaction {
+                id: transaction.id,
+                date: transaction.date,
+                amount: transaction.amount,
+                payee_name: transaction.payee_name,
+                import_id: transaction.import_id,
+            })
+            .collect())
+    }
+
+    async fn scheduled_transactions(
+        &self,
+        budget_id: &str,
+    ) -> eyre::Result<Vec<ScheduledTransaction>> {
+        let transactions = self
+            .get_scheduled_transactions(budget_id, None)
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner()
+            .data
+            .scheduled_transactions;
+
+        Ok(transactions
+            .into_iter()
+            .filter(|transaction| !transaction.deleted)
+            .map(|transaction| ScheduledTransaction {
+                account_id: transaction.account_id,
+                amount: transaction.amount,
+                payee_name: transaction.payee_name,
+            })
+            .collect())
+    }
+
+    async fn schedule_transaction(
+        &self,
+        budget_id: &str,
+        transaction: SaveScheduledTransaction,
+    ) -> eyre::Result<String> {
+        let response = self
+            .create_scheduled_transaction(
+                budget_id,
+                &PostScheduledTransactionWrapper {
+                    scheduled_transaction: transaction,
+                },
+            )
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner();
+
+        Ok(response.data.scheduled_transaction.id.to_string())
+    }
+
+    async fn delete_transaction(&self, budget_id: &str, transaction_id: &str) -> eyre::Result<()> {
+        // Inherent method of the generated client
+        ynab::Client::delete_transaction(self, budget_id, transaction_id)
+            .await
+            .map_err(|err| ynab_error(err.status(), err.to_string()))?;
+        Ok(())
+    }
+
+    async fn categories(&self, budget_id: &str) -> eyre::Result<Vec<CategoryBalance>> {
+        let category_groups = self
+            .get_categories(budget_id, None)
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner()
+            .data
+            .category_groups;
+
+        Ok(category_groups
+            .into_iter()
+            .filter(|group| !group.deleted)
+            .flat_map(|group| {
+                let group_name = group.name;
+                group
+                    .categories
+                    .into_iter()
+                    .map(move |category| (group_name.clone(), category))
+            })
+            .filter(|(_, category)| !category.deleted)
+            .map(|(group, category)| CategoryBalance {
+                id: category.id,
+                name: category.name,
+                group,
+                hidden: category.hidden,
+                budgeted: category.budgeted,
+                activity: category.activity,
+                balance: category.balance,
+            })
+            .collect())
+    }
+
+    async fn transaction_changes(
+        &self,
+        budget_id: &str,
+        last_knowledge_of_server: i64,
+    ) -> eyre::Result<TransactionChanges> {
+        let response = self
+            .get_transactions(budget_id, Some(last_knowledge_of_server), None, None)
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner();
+
+        Ok(TransactionChanges {
+            changed: response.data.transactions.len(),
+            server_knowledge: response.data.server_knowledge,
+        })
+    }
+
+    async fn budget_currency(&self, budget_id: &str) -> eyre::Result<String> {
+        let settings = self
+            .get_budget_settings_by_id(budget_id)
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner()
+            .data
+            .settings;
+
+        settings
+            .currency_format
+            .0
+            .map(|format| format.iso_code)
+            .ok_or_else(|| eyre::eyre!("Budget {budget_id} has no currency format"))
+    }
+
+    async fn categorize_transactions(
+        &self,
+        budget_id: &str,
+        transaction_ids: &[String],
+        category_id: Uuid,
+    ) -> eyre::Result<usize> {
+        let transactions = transaction_ids
+            .iter()
+            .map(|transaction_id| SaveTransactionWithIdOrImportId {
+                id: Some(transaction_id.clone()),
+                import_id: None,
+                account_id: None,
+                amount: None,
+                approved: None,
+                category_id: Some(category_id),
+                cleared: None,
+                date: None,
+                flag_color: None,
+                memo: None,
+                payee_id: None,
+                payee_name: None,
+                subtransactions: vec![],
+            })
+            .collect();
+        let response = self
+            .update_transactions(budget_id, &PatchTransactionsWrapper { transactions })
+            .await
+            .map_err(|err| budget_error(err.status(), err.to_string()))?
+            .into_inner();
+
+        Ok(response.data.transaction_ids.len())
+    }
+}
+
+/// YNAB API limiting the number of concurrent requests to stay within Workers subrequest limits
+pub struct LimitedYnab<'a, T> {
+    inner: &'a T,
+    semaphore: Semaphore,
+}
+
+impl<'a, T: YnabApi> LimitedYnab<'a, T> {
+    pub fn new(inner: &'a T, max_concurrent_requests: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Semaphore::new(max_concurrent_requests),
+        }
+    }
+}
+
+impl<T: YnabApi> YnabApi for LimitedYnab<'_, T> {
+    async fn create_transactions(
+        &self,
+        budget_id: &str,
+        transactions: Vec<NewTransaction>,
+    ) -> eyre::Result<SavedTransactions> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .create_transactions(budget_id, transactions)
+            .await
+    }
+
+    async fn account(&self, budget_id: &str, account_id: Uuid) -> eyre::Result<AccountSummary> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.account(budget_id, account_id).await
+    }
+
+    async fn account_transactions(
+        &self,
+        budget_id: &str,
+        account_id: Uuid,
+        since_date: Option<NaiveDate>,
+    ) -> eyre::Result<Vec<AccountTransaction>> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .account_transactions(budget_id, account_id, since_date)
+            .await
+    }
+
+    async fn scheduled_transactions(
+        &self,
+        budget_id: &str,
+    ) -> eyre::Result<Vec<ScheduledTransaction>> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.scheduled_transactions(budget_id).await
+    }
+
+    async fn schedule_transaction(
+        &self,
+        budget_id: &str,
+        transaction: SaveScheduledTransaction,
+    ) -> eyre::Result<String> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .schedule_transaction(budget_id, transaction)
+            .await
+    }
+
+    async fn delete_transaction(&self, budget_id: &str, transaction_id: &str) -> eyre::Result<()> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .delete_transaction(budget_id, transaction_id)
+            .await
+    }
+
+    async fn categories(&self, budget_id: &str) -> eyre::Result<Vec<CategoryBalance>> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.categories(budget_id).await
+    }
+
+    async fn transaction_changes(
+        &self,
+        budget_id: &str,
+        last_knowledge_of_server: i64,
+    ) -> eyre::Result<TransactionChanges> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .transaction_changes(budget_id, last_knowledge_of_server)
+            .await
+    }
+
+    async fn budget_currency(&self, budget_id: &str) -> eyre::Result<String> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.budget_currency(budget_id).await
+    }
+
+    async fn categorize_transactions(
+        &self,
+        budget_id: &str,
+        transaction_ids: &[String],
+        category_id: Uuid,
+    ) -> eyre::Result<usize> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner
+            .categorize_transactions(budget_id, transaction_ids, category_id)
+            .await
+    }
+}